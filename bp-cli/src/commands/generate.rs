@@ -1,7 +1,14 @@
 use std::path::Path;
 
 use anyhow::{Error, Result};
-use bp_core::{utils::tls, ClientOptions, ServerOptions};
+use bp_core::{
+    utils::{
+        crypto::Crypto,
+        kdf::{KeyDerivation, SALT_SIZE},
+        tls,
+    },
+    ClientOptions, ServerOptions,
+};
 use tokio::fs;
 
 use crate::{
@@ -25,6 +32,17 @@ pub async fn run(opts: GenerateOptions) {
     if opts.certificate {
         res = generate_certificate(&opts.hostname.unwrap(), "cert.der", "key.der").await;
     }
+    // generate a random key
+    if opts.key {
+        println!("{}", generate_key());
+    }
+    // generate a key derivation with random salt
+    if let Some(kdf) = &opts.key_derivation {
+        match generate_key_derivation(kdf) {
+            Ok(kdf) => println!("{}", kdf.to_string()),
+            Err(err) => res = Err(err),
+        }
+    }
 
     if let Err(err) = res {
         log::error!("{}", err);
@@ -68,6 +86,19 @@ pub async fn generate_config(path: &str, config_type: ConfigType) -> Result<()>
     Ok(())
 }
 
+/// 256 bits of randomness, encoded without characters which need quoting in shell
+pub fn generate_key() -> String {
+    base64::encode_config(Crypto::random_bytes(32), base64::URL_SAFE_NO_PAD)
+}
+
+/// Append a random salt to the key derivation, which must be shared by both sides
+pub fn generate_key_derivation(kdf: &str) -> Result<KeyDerivation> {
+    let salt = base64::encode_config(Crypto::random_bytes(SALT_SIZE), base64::URL_SAFE_NO_PAD);
+    let separator = if kdf.contains(':') { ',' } else { ':' };
+
+    format!("{}{}salt={}", kdf, separator, salt).parse().map_err(Error::msg)
+}
+
 pub async fn generate_certificate(hostname: &str, cert_path: &str, key_path: &str) -> Result<()> {
     tls::generate_cert_and_key(vec![hostname.to_string()], cert_path, key_path)?;
     Ok(())
@@ -2,13 +2,14 @@ use std::{future::Future, sync::Arc, time::Duration};
 
 use anyhow::{Error, Result};
 use bp_core::{
-    acl::get_acl, init_dns_resolver, init_quic_endpoint_pool, init_quinn_client_config, init_quinn_server_config,
-    init_tls_client_config, init_tls_server_config, monitor_log, set_monitor, start_monitor_service, start_pac_service,
-    start_quic_service, start_tcp_service, start_tls_service, start_udp_service, Connection, Options, ServiceInfo,
-    ServiceProtocol, Shutdown, Socket, Startup,
+    acl::get_acl, get_user_limiter, init_dns_resolver, init_quic_connection_pool, init_quic_endpoint_pool,
+    init_quinn_client_config, init_quinn_server_config, init_tls_client_config, init_tls_server_config, monitor_log,
+    set_monitor, start_h2_service, start_monitor_service, start_pac_service, start_quic_service, start_tcp_service,
+    start_tls_service, start_udp_service, start_websocket_service, Connection, Options, ServiceInfo, ServiceProtocol,
+    Shutdown, Socket, Startup, Transport,
 };
 use bp_monitor::{events, Monitor};
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, task::JoinHandle};
 
 use crate::{dirs::Dirs, utils::counter::Counter};
 
@@ -34,8 +35,23 @@ pub async fn run(mut opts: Options, startup: StartupSender, shutdown: impl Futur
         return fail(err).await;
     }
 
+    // strengthen keys, which may take a while
+    if !opts.key_derivation().is_none() {
+        log::info!("deriving keys by {}...", opts.key_derivation().to_string());
+
+        if let Err(err) = opts.derive_keys() {
+            return fail(err).await;
+        }
+    }
+
     let inner_shutdown = Shutdown::new();
 
+    // apply limits of users
+    let persist = match apply_user_limits(&opts, inner_shutdown.clone()) {
+        Ok(persist) => persist,
+        Err(err) => return fail(err).await,
+    };
+
     // bootstrap bp service
     tokio::select! {
         Err(err) = boot(opts, startup.clone(), inner_shutdown.clone()) => {
@@ -45,11 +61,46 @@ pub async fn run(mut opts: Options, startup: StartupSender, shutdown: impl Futur
             log::info!("gracefully shutting down...");
             let count = inner_shutdown.broadcast();
             log::info!("informed {} receivers, waiting for 2 seconds...", count);
-            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            // usage of users must be saved before exit, but don't wait longer than others
+            let wait = Duration::from_secs(2);
+            let saved = async {
+                if let Some(persist) = persist {
+                    let _ = tokio::time::timeout(wait, persist).await;
+                }
+            };
+
+            tokio::join!(saved, tokio::time::sleep(wait));
         }
     };
 }
 
+/// Apply limits of users, and save usage of users in background if --user-usage-file is set
+fn apply_user_limits(opts: &Options, shutdown: Shutdown) -> Result<Option<JoinHandle<()>>> {
+    if !opts.is_server() || opts.server_opts().users.is_empty() {
+        return Ok(None);
+    }
+
+    let server_opts = opts.server_opts();
+    let limiter = get_user_limiter();
+
+    limiter.set_limits(server_opts.user_limits());
+
+    let path = match server_opts.user_usage_file {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    limiter.load_from_file(&path).map_err(|err| {
+        let msg = format!("cannot load usage of users from {} due to: {}", path, err);
+        Error::msg(msg)
+    })?;
+
+    Ok(Some(tokio::spawn(async move {
+        limiter.persist(&path, shutdown).await;
+    })))
+}
+
 const SERVICE_CONNECTION_THRESHOLD: usize = 1024;
 
 async fn boot(opts: Options, startup: StartupSender, shutdown: Shutdown) -> Result<()> {
@@ -71,14 +122,15 @@ async fn pre_boot(opts: &Options) -> Result<()> {
     init_dns_resolver(opts.dns_server().as_socket_addr()).await?;
 
     // init tls configs
-    if opts.tls() || opts.quic() {
+    if opts.tls() || opts.quic() || (opts.is_client() && opts.client_opts().https_bind.is_some()) {
         init_tls_configs(opts)?;
     }
 
-    // init quic endpoint pool
+    // init quic endpoint and connection pools
     if opts.is_client() && opts.quic() {
         let quic_max_concurrency = opts.client_opts().quic_max_concurrency;
         init_quic_endpoint_pool(quic_max_concurrency)?;
+        init_quic_connection_pool(quic_max_concurrency)?;
     }
 
     Ok(())
@@ -93,6 +145,7 @@ async fn start_services(opts: Options, startup: StartupSender, shutdown: Shutdow
     let bind_ip = bind_addr.ip().to_string();
     let bind_host = opts.bind().host();
     let bind_port = opts.bind().port();
+    let accept_proxy_protocol = opts.accept_proxy_protocol();
 
     macro_rules! add_service {
         ($protocol:expr) => {{
@@ -107,9 +160,42 @@ async fn start_services(opts: Options, startup: StartupSender, shutdown: Shutdow
     }
 
     if opts.is_server() {
+        // server side set --transport, start WebSocket service, over TLS if --tls is on
+        if matches!(opts.transport(), Some(Transport::WebSocket)) {
+            start_websocket_service(
+                bind_addr,
+                opts.transport_path(),
+                opts.tls(),
+                accept_proxy_protocol,
+                sender.clone(),
+                shutdown.clone(),
+            )
+            .await?;
+            start_udp_service(bind_addr, sender.clone(), shutdown.clone()).await?;
+            add_service!(if opts.tls() {
+                ServiceProtocol::Wss
+            } else {
+                ServiceProtocol::Ws
+            });
+            add_service!(ServiceProtocol::Udp);
+        }
+        // server side set --transport h2, start HTTP/2 service over TLS
+        else if matches!(opts.transport(), Some(Transport::H2)) {
+            start_h2_service(
+                bind_addr,
+                opts.transport_path(),
+                accept_proxy_protocol,
+                sender.clone(),
+                shutdown.clone(),
+            )
+            .await?;
+            start_udp_service(bind_addr, sender.clone(), shutdown.clone()).await?;
+            add_service!(ServiceProtocol::H2);
+            add_service!(ServiceProtocol::Udp);
+        }
         // server side enable --tls, start TLS service
-        if opts.tls() {
-            start_tls_service(bind_addr, sender.clone(), shutdown.clone()).await?;
+        else if opts.tls() {
+            start_tls_service(bind_addr, accept_proxy_protocol, sender.clone(), shutdown.clone()).await?;
             start_udp_service(bind_addr, sender.clone(), shutdown.clone()).await?;
             add_service!(ServiceProtocol::Tls);
             add_service!(ServiceProtocol::Udp);
@@ -121,7 +207,7 @@ async fn start_services(opts: Options, startup: StartupSender, shutdown: Shutdow
         }
         // others
         else {
-            start_tcp_service(bind_addr, sender.clone(), shutdown.clone()).await?;
+            start_tcp_service(bind_addr, accept_proxy_protocol, sender.clone(), shutdown.clone()).await?;
             start_udp_service(bind_addr, sender.clone(), shutdown.clone()).await?;
             add_service!(ServiceProtocol::Tcp);
             add_service!(ServiceProtocol::Udp);
@@ -129,14 +215,29 @@ async fn start_services(opts: Options, startup: StartupSender, shutdown: Shutdow
     }
 
     if opts.is_client() {
-        start_tcp_service(bind_addr, sender.clone(), shutdown.clone()).await?;
-        start_udp_service(bind_addr, sender, shutdown.clone()).await?;
+        start_tcp_service(bind_addr, accept_proxy_protocol, sender.clone(), shutdown.clone()).await?;
+        start_udp_service(bind_addr, sender.clone(), shutdown.clone()).await?;
 
         add_service!(ServiceProtocol::Tcp);
         add_service!(ServiceProtocol::Udp);
 
         let opts = opts.client_opts();
 
+        // start https service
+        if let Some(addr) = opts.https_bind.clone() {
+            let bind_addr = addr.resolve().await?;
+
+            start_tls_service(bind_addr, accept_proxy_protocol, sender.clone(), shutdown.clone()).await?;
+
+            services.push(ServiceInfo {
+                protocol: ServiceProtocol::Https,
+                bind_addr,
+                bind_host: addr.host(),
+                bind_ip: bind_addr.ip().to_string(),
+                bind_port: bind_addr.port(),
+            });
+        }
+
         // start pac service
         if let Some(addr) = opts.pac_bind.clone() {
             let bind_addr = addr.resolve().await?;
@@ -243,9 +344,14 @@ async fn start_services(opts: Options, startup: StartupSender, shutdown: Shutdow
 
                 live_cnt.dec();
 
+                let user = conn.user();
+
                 log::info!(
-                    "[{}] closed, {} live connections, {} in total",
+                    "[{}] closed{}, {} live connections, {} in total",
                     peer_addr,
+                    user.as_ref()
+                        .map(|user| format!(" by user {}", user))
+                        .unwrap_or_default(),
                     live_cnt,
                     total_cnt
                 );
@@ -255,6 +361,7 @@ async fn start_services(opts: Options, startup: StartupSender, shutdown: Shutdow
                     peer_addr,
                     live_cnt: live_cnt.value(),
                     total_cnt: total_cnt.value(),
+                    user,
                 });
             });
         }
@@ -293,6 +400,16 @@ fn init_tls_configs(opts: &Options) -> Result<()> {
                 init_quinn_client_config(cert)?;
             }
         }
+
+        // certificate and private key for --https-bind
+        let opts = opts.client_opts();
+
+        if let (Some(addr), Some(cert), Some(key)) = (opts.https_bind, opts.https_cert, opts.https_key) {
+            log::info!("loading TLS certificate from {} for https service at {}", cert, addr);
+            log::info!("loading TLS private key from {} for https service at {}", key, addr);
+
+            init_tls_server_config(&cert, &key)?;
+        }
     }
 
     Ok(())
@@ -19,6 +19,14 @@ pub struct GenerateOptions {
     /// Hostname for generating TLS certificates [default: <empty>]
     #[clap(long)]
     pub hostname: Option<String>,
+
+    /// Print a strong random key for --key [default: false]
+    #[clap(long)]
+    pub key: bool,
+
+    /// Print a value with random salt for --key-derivation, e.g, "argon2id" or "scrypt:n=15" [default: <empty>]
+    #[clap(long)]
+    pub key_derivation: Option<String>,
 }
 
 pub enum ConfigType {
@@ -5,6 +5,9 @@ pub const DEFAULT_SERVER_SERVICE_ADDRESS: &str = "127.0.0.1:3000";
 /// The default dns server address
 pub const DEFAULT_DNS_SERVER_ADDRESS: &str = "8.8.8.8:53";
 
+/// The default request path of --transport
+pub const DEFAULT_TRANSPORT_PATH: &str = "/";
+
 /// The timeout for resolving destination address
 pub const DEST_ADDR_RESOLVE_TIMEOUT_SECONDS: u64 = 10;
 
@@ -17,6 +20,33 @@ pub const TCP_CONNECT_TIMEOUT_SECONDS: u64 = 10;
 /// The timeout for QUIC connect
 pub const QUIC_CONNECT_TIMEOUT_SECONDS: u64 = 10;
 
+/// The max number of streams bp server accepts concurrently from a QUIC connection
+pub const QUIC_MAX_CONCURRENT_STREAMS: u32 = 1024;
+
+/// The max number of datagrams queued for a QUIC datagram session, more are dropped
+pub const QUIC_DATAGRAM_SESSION_QUEUE_SIZE: usize = 64;
+
+/// The timeout for waiting an incoming connection of Socks5 BIND
+pub const BIND_ACCEPT_TIMEOUT_SECONDS: u64 = 60;
+
+/// The max number of streams multiplexed in a mux session
+pub const MUX_MAX_STREAMS_PER_SESSION: usize = 128;
+
+/// The max data size of a mux frame
+pub const MUX_MAX_FRAME_SIZE: usize = 32 * 1024;
+
+/// The bytes can be sent by each mux stream before acknowledged by peer
+pub const MUX_WINDOW_SIZE: usize = 256 * 1024;
+
+/// The buffer size between a mux stream and its connection
+pub const MUX_STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The max number of DATA frames received but not written to a mux stream, the stream is reset beyond it
+pub const MUX_STREAM_QUEUE_SIZE: usize = 1024;
+
+/// The timeout for a mux session without any streams, bp server waits twice as long as bp client
+pub const MUX_IDLE_TIMEOUT_SECONDS: u64 = 60;
+
 /// The read or write timeout for each connection
 pub const READ_WRITE_TIMEOUT_SECONDS: u64 = 60;
 
@@ -25,3 +55,27 @@ pub const RECV_BUFFER_SIZE: usize = 1024 * 1024; // 1MB
 
 /// The max transmission unit for udp packet
 pub const UDP_MTU: usize = 1500;
+
+/// The timeout for reading PROXY protocol header
+pub const PROXY_PROTOCOL_READ_TIMEOUT_SECONDS: u64 = 10;
+
+/// The max difference between the timestamp in erp header and local time
+pub const ERP_MAX_TIMESTAMP_DIFF_SECONDS: u64 = 120;
+
+/// The number of erp salts remembered by bp server in each period, for replay protection
+pub const ERP_SALT_FILTER_CAPACITY: usize = 200_000;
+
+/// The false positive rate of erp salt filter, new sessions may be rejected as replayed at this rate
+pub const ERP_SALT_FILTER_FP_RATE: f64 = 1e-6;
+
+/// The max bytes read and discarded from a connection failed to handshake, the actual limit is random
+pub const HANDSHAKE_FAILURE_DRAIN_MAX_BYTES: usize = 64 * 1024;
+
+/// The timeout for reading and discarding data from a connection failed to handshake
+pub const HANDSHAKE_FAILURE_DRAIN_TIMEOUT_SECONDS: u64 = 30;
+
+/// The interval of saving usage of users to --user-usage-file
+pub const USER_USAGE_SAVE_INTERVAL_SECONDS: u64 = 60;
+
+/// The ALPN protocol negotiated by TLS for --transport h2
+pub const H2_ALPN_PROTOCOL: &[u8] = b"h2";
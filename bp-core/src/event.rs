@@ -1,12 +1,15 @@
 use anyhow::Error;
 use bytes::Bytes;
 
+use crate::protos::ResolvedResult;
+
 #[derive(Debug)]
 pub enum Event {
     ClientEncodeDone(Bytes),
     ServerEncodeDone(Bytes),
     ClientDecodeDone(Bytes),
     ServerDecodeDone(Bytes),
+    HttpRequestDone(ResolvedResult),
     InboundError(Error),
     OutboundError(Error),
 }
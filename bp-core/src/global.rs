@@ -1,15 +1,24 @@
-use std::sync::Arc;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{Error, Result};
 use bp_monitor::{events::Event, Monitor, Subscriber};
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use serde::Serialize;
+use tokio::sync::OnceCell;
 use trust_dns_resolver::TokioAsyncResolver;
 
 use crate::{
     acl::AccessControlList,
-    net::quic::{EndpointPool, RandomEndpoint},
+    constants,
+    net::{
+        h2::H2Connection,
+        limiter::UserLimiter,
+        mux::MuxSessions,
+        quic::{ConnectionPool, EndpointPool, RandomEndpoint},
+    },
+    utils::bloom::RotatingBloomFilter,
+    Shutdown,
 };
 
 type AsyncMutex<T> = tokio::sync::Mutex<T>;
@@ -23,6 +32,18 @@ lazy_static! {
     static ref QUINN_SERVER_CONFIG: Mutex<Option<quinn::ServerConfig>> = Default::default();
     static ref QUINN_CLIENT_CONFIG: Mutex<Option<quinn::ClientConfig>> = Default::default();
     static ref QUINN_ENDPOINT_POOL: Mutex<EndpointPool> = Default::default();
+    static ref QUINN_CONNECTION_POOL: Arc<Mutex<ConnectionPool>> = Default::default();
+    static ref USER_LIMITER: Arc<UserLimiter> = Default::default();
+    static ref MUX_SESSIONS: Arc<Mutex<HashMap<String, MuxSessions>>> = Default::default();
+    static ref H2_CONNECTIONS: Arc<Mutex<HashMap<String, Arc<OnceCell<H2Connection>>>>> = Default::default();
+    // keyed by the client address announced in UDP ASSOCIATE, port 0 accepts datagrams from any port of the ip
+    static ref SOCKS_UDP_ASSOCIATIONS: Mutex<HashMap<SocketAddr, (usize, Shutdown)>> = Default::default();
+    // a salt should be remembered as long as the timestamp along with it is acceptable, in case of clock skew
+    static ref ERP_SALT_FILTER: Mutex<RotatingBloomFilter> = Mutex::new(RotatingBloomFilter::new(
+        constants::ERP_SALT_FILTER_CAPACITY,
+        constants::ERP_SALT_FILTER_FP_RATE,
+        Duration::from_secs(constants::ERP_MAX_TIMESTAMP_DIFF_SECONDS * 2),
+    ));
 }
 
 // acl
@@ -31,6 +52,27 @@ pub fn get_acl() -> Arc<AccessControlList> {
     ACL.clone()
 }
 
+// user limiter
+
+pub fn get_user_limiter() -> Arc<UserLimiter> {
+    USER_LIMITER.clone()
+}
+
+// mux sessions
+
+/// Mux sessions to each bp server address, shared by all connections of bp client
+pub fn get_mux_sessions() -> Arc<Mutex<HashMap<String, MuxSessions>>> {
+    MUX_SESSIONS.clone()
+}
+
+// h2 connections
+
+/// HTTP/2 connections to each bp server address, shared by all connections of bp client, the cell is empty while
+/// the connection is being made
+pub fn get_h2_connections() -> Arc<Mutex<HashMap<String, Arc<OnceCell<H2Connection>>>>> {
+    H2_CONNECTIONS.clone()
+}
+
 // dns_resolver
 
 pub fn get_dns_resolver() -> Arc<AsyncMutex<Option<TokioAsyncResolver>>> {
@@ -112,3 +154,60 @@ pub fn get_quic_random_endpoint() -> Result<RandomEndpoint> {
     let mut quic_endpoint_pool = QUINN_ENDPOINT_POOL.lock();
     quic_endpoint_pool.random_endpoint()
 }
+
+pub fn set_quic_connection_pool(pool: ConnectionPool) {
+    let mut quic_connection_pool = QUINN_CONNECTION_POOL.lock();
+    *quic_connection_pool = pool;
+}
+
+pub fn get_quic_connection_pool() -> Arc<Mutex<ConnectionPool>> {
+    QUINN_CONNECTION_POOL.clone()
+}
+
+// socks5 udp associations
+
+pub fn socks_udp_associate(addr: SocketAddr) {
+    let mut associations = SOCKS_UDP_ASSOCIATIONS.lock();
+    let (count, _) = associations.entry(addr).or_insert_with(|| (0, Shutdown::new()));
+    *count += 1;
+}
+
+pub fn socks_udp_dissociate(addr: SocketAddr) {
+    let mut associations = SOCKS_UDP_ASSOCIATIONS.lock();
+
+    if let Some((count, shutdown)) = associations.get_mut(&addr) {
+        *count -= 1;
+
+        // the last association is terminated, stop all udp relays of this client
+        if *count == 0 {
+            shutdown.broadcast();
+            associations.remove(&addr);
+        }
+    }
+}
+
+/// Find the association a datagram from peer_addr belongs to, the exact address is preferred
+pub fn get_socks_udp_association(peer_addr: SocketAddr) -> Option<Shutdown> {
+    let associations = SOCKS_UDP_ASSOCIATIONS.lock();
+    associations
+        .get(&peer_addr)
+        .or_else(|| associations.get(&SocketAddr::new(peer_addr.ip(), 0)))
+        .map(|(_, shutdown)| shutdown.clone())
+}
+
+pub fn is_socks_udp_associated(peer_addr: SocketAddr) -> bool {
+    get_socks_udp_association(peer_addr).is_some()
+}
+
+// erp salts
+
+/// Remember the salt of an erp session, fail if the salt is used recently
+pub fn erp_remember_salt(salt: &[u8]) -> Result<()> {
+    let mut filter = ERP_SALT_FILTER.lock();
+
+    if filter.contains_or_insert(salt) {
+        return Err(Error::msg("salt is used recently, the session may be replayed"));
+    }
+
+    Ok(())
+}
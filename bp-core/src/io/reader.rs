@@ -5,14 +5,16 @@ use std::sync::{
 
 use anyhow::{Error, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::{stream::SplitStream, StreamExt};
 use parking_lot;
 use quinn::RecvStream;
 use tokio::{
-    io::{AsyncReadExt, ReadHalf},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, DuplexStream, ReadHalf},
     net::{TcpStream, UdpSocket},
-    sync::Mutex,
+    sync::{mpsc::Receiver, Mutex},
 };
 use tokio_rustls::TlsStream;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 
 use crate::{constants, utils::store::Store};
 
@@ -23,6 +25,11 @@ enum ReaderType {
     Tls(ReadHalf<TlsStream<TcpStream>>),
     Udp(Arc<UdpSocket>),
     Quic(RecvStream),
+    Mux(ReadHalf<DuplexStream>),
+    QuicDatagram(Receiver<Bytes>),
+    Ws(SplitStream<WebSocketStream<TcpStream>>),
+    Wss(SplitStream<WebSocketStream<TlsStream<TcpStream>>>),
+    H2(h2::RecvStream),
 }
 
 impl Default for ReaderType {
@@ -69,6 +76,41 @@ impl SocketReader {
         }
     }
 
+    pub fn from_mux(read_half: ReadHalf<DuplexStream>) -> Self {
+        Self {
+            reader: Mutex::new(ReaderType::Mux(read_half)),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_quic_datagram(receiver: Receiver<Bytes>) -> Self {
+        Self {
+            reader: Mutex::new(ReaderType::QuicDatagram(receiver)),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_ws(stream: SplitStream<WebSocketStream<TcpStream>>) -> Self {
+        Self {
+            reader: Mutex::new(ReaderType::Ws(stream)),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_wss(stream: SplitStream<WebSocketStream<TlsStream<TcpStream>>>) -> Self {
+        Self {
+            reader: Mutex::new(ReaderType::Wss(stream)),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_h2(recv_stream: h2::RecvStream) -> Self {
+        Self {
+            reader: Mutex::new(ReaderType::H2(recv_stream)),
+            ..Self::default()
+        }
+    }
+
     pub async fn read_some(&self) -> Result<Bytes> {
         let mut recv_buf = BytesMut::with_capacity(constants::RECV_BUFFER_SIZE);
         let n = self.read_into(&mut recv_buf).await?;
@@ -106,8 +148,8 @@ impl SocketReader {
         }
 
         macro_rules! read_packet {
-            ($reader:ident) => {{
-                let (buf, n) = self.packet_recv($reader).await?;
+            ($recv:expr) => {{
+                let (buf, n) = $recv.await?;
                 self.store(|| buf.clone());
                 out_buf.put(buf);
                 n
@@ -118,7 +160,12 @@ impl SocketReader {
             ReaderType::Tcp(reader) => Ok(read_stream!(reader)),
             ReaderType::Tls(reader) => Ok(read_stream!(reader)),
             ReaderType::Quic(reader) => Ok(read_stream!(reader)),
-            ReaderType::Udp(reader) => Ok(read_packet!(reader)),
+            ReaderType::Mux(reader) => Ok(read_stream!(reader)),
+            ReaderType::Udp(reader) => Ok(read_packet!(self.packet_recv(reader))),
+            ReaderType::QuicDatagram(receiver) => Ok(read_packet!(Self::datagram_recv(receiver))),
+            ReaderType::Ws(reader) => Ok(read_packet!(Self::message_recv(reader))),
+            ReaderType::Wss(reader) => Ok(read_packet!(Self::message_recv(reader))),
+            ReaderType::H2(reader) => Ok(read_packet!(Self::data_recv(reader))),
             ReaderType::Unknown => unreachable!(),
         }
     }
@@ -138,9 +185,9 @@ impl SocketReader {
         }
 
         macro_rules! read_packet {
-            ($reader:ident) => {{
+            ($recv:expr) => {{
                 let req_buf_len = len - cache_len;
-                let (packet, size) = self.packet_recv($reader).await?;
+                let (packet, size) = $recv.await?;
 
                 if size < req_buf_len {
                     let msg = format!(
@@ -155,13 +202,30 @@ impl SocketReader {
             }};
         }
 
+        // a message or frame may carry less or more data than required, the rest is kept in cache
+        macro_rules! read_message {
+            ($recv:expr) => {{
+                while self.cache_len() < len {
+                    let (message, _) = $recv.await?;
+
+                    let mut cache = self.cache.lock();
+                    cache.push_back(message);
+                }
+            }};
+        }
+
         // cached data is not enough
         if len > cache_len {
             match &mut *self.reader.lock().await {
                 ReaderType::Tcp(reader) => read_stream!(reader),
                 ReaderType::Tls(reader) => read_stream!(reader),
                 ReaderType::Quic(reader) => read_stream!(reader),
-                ReaderType::Udp(reader) => read_packet!(reader),
+                ReaderType::Mux(reader) => read_stream!(reader),
+                ReaderType::Udp(reader) => read_packet!(self.packet_recv(reader)),
+                ReaderType::QuicDatagram(receiver) => read_packet!(Self::datagram_recv(receiver)),
+                ReaderType::Ws(reader) => read_message!(Self::message_recv(reader)),
+                ReaderType::Wss(reader) => read_message!(Self::message_recv(reader)),
+                ReaderType::H2(reader) => read_message!(Self::data_recv(reader)),
                 ReaderType::Unknown => unreachable!(),
             }
         }
@@ -207,6 +271,53 @@ impl SocketReader {
         }
     }
 
+    async fn datagram_recv(receiver: &mut Receiver<Bytes>) -> Result<(Bytes, usize)> {
+        match receiver.recv().await {
+            Some(packet) => {
+                let len = packet.len();
+                Ok((packet, len))
+            }
+            None => Err(Error::msg("quic datagram session is closed")),
+        }
+    }
+
+    /// Receive data of the next binary message, control frames are handled by tungstenite
+    async fn message_recv<S>(reader: &mut SplitStream<WebSocketStream<S>>) -> Result<(Bytes, usize)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        loop {
+            match reader.next().await {
+                Some(Ok(Message::Binary(data))) if !data.is_empty() => {
+                    let len = data.len();
+                    return Ok((data.into(), len));
+                }
+                Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_))) | None => return Err(Error::msg("websocket is closed")),
+                Some(Ok(message)) => return Err(Error::msg(format!("unexpected websocket message: {}", message))),
+                Some(Err(err)) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Receive data of the next DATA frame, the capacity is released at once so that peer can send more
+    async fn data_recv(reader: &mut h2::RecvStream) -> Result<(Bytes, usize)> {
+        loop {
+            match reader.data().await {
+                Some(Ok(data)) => {
+                    let len = data.len();
+                    reader.flow_control().release_capacity(len)?;
+
+                    if len > 0 {
+                        return Ok((data, len));
+                    }
+                }
+                Some(Err(err)) => return Err(err.into()),
+                None => return Err(Error::msg("h2 stream is closed")),
+            }
+        }
+    }
+
     #[inline]
     fn cache_len(&self) -> usize {
         let cache = self.cache.lock();
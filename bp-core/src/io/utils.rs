@@ -1,7 +1,14 @@
 use std::sync::Arc;
 
-use tokio::net::{TcpStream, UdpSocket};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use tokio::{
+    io::DuplexStream,
+    net::{TcpStream, UdpSocket},
+    sync::mpsc::Receiver,
+};
 use tokio_rustls::TlsStream;
+use tokio_tungstenite::WebSocketStream;
 
 use super::{reader::SocketReader, writer::SocketWriter};
 
@@ -23,6 +30,33 @@ pub fn split_tls(stream: TlsStream<TcpStream>) -> (SocketReader, SocketWriter) {
     (reader, writer)
 }
 
+pub fn split_ws(stream: WebSocketStream<TcpStream>) -> (SocketReader, SocketWriter) {
+    let (sink, stream) = stream.split();
+
+    let reader = SocketReader::from_ws(stream);
+    let writer = SocketWriter::from_ws(sink);
+
+    (reader, writer)
+}
+
+pub fn split_wss(stream: WebSocketStream<TlsStream<TcpStream>>) -> (SocketReader, SocketWriter) {
+    let (sink, stream) = stream.split();
+
+    let reader = SocketReader::from_wss(stream);
+    let writer = SocketWriter::from_wss(sink);
+
+    (reader, writer)
+}
+
+pub fn split_h2(stream: (h2::SendStream<Bytes>, h2::RecvStream)) -> (SocketReader, SocketWriter) {
+    let (send, recv) = stream;
+
+    let reader = SocketReader::from_h2(recv);
+    let writer = SocketWriter::from_h2(send);
+
+    (reader, writer)
+}
+
 pub fn split_udp(socket: Arc<UdpSocket>) -> (SocketReader, SocketWriter) {
     let reader = SocketReader::from_udp(socket.clone());
     let writer = SocketWriter::from_udp(socket);
@@ -38,3 +72,23 @@ pub fn split_quic(stream: (quinn::SendStream, quinn::RecvStream)) -> (SocketRead
 
     (reader, writer)
 }
+
+pub fn split_mux(stream: DuplexStream) -> (SocketReader, SocketWriter) {
+    let (read_half, write_half) = tokio::io::split(stream);
+
+    let reader = SocketReader::from_mux(read_half);
+    let writer = SocketWriter::from_mux(write_half);
+
+    (reader, writer)
+}
+
+pub fn split_quic_datagram(
+    conn: quinn::Connection,
+    session_id: u32,
+    receiver: Receiver<Bytes>,
+) -> (SocketReader, SocketWriter) {
+    let reader = SocketReader::from_quic_datagram(receiver);
+    let writer = SocketWriter::from_quic_datagram(conn, session_id);
+
+    (reader, writer)
+}
@@ -1,12 +1,15 @@
 use std::{net::SocketAddr, sync::Arc};
 
-use quinn::SendStream;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{future::poll_fn, stream::SplitSink, SinkExt};
+use quinn::{Connection, SendStream};
 use tokio::{
-    io::{AsyncWriteExt, WriteHalf},
+    io::{AsyncWriteExt, DuplexStream, WriteHalf},
     net::{TcpStream, UdpSocket},
     sync::Mutex,
 };
 use tokio_rustls::TlsStream;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 
 #[derive(Debug)]
 enum WriterType {
@@ -15,6 +18,11 @@ enum WriterType {
     Tls(WriteHalf<TlsStream<TcpStream>>),
     Udp(Arc<UdpSocket>),
     Quic(SendStream),
+    Mux(WriteHalf<DuplexStream>),
+    QuicDatagram(Connection, u32),
+    Ws(SplitSink<WebSocketStream<TcpStream>, Message>),
+    Wss(SplitSink<WebSocketStream<TlsStream<TcpStream>>, Message>),
+    H2(h2::SendStream<Bytes>),
 }
 
 impl Default for WriterType {
@@ -54,6 +62,36 @@ impl SocketWriter {
         }
     }
 
+    pub fn from_mux(write_half: WriteHalf<DuplexStream>) -> Self {
+        Self {
+            inner: Mutex::new(WriterType::Mux(write_half)),
+        }
+    }
+
+    pub fn from_quic_datagram(conn: Connection, session_id: u32) -> Self {
+        Self {
+            inner: Mutex::new(WriterType::QuicDatagram(conn, session_id)),
+        }
+    }
+
+    pub fn from_ws(sink: SplitSink<WebSocketStream<TcpStream>, Message>) -> Self {
+        Self {
+            inner: Mutex::new(WriterType::Ws(sink)),
+        }
+    }
+
+    pub fn from_wss(sink: SplitSink<WebSocketStream<TlsStream<TcpStream>>, Message>) -> Self {
+        Self {
+            inner: Mutex::new(WriterType::Wss(sink)),
+        }
+    }
+
+    pub fn from_h2(send_stream: h2::SendStream<Bytes>) -> Self {
+        Self {
+            inner: Mutex::new(WriterType::H2(send_stream)),
+        }
+    }
+
     pub async fn send(&self, buf: &[u8]) -> tokio::io::Result<()> {
         macro_rules! write_stream {
             ($writer:ident) => {{
@@ -62,10 +100,21 @@ impl SocketWriter {
             }};
         }
 
+        macro_rules! write_message {
+            ($writer:ident) => {{
+                let message = Message::Binary(buf.to_vec());
+                $writer.send(message).await.map_err(Self::ws_error)?;
+            }};
+        }
+
         match &mut *self.inner.lock().await {
             WriterType::Tcp(writer) => write_stream!(writer),
             WriterType::Tls(writer) => write_stream!(writer),
             WriterType::Quic(writer) => write_stream!(writer),
+            WriterType::Mux(writer) => write_stream!(writer),
+            WriterType::Ws(writer) => write_message!(writer),
+            WriterType::Wss(writer) => write_message!(writer),
+            WriterType::H2(writer) => Self::data_send(writer, buf).await?,
             _ => unreachable!(),
         }
 
@@ -77,6 +126,22 @@ impl SocketWriter {
             WriterType::Udp(writer) => {
                 writer.send_to(buf, peer_addr).await?;
             }
+            // the peer of a session is the other end of QUIC connection
+            WriterType::QuicDatagram(conn, session_id) => {
+                let mut datagram = BytesMut::with_capacity(4 + buf.len());
+                datagram.put_u32(*session_id);
+                datagram.put_slice(buf);
+
+                let datagram = datagram.freeze();
+
+                // packets exceed the max datagram size of the path are sent by unidirectional streams instead
+                if conn.max_datagram_size().map_or(true, |size| datagram.len() > size) {
+                    Self::uni_stream_send(conn.clone(), datagram);
+                } else {
+                    conn.send_datagram(datagram)
+                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+                }
+            }
             _ => unreachable!(),
         }
         Ok(())
@@ -87,10 +152,70 @@ impl SocketWriter {
             WriterType::Tcp(writer) => writer.shutdown().await?,
             WriterType::Tls(writer) => writer.shutdown().await?,
             WriterType::Quic(writer) => writer.shutdown().await?,
+            WriterType::Mux(writer) => writer.shutdown().await?,
             WriterType::Udp(_writer) => (),
+            WriterType::QuicDatagram(..) => (),
+            WriterType::Ws(writer) => writer.close().await.map_err(Self::ws_error)?,
+            WriterType::Wss(writer) => writer.close().await.map_err(Self::ws_error)?,
+            // end the body, the stream is closed once peer ends as well
+            WriterType::H2(writer) => writer.send_data(Bytes::new(), true).map_err(Self::h2_error)?,
             WriterType::Unknown => unreachable!(),
         }
 
         Ok(())
     }
+
+    /// Send data as much as the flow control window allows, and wait for more capacity for the rest
+    async fn data_send(writer: &mut h2::SendStream<Bytes>, buf: &[u8]) -> tokio::io::Result<()> {
+        let mut data = Bytes::copy_from_slice(buf);
+
+        while !data.is_empty() {
+            writer.reserve_capacity(data.len());
+
+            let capacity = match poll_fn(|cx| writer.poll_capacity(cx)).await {
+                Some(capacity) => capacity.map_err(Self::h2_error)?,
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        "h2 stream is closed",
+                    ))
+                }
+            };
+
+            if capacity > 0 {
+                let chunk = data.split_to(capacity.min(data.len()));
+                writer.send_data(chunk, false).map_err(Self::h2_error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a packet by a new unidirectional stream in background, it's dropped on failure like a datagram
+    fn uni_stream_send(conn: Connection, packet: Bytes) {
+        tokio::spawn(async move {
+            let res = async {
+                let mut stream = conn.open_uni().await?;
+                stream.write_all(&packet).await?;
+                stream.finish().await?;
+                Ok::<_, anyhow::Error>(())
+            };
+
+            if let Err(err) = res.await {
+                log::warn!(
+                    "[{}] cannot send packet by quic stream due to: {}",
+                    conn.remote_address(),
+                    err
+                );
+            }
+        });
+    }
+
+    fn h2_error(err: h2::Error) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Other, err)
+    }
+
+    fn ws_error(err: tokio_tungstenite::tungstenite::Error) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Other, err)
+    }
 }
@@ -20,19 +20,25 @@ pub use net::{
     address::Address,
     connection::Connection,
     dns::init_dns_resolver,
-    quic::{init_quic_endpoint_pool, init_quinn_client_config, init_quinn_server_config},
+    inbound::HandshakeFailurePolicy,
+    limiter::{get_user_limiter, UserLimiter, UserLimits, UserUsage},
+    quic::{init_quic_connection_pool, init_quic_endpoint_pool, init_quinn_client_config, init_quinn_server_config},
     socket::Socket,
     tls::{init_tls_client_config, init_tls_server_config},
+    transport::Transport,
+    upstream::{UpstreamProxy, UpstreamProxyScheme},
 };
 pub use options::{
     client::ClientOptions,
     common::{Options, ServiceType},
     server::ServerOptions,
+    user::User,
     utils::options_from_file,
 };
-pub use protos::{EncryptionMethod, HttpBasicAuth};
+pub use protos::{Credentials, EncryptionMethod, HttpBasicAuth, SocksAuth};
 pub use services::{
-    monitor::start_monitor_service, pac::start_pac_service, quic::start_quic_service, tcp::start_tcp_service,
-    tls::start_tls_service, udp::start_udp_service, ServiceInfo, ServiceProtocol, Startup,
+    h2::start_h2_service, monitor::start_monitor_service, pac::start_pac_service, quic::start_quic_service,
+    tcp::start_tcp_service, tls::start_tls_service, udp::start_udp_service, websocket::start_websocket_service,
+    ServiceInfo, ServiceProtocol, Startup,
 };
 pub use shutdown::Shutdown;
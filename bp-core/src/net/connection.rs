@@ -1,6 +1,9 @@
-use anyhow::Result;
+use std::net::IpAddr;
+
+use anyhow::{Error, Result};
+use futures_util::future::BoxFuture;
 use tokio::{
-    sync::mpsc::{channel, Receiver},
+    sync::mpsc::{channel, Receiver, Sender},
     time,
 };
 
@@ -11,11 +14,17 @@ use crate::{
     net::{
         address::Address,
         inbound::Inbound,
-        outbound::Outbound,
+        limiter::UserGuard,
+        mux::MuxSession,
+        outbound::{ConnectStatus, ConnectStatusError, Outbound},
         socket::{Socket, SocketType},
+        transport::Transport,
+    },
+    protos::{
+        init_protocol, Direct, Dns, DynProtocol, EncryptionMethod, Http, Plain, Protocol, ProtocolType, ResolvedResult,
+        Socks,
     },
-    protos::{init_protocol, Direct, Dns, DynProtocol, ProtocolType, ResolvedResult},
-    Options, ServiceType, Shutdown,
+    Options, ServerOptions, ServiceType, Shutdown,
 };
 
 pub struct Connection {
@@ -23,19 +32,23 @@ pub struct Connection {
     inbound: Inbound,
     outbound: Outbound,
     peer_addr: Address,
+    user_guard: Option<UserGuard>,
+    shutdown: Shutdown,
 }
 
 impl Connection {
     pub fn new(socket: Socket, opts: Options, shutdown: Shutdown) -> Self {
         let peer_addr = socket.peer_addr();
         let inbound = Inbound::new(socket, opts.clone(), shutdown.clone());
-        let outbound = Outbound::new(peer_addr, opts.clone(), shutdown);
+        let outbound = Outbound::new(peer_addr, opts.clone(), shutdown.clone());
 
         Connection {
             inbound,
             outbound,
             peer_addr: peer_addr.into(),
             opts,
+            user_guard: None,
+            shutdown,
         }
     }
 
@@ -43,53 +56,61 @@ impl Connection {
         // NOTE: higher buffer size leads to higher memory & cpu usage
         let (tx, rx) = channel::<Event>(32);
 
-        let in_proto = self.inbound.resolve().await?;
-        let resolved = in_proto.get_resolved_result();
+        let mut in_proto = self.inbound.resolve().await?;
+        let resolved = in_proto.get_resolved_result().clone();
 
         self.inbound.set_protocol_name(in_proto.get_name());
 
-        // check resolved target address
-        self.check_resolved_result(resolved).await?;
+        // streams of a mux session are handled as separate connections
+        if matches!(resolved.protocol, ProtocolType::Mux) {
+            return self.handle_mux(in_proto).await;
+        }
 
-        let mut out_proto: DynProtocol;
+        // limits of the user are applied until the connection is closed
+        if let Some(user) = self.inbound.user() {
+            match UserGuard::acquire(&user) {
+                Ok(guard) => self.user_guard = Some(guard),
+                Err(err) => {
+                    log::warn!("[{}] [{}] {}", self.peer_addr, self.inbound.socket_type(), err);
+                    let _ = self
+                        .inbound
+                        .reply_connect_status(&mut in_proto, ConnectStatus::NotAllowed)
+                        .await;
+                    return Err(err);
+                }
+            }
+        }
 
-        // check acl
-        if self.check_acl(&resolved.address) {
-            self.outbound.set_socket_type(self.get_outbound_socket_type(resolved));
-            out_proto = self.create_outbound_protocol(resolved);
-        } else {
-            let will = match self.opts.service_type() {
-                ServiceType::Client => "not proxy to bp server",
-                ServiceType::Server => "close this connection",
-            };
+        // socks5 UDP ASSOCIATE has no destination to connect, datagrams are relayed by udp service
+        if matches!(resolved.protocol, ProtocolType::SocksUdpAssociate) {
+            return self.inbound.hold_udp_association(&resolved.address).await;
+        }
 
-            log::warn!(
-                "[{}] [{}] {} is DENY by acl, will {}",
-                self.peer_addr,
-                self.inbound.socket_type(),
-                resolved.address,
-                will,
-            );
+        // socks5 BIND waits for an incoming connection instead of connecting to the destination
+        if matches!(resolved.protocol, ProtocolType::SocksBind) {
+            return self.handle_bind(in_proto).await;
+        }
 
-            match self.opts.service_type() {
-                ServiceType::Client => {
-                    // change outbound protocol to TCP
-                    // TODO: close connection on client side?
-                    self.outbound.set_socket_type(SocketType::Tcp);
-                    self.outbound.set_allow_proxy(false);
-                    out_proto = Box::<Direct>::default();
-                }
-                ServiceType::Server => {
-                    // close connection
-                    return Ok(());
-                }
-            }
+        // plain HTTP requests of a keep-alive connection may be sent to different dest addresses
+        if matches!(resolved.protocol, ProtocolType::Http) {
+            return self.handle_http(in_proto).await;
         }
 
-        self.outbound.set_protocol_name(&out_proto.get_name());
+        // check resolved target address
+        if let Err((status, err)) = self.check_resolved_result(&resolved).await {
+            self.inbound.reply_connect_status(&mut in_proto, status).await?;
+            return Err(err);
+        }
 
-        // sync resolve result to outbound protocol
-        out_proto.set_resolved_result(resolved.clone());
+        let mut out_proto = match self.prepare_outbound(&resolved) {
+            Some(out_proto) => out_proto,
+            None => {
+                self.inbound
+                    .reply_connect_status(&mut in_proto, ConnectStatus::NotAllowed)
+                    .await?;
+                return Ok(());
+            }
+        };
 
         // handle pending_buf from inbound
         if let Some(buf) = resolved.pending_buf.as_ref() {
@@ -98,18 +119,39 @@ impl Connection {
                 .await?;
         }
 
+        // connect to remote from outbound, some protocols reply the result to client
+        let mut connected = self.outbound.start_connect(&resolved).await;
+
+        // wait for bp server connecting to dest address if the result should be replied to client,
+        // otherwise the status is checked when receiving data
+        let is_reply_required = matches!(resolved.protocol, ProtocolType::Socks | ProtocolType::HttpProxy);
+
+        if connected.is_ok() && is_reply_required && resolved.pending_buf.is_none() {
+            connected = self.outbound.recv_connect_status(&mut out_proto).await;
+        }
+
+        self.inbound
+            .reply_connect_status(&mut in_proto, self.outbound.connect_status())
+            .await?;
+
+        connected?;
+
         // start receiving data from inbound
         match self.inbound.socket_type() {
-            SocketType::Tcp | SocketType::Tls | SocketType::Quic => {
+            SocketType::Tcp
+            | SocketType::Tls
+            | SocketType::Quic
+            | SocketType::Mux
+            | SocketType::Ws
+            | SocketType::H2 => {
                 self.inbound
                     .handle_incoming_data(in_proto.clone(), out_proto.clone(), tx.clone());
             }
-            SocketType::Udp => (),
+            SocketType::Udp => {
+                self.inbound.watch_udp_association(tx.clone());
+            }
         }
 
-        // connect to remote from outbound
-        self.outbound.start_connect(resolved).await?;
-
         // start receiving data from outbound
         self.outbound.handle_incoming_data(in_proto, out_proto, tx);
 
@@ -118,12 +160,371 @@ impl Connection {
         Ok(())
     }
 
+    /// The user identified by key on server side
+    pub fn user(&self) -> Option<String> {
+        self.inbound.user()
+    }
+
+    /// Set the user of a stream demultiplexed from a mux session
+    pub fn set_user(&mut self, user: Option<String>) {
+        self.inbound.set_user(user);
+    }
+
     pub async fn close(&mut self) -> Result<()> {
         self.inbound.close().await?;
         self.outbound.close().await?;
         Ok(())
     }
 
+    async fn handle_mux(&mut self, mut in_proto: DynProtocol) -> Result<()> {
+        self.inbound
+            .reply_connect_status(&mut in_proto, ConnectStatus::Succeeded)
+            .await?;
+
+        let pending_buf = in_proto.get_resolved_result().pending_buf.clone();
+        let (session, mut incoming) = MuxSession::server(self.inbound.socket(), in_proto, pending_buf);
+
+        log::info!(
+            "[{}] [{}] mux session started",
+            self.peer_addr,
+            self.inbound.socket_type()
+        );
+
+        // streams are encrypted by the session already, so they carry plain protocol
+        let opts = Options::Server(ServerOptions {
+            encryption: EncryptionMethod::Plain,
+            users: vec![],
+            handshake_failure: Default::default(),
+            ..self.opts.server_opts()
+        });
+
+        loop {
+            let socket = tokio::select! {
+                socket = incoming.recv() => socket,
+                _ = self.shutdown.recv() => None,
+            };
+
+            let socket = match socket {
+                Some(socket) => socket,
+                None => break,
+            };
+
+            let mut conn = Connection::new(socket, opts.clone(), self.shutdown.clone());
+            conn.set_user(self.inbound.user());
+
+            tokio::spawn(Self::handle_mux_stream(conn));
+        }
+
+        session.close().await;
+
+        log::info!(
+            "[{}] [{}] mux session closed",
+            self.peer_addr,
+            self.inbound.socket_type()
+        );
+
+        Ok(())
+    }
+
+    // boxed, since it's recursive with handle()
+    fn handle_mux_stream(mut conn: Connection) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            if let Err(err) = conn.handle().await {
+                log::trace!("{}", err);
+                let _ = conn.close().await;
+            }
+        })
+    }
+
+    async fn handle_http(&mut self, mut in_proto: DynProtocol) -> Result<()> {
+        let (tx, mut rx) = channel::<Event>(32);
+
+        let mut http = Http::new(self.opts.client_opts().with_basic_auth);
+        http.set_resolved_result(in_proto.get_resolved_result().clone());
+
+        self.inbound.handle_http_requests(http, tx);
+
+        let mut out_proto: Option<DynProtocol> = None;
+
+        // events of the current outbound, previous ones are drained before switching to a new outbound
+        let mut out_rx: Option<Receiver<Event>> = None;
+
+        // whether the current dest address responded anything
+        let mut is_replied = false;
+
+        loop {
+            let future = Self::recv_http_event(&mut rx, &mut out_rx);
+
+            // timeout check
+            let timeout = time::timeout(time::Duration::from_secs(constants::READ_WRITE_TIMEOUT_SECONDS), future).await;
+
+            if timeout.is_err() {
+                log::warn!(
+                    "[{}] [{}] no data read/write for {} seconds",
+                    self.peer_addr,
+                    self.inbound.socket_type(),
+                    constants::READ_WRITE_TIMEOUT_SECONDS
+                );
+                self.close().await?;
+                break;
+            }
+
+            let event = match timeout.unwrap() {
+                Some(event) => event,
+                None => break,
+            };
+
+            match event {
+                Event::HttpRequestDone(resolved) => {
+                    let is_same_dest = out_proto
+                        .as_ref()
+                        .map(|proto| proto.get_resolved_result().address == resolved.address)
+                        .unwrap_or(false);
+
+                    if !is_same_dest {
+                        if out_proto.is_some() {
+                            log::info!(
+                                "[{}] [{}] dest address is changed to {}, reconnecting...",
+                                self.peer_addr,
+                                self.inbound.socket_type(),
+                                resolved.address,
+                            );
+                            self.outbound.close().await?;
+
+                            // pipelined requests to previous dest address must be responded before the new one
+                            if let Some(prev_out_rx) = out_rx.take() {
+                                self.drain_http_responses(prev_out_rx).await?;
+                            }
+
+                            self.outbound = self.outbound.fork();
+                        }
+
+                        in_proto.set_resolved_result(resolved.clone());
+
+                        let (out_tx, new_out_rx) = channel::<Event>(32);
+
+                        match self.connect_http(&resolved, in_proto.clone(), out_tx).await {
+                            Ok(proto) => {
+                                out_proto = Some(proto);
+                                out_rx = Some(new_out_rx);
+                                is_replied = false;
+                            }
+                            Err((status, err)) => {
+                                self.inbound.reply_connect_status(&mut in_proto, status).await?;
+                                return Err(err);
+                            }
+                        }
+                    }
+
+                    let buf = out_proto
+                        .as_mut()
+                        .unwrap()
+                        .client_encode_buf(resolved.pending_buf.unwrap())?;
+                    self.outbound.send(buf).await?;
+                }
+                Event::ClientDecodeDone(buf) => {
+                    is_replied = true;
+                    self.inbound.send(buf).await?;
+                }
+                Event::OutboundError(err) => {
+                    // remote closed without response, e.g, bp server replied a failure status
+                    if !is_replied {
+                        let _ = self
+                            .inbound
+                            .reply_connect_status(&mut in_proto, ConnectStatus::from_error(&err))
+                            .await;
+                    }
+                    self.close().await?;
+                    break;
+                }
+                Event::InboundError(_) => {
+                    self.close().await?;
+                    break;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receive events of HTTP requests from inbound, and events of responses from the current outbound
+    async fn recv_http_event(rx: &mut Receiver<Event>, out_rx: &mut Option<Receiver<Event>>) -> Option<Event> {
+        match out_rx {
+            Some(out_rx) => tokio::select! {
+                event = rx.recv() => event,
+                Some(event) = out_rx.recv() => Some(event),
+            },
+            None => rx.recv().await,
+        }
+    }
+
+    /// Relay responses of the closed outbound until it's ended by the remote, or no data for a while
+    async fn drain_http_responses(&mut self, mut out_rx: Receiver<Event>) -> Result<()> {
+        let idle = time::Duration::from_secs(constants::READ_WRITE_TIMEOUT_SECONDS);
+
+        while let Ok(Some(event)) = time::timeout(idle, out_rx.recv()).await {
+            match event {
+                Event::ClientDecodeDone(buf) => self.inbound.send(buf).await?,
+                _ => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn connect_http(
+        &mut self,
+        resolved: &ResolvedResult,
+        in_proto: DynProtocol,
+        tx: Sender<Event>,
+    ) -> Result<DynProtocol, (ConnectStatus, Error)> {
+        self.check_resolved_result(resolved).await?;
+
+        // bp client never closes connection due to acl
+        let out_proto = self.prepare_outbound(resolved).unwrap();
+
+        if let Err(err) = self.outbound.start_connect(resolved).await {
+            return Err((self.outbound.connect_status(), err));
+        }
+
+        self.outbound.handle_incoming_data(in_proto, out_proto.clone(), tx);
+
+        Ok(out_proto)
+    }
+
+    async fn handle_bind(&mut self, mut in_proto: DynProtocol) -> Result<()> {
+        let (tx, rx) = channel::<Event>(32);
+
+        let resolved = in_proto.get_resolved_result().clone();
+        let mut out_proto: DynProtocol = Box::<Direct>::default();
+
+        match self.opts.service_type() {
+            ServiceType::Client => {
+                out_proto = match self.handle_client_bind(resolved).await {
+                    Ok(out_proto) => out_proto,
+                    Err(err) => {
+                        let reply = Socks::build_reply(ConnectStatus::from_error(&err), None);
+                        let _ = self.inbound.send(reply).await;
+                        return Err(err);
+                    }
+                };
+            }
+            ServiceType::Server => {
+                if !self.check_acl(&resolved.address) {
+                    log::warn!(
+                        "[{}] [{}] bind from {} is DENY by acl, will close this connection",
+                        self.peer_addr,
+                        self.inbound.socket_type(),
+                        resolved.address,
+                    );
+                    self.inbound
+                        .reply_connect_status(&mut in_proto, ConnectStatus::NotAllowed)
+                        .await?;
+                    return Ok(());
+                }
+
+                let listener = match self.outbound.listen(self.get_bind_ip().await?).await {
+                    Ok(listener) => listener,
+                    Err(err) => {
+                        self.inbound
+                            .reply_connect_status(&mut in_proto, ConnectStatus::GeneralFailure)
+                            .await?;
+                        return Err(err);
+                    }
+                };
+                let bind_addr: Address = listener.local_addr()?.into();
+
+                self.outbound.set_protocol_name(&out_proto.get_name());
+
+                self.inbound
+                    .reply_connect_status(&mut in_proto, ConnectStatus::Succeeded)
+                    .await?;
+
+                // 1. reply the address we are listening at
+                let buf = in_proto.server_encode_buf(bind_addr.as_bytes())?;
+                self.inbound.send(buf).await?;
+
+                // 2. reply the address of incoming connection
+                let peer_addr: Address = self.outbound.accept(listener, &resolved.address).await?.into();
+                let buf = in_proto.server_encode_buf(peer_addr.as_bytes())?;
+                self.inbound.send(buf).await?;
+            }
+        }
+
+        self.relay(in_proto, out_proto, tx, rx).await
+    }
+
+    async fn handle_client_bind(&mut self, resolved: ResolvedResult) -> Result<DynProtocol> {
+        let mut out_proto: DynProtocol = Box::<Direct>::default();
+
+        let via_server = self.opts.client_opts().server_bind.is_some() && self.check_acl(&resolved.address);
+
+        if via_server {
+            // request bp server to listen by CMD in header, the expected address is kept as DST.ADDR
+            self.outbound.set_socket_type(self.get_outbound_socket_type(&resolved));
+            self.outbound.set_mux(self.is_mux());
+
+            out_proto = self.create_outbound_protocol(&resolved);
+            out_proto.set_resolved_result(resolved.clone());
+
+            self.outbound.set_protocol_name(&out_proto.get_name());
+            self.outbound.start_connect(&resolved).await?;
+
+            // send header only, and wait for bp server listening
+            if self.outbound.recv_connect_status(&mut out_proto).await.is_err() {
+                return Err(ConnectStatusError(self.outbound.connect_status()).into());
+            }
+
+            // 1. reply the address bp server is listening at
+            let bind_addr = self.outbound.recv_address(&mut out_proto).await?;
+            let reply = Socks::build_reply(ConnectStatus::Succeeded, Some(&bind_addr));
+            self.inbound.send(reply).await?;
+
+            // 2. reply the address of incoming connection accepted by bp server
+            let peer_addr = self.outbound.recv_address(&mut out_proto).await?;
+            let reply = Socks::build_reply(ConnectStatus::Succeeded, Some(&peer_addr));
+            self.inbound.send(reply).await?;
+        } else {
+            let listener = self.outbound.listen(self.get_bind_ip().await?).await?;
+            let bind_addr = listener.local_addr()?;
+
+            self.outbound.set_protocol_name(&out_proto.get_name());
+
+            // 1. reply the address we are listening at
+            let reply = Socks::build_reply(ConnectStatus::Succeeded, Some(&bind_addr.into()));
+            self.inbound.send(reply).await?;
+
+            // 2. reply the address of incoming connection
+            let peer_addr = self.outbound.accept(listener, &resolved.address).await?;
+            let reply = Socks::build_reply(ConnectStatus::Succeeded, Some(&peer_addr.into()));
+            self.inbound.send(reply).await?;
+        }
+
+        Ok(out_proto)
+    }
+
+    async fn relay(
+        &mut self,
+        in_proto: DynProtocol,
+        out_proto: DynProtocol,
+        tx: Sender<Event>,
+        rx: Receiver<Event>,
+    ) -> Result<()> {
+        self.inbound
+            .handle_incoming_data(in_proto.clone(), out_proto.clone(), tx.clone());
+        self.outbound.handle_incoming_data(in_proto, out_proto, tx);
+        self.handle_events(rx).await
+    }
+
+    /// The local ip of incoming connection, fallback to --bind
+    async fn get_bind_ip(&self) -> Result<IpAddr> {
+        match self.inbound.local_addr() {
+            Some(addr) => Ok(addr.ip()),
+            None => Ok(self.opts.bind().resolve().await?.ip()),
+        }
+    }
+
     fn get_outbound_socket_type(&self, resolved: &ResolvedResult) -> SocketType {
         if self.opts.is_server() {
             // server side resolved DNS protocol, outbound should be UDP
@@ -133,10 +534,21 @@ impl Connection {
         }
 
         if self.opts.is_client() {
-            if matches!(self.inbound.socket_type(), SocketType::Udp) && !self.opts.client_opts().udp_over_tcp {
-                // inbound is UDP, but not enable --udp-over-tcp, outbound should be UDP as well
+            let client_opts = self.opts.client_opts();
+
+            if matches!(self.inbound.socket_type(), SocketType::Udp)
+                && !client_opts.udp_over_tcp
+                && !client_opts.quic_datagram
+            {
+                // inbound is UDP, but not enable --udp-over-tcp or --quic-datagram, outbound should be UDP as well
                 return SocketType::Udp;
             }
+            // client side set --transport, outbound should be WebSocket or HTTP/2 stream
+            match self.opts.transport() {
+                Some(Transport::WebSocket) => return SocketType::Ws,
+                Some(Transport::H2) => return SocketType::H2,
+                None => {}
+            }
             // client side enable --tls, outbound should be TLS
             if self.opts.tls() {
                 return SocketType::Tls;
@@ -151,12 +563,34 @@ impl Connection {
         SocketType::Tcp
     }
 
-    async fn check_resolved_result(&self, resolved: &ResolvedResult) -> Result<()> {
+    /// Whether bp client opens a stream on a shared mux session rather than a new connection to bp server,
+    /// udp over tcp is excluded since plain protocol cannot keep boundaries of packets
+    fn is_mux(&self) -> bool {
+        self.opts.is_client() && self.opts.client_opts().mux && !matches!(self.inbound.socket_type(), SocketType::Udp)
+    }
+
+    /// Whether bp client relays UDP packets by datagrams of a shared QUIC connection to bp server
+    fn is_quic_datagram(&self) -> bool {
+        self.opts.is_client()
+            && self.opts.client_opts().quic_datagram
+            && matches!(self.inbound.socket_type(), SocketType::Udp)
+    }
+
+    async fn check_resolved_result(&self, resolved: &ResolvedResult) -> Result<(), (ConnectStatus, Error)> {
         // we must drop connection to bp itself, because:
         // connect to bp itself will cause listener.accept() run into infinite loop and
         // produce "No file descriptors available" errors.
-        let resolved_addr = resolved.address.resolve().await?;
-        let bind_addr = self.opts.bind().resolve().await?;
+        let resolved_addr = resolved
+            .address
+            .resolve()
+            .await
+            .map_err(|err| (ConnectStatus::HostUnreachable, err))?;
+        let bind_addr = self
+            .opts
+            .bind()
+            .resolve()
+            .await
+            .map_err(|err| (ConnectStatus::GeneralFailure, err))?;
 
         if resolved_addr == bind_addr {
             let msg = format!(
@@ -165,7 +599,7 @@ impl Connection {
                 self.inbound.socket_type()
             );
             log::error!("{}", msg);
-            return Err(anyhow::Error::msg(msg));
+            return Err((ConnectStatus::NotAllowed, Error::msg(msg)));
         }
 
         Ok(())
@@ -192,9 +626,60 @@ impl Connection {
         rule.is_allow()
     }
 
+    /// Create outbound protocol according to acl, returns None if this connection should be closed
+    fn prepare_outbound(&mut self, resolved: &ResolvedResult) -> Option<DynProtocol> {
+        let mut out_proto: DynProtocol;
+
+        // check acl, the fallback address is configured along with acl so it's always allowed
+        if self.inbound.is_fallback() || self.check_acl(&resolved.address) {
+            self.outbound.set_socket_type(self.get_outbound_socket_type(resolved));
+            self.outbound.set_mux(self.is_mux());
+            self.outbound.set_quic_datagram(self.is_quic_datagram());
+            out_proto = self.create_outbound_protocol(resolved);
+        } else {
+            let will = match self.opts.service_type() {
+                ServiceType::Client => "not proxy to bp server",
+                ServiceType::Server => "close this connection",
+            };
+
+            log::warn!(
+                "[{}] [{}] {} is DENY by acl, will {}",
+                self.peer_addr,
+                self.inbound.socket_type(),
+                resolved.address,
+                will,
+            );
+
+            match self.opts.service_type() {
+                ServiceType::Client => {
+                    // change outbound protocol to TCP
+                    // TODO: close connection on client side?
+                    self.outbound.set_socket_type(SocketType::Tcp);
+                    self.outbound.set_allow_proxy(false);
+                    out_proto = Box::<Direct>::default();
+                }
+                ServiceType::Server => {
+                    // close connection
+                    return None;
+                }
+            }
+        }
+
+        self.outbound.set_protocol_name(&out_proto.get_name());
+
+        // sync resolve result to outbound protocol
+        out_proto.set_resolved_result(resolved.clone());
+
+        Some(out_proto)
+    }
+
     fn create_outbound_protocol(&self, resolved: &ResolvedResult) -> DynProtocol {
         // bp client should always use bp transport connect to bp server
         if self.opts.is_client() && self.opts.client_opts().server_bind.is_some() {
+            // streams of a mux session are encrypted by the session already
+            if self.outbound.is_mux() {
+                return Box::<Plain>::default();
+            }
             return init_protocol(self.opts.encryption(), self.opts.key(), self.opts.service_type());
         }
 
@@ -243,14 +728,19 @@ impl Connection {
                     self.outbound.send(buf).await?;
                 }
                 Event::ServerEncodeDone(buf) => {
+                    self.consume_user_traffic(buf.len()).await?;
                     self.inbound.send(buf).await?;
                 }
                 Event::ClientDecodeDone(buf) => {
                     self.inbound.send(buf).await?;
                 }
                 Event::ServerDecodeDone(buf) => {
+                    self.consume_user_traffic(buf.len()).await?;
                     self.outbound.send(buf).await?;
                 }
+                Event::HttpRequestDone(_) => {
+                    unreachable!("plain HTTP requests are handled by handle_http()");
+                }
                 Event::InboundError(_) | Event::OutboundError(_) => {
                     self.close().await?;
                     break;
@@ -260,4 +750,16 @@ impl Connection {
 
         Ok(())
     }
+
+    /// Count traffic relayed for the user on server side, wait if the bandwidth is exceeded
+    async fn consume_user_traffic(&self, n: usize) -> Result<()> {
+        if let Some(guard) = &self.user_guard {
+            guard.consume(n).await.map_err(|err| {
+                log::warn!("[{}] [{}] {}", self.peer_addr, self.inbound.socket_type(), err);
+                err
+            })?;
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,80 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{Error, Result};
+use bytes::Bytes;
+use h2::{client::SendRequest, RecvStream, SendStream};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_tungstenite::tungstenite::http::{Request, StatusCode};
+
+/// A HTTP/2 connection to bp server, each proxied connection is carried by a stream of it
+///
+/// # Explain
+///
+/// * bp client opens a stream by a POST request to --transport-path, data is sent in the request body.
+/// * bp server responds 200 to accept the stream, data sent back is in the response body, requests to other paths
+///   or with other methods are responded 404.
+/// * Either side ends its body to close the stream, flow control is done by HTTP/2 itself.
+#[derive(Clone)]
+pub struct H2Connection {
+    send_request: SendRequest<Bytes>,
+    peer_addr: SocketAddr,
+    is_closed: Arc<AtomicBool>,
+}
+
+impl H2Connection {
+    /// Start HTTP/2 on a TLS connection, the connection is driven in background until it's lost
+    pub async fn handshake(tls_stream: TlsStream<TcpStream>) -> Result<Self> {
+        let peer_addr = tls_stream.get_ref().0.peer_addr()?;
+        let (send_request, connection) = h2::client::handshake(tls_stream).await?;
+
+        let is_closed = Arc::new(AtomicBool::new(false));
+        let is_closed_copy = is_closed.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                log::warn!("h2 connection to {} is lost due to: {}", peer_addr, err);
+            }
+            is_closed_copy.store(true, Ordering::Relaxed);
+        });
+
+        Ok(Self {
+            send_request,
+            peer_addr,
+            is_closed,
+        })
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.is_closed.load(Ordering::Relaxed)
+    }
+
+    /// Open a stream by a POST request to the uri, the stream is ready once bp server responds 200
+    pub async fn open_stream(&self, uri: &str) -> Result<(SendStream<Bytes>, RecvStream)> {
+        let mut send_request = self.send_request.clone().ready().await?;
+
+        let request = Request::post(uri).body(())?;
+        let (response, send_stream) = send_request.send_request(request, false)?;
+
+        let response = response.await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::msg(format!(
+                "h2 stream is rejected with status {}",
+                response.status()
+            )));
+        }
+
+        Ok((send_stream, response.into_body()))
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     net::SocketAddr,
+    str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -8,6 +9,7 @@ use std::{
 
 use anyhow::{Error, Result};
 use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serialize};
 use tokio::{
     sync::mpsc::Sender,
     time::{timeout, Duration},
@@ -17,16 +19,91 @@ use super::socket::SocketType;
 use crate::{
     constants,
     event::*,
-    net::{address::Address, socket::Socket},
+    global,
+    net::{address::Address, outbound::ConnectStatus, socket::Socket},
     protos::*,
+    utils::crypto::Crypto,
     Options, ServiceType, Shutdown,
 };
 
+/// What bp server does when an incoming connection fails to handshake, closing it immediately can be fingerprinted
+/// by active probers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeFailurePolicy {
+    /// Close the connection immediately
+    Close,
+    /// Read and discard data until a random number of bytes are received or timeout, then close the connection
+    Drain,
+    /// Relay the connection to the fallback address as is, including data already received, e.g, a web server
+    Fallback(Address),
+}
+
+impl Default for HandshakeFailurePolicy {
+    fn default() -> Self {
+        Self::Close
+    }
+}
+
+impl FromStr for HandshakeFailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("fallback:") {
+            let addr = addr
+                .parse()
+                .map_err(|err| format!("cannot parse fallback address {} due to: {}", addr, err))?;
+            return Ok(Self::Fallback(addr));
+        }
+
+        match s {
+            "close" => Ok(Self::Close),
+            "drain" => Ok(Self::Drain),
+            _ => Err(format!(
+                "{} is not supported, available policies are: close, drain, fallback:<host:port>",
+                s
+            )),
+        }
+    }
+}
+
+impl ToString for HandshakeFailurePolicy {
+    fn to_string(&self) -> String {
+        match self {
+            Self::Close => "close".to_string(),
+            Self::Drain => "drain".to_string(),
+            Self::Fallback(addr) => format!("fallback:{}", addr.as_string()),
+        }
+    }
+}
+
+impl Serialize for HandshakeFailurePolicy {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for HandshakeFailurePolicy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 pub struct Inbound {
     opts: Options,
     socket: Arc<Socket>,
     peer_address: SocketAddr,
     protocol_name: Option<String>,
+    user: Option<String>,
+    is_fallback: bool,
+    udp_reply_header: Option<Bytes>,
+    udp_association: Option<Shutdown>,
     is_closed: Arc<AtomicBool>,
     shutdown: Shutdown,
 }
@@ -41,6 +118,10 @@ impl Inbound {
             socket,
             peer_address,
             protocol_name: None,
+            user: None,
+            is_fallback: false,
+            udp_reply_header: None,
+            udp_association: None,
             is_closed: Arc::new(AtomicBool::new(false)),
             shutdown,
         }
@@ -50,9 +131,40 @@ impl Inbound {
         self.socket.socket_type()
     }
 
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn socket(&self) -> Arc<Socket> {
+        self.socket.clone()
+    }
+
+    /// The user identified by key on server side
+    pub fn user(&self) -> Option<String> {
+        self.user.clone()
+    }
+
+    pub fn set_user(&mut self, user: Option<String>) {
+        self.user = user;
+    }
+
+    /// Whether relayed to the fallback address of --handshake-failure, which is chosen by bp server rather than peer
+    pub fn is_fallback(&self) -> bool {
+        self.is_fallback
+    }
+
     pub async fn resolve(&mut self) -> Result<DynProtocol> {
         let res = self.try_resolve().await?;
         self.socket.disable_restore();
+
+        // datagrams relayed by socks5 UDP ASSOCIATE should be sent back with the socks5 UDP header
+        let resolved = res.get_resolved_result();
+
+        if self.socket.is_udp() && matches!(resolved.protocol, ProtocolType::Socks) {
+            self.udp_reply_header = Some(Socks::udp_header(&resolved.address));
+            self.udp_association = global::get_socks_udp_association(self.peer_address);
+        }
+
         Ok(res)
     }
 
@@ -64,6 +176,7 @@ impl Inbound {
                 protocol: ProtocolType::Direct,
                 address: addr.clone(),
                 pending_buf: None,
+                client_hello: None,
             });
 
             direct
@@ -89,15 +202,32 @@ impl Inbound {
             #[cfg(not(target_os = "linux"))]
             let redirect_dest_addr: Option<Address> = None;
 
-            let mut try_list: Vec<DynProtocol> = vec![
-                Box::new(Socks::new(Some(self.opts.bind()))),
-                Box::new(Http::new(self.opts.client_opts().with_basic_auth)),
-                Box::<Https>::default()
-            ];
+            let socks_auth = self.opts.client_opts().socks_auth;
+            let http = Box::new(Http::new(self.opts.client_opts().with_basic_auth));
 
-            if self.socket.is_udp() {
-                try_list.push(Box::new(Dns::new(self.opts.dns_server())));
-            }
+            let try_list: Vec<DynProtocol> = match self.socket.socket_type() {
+                // only HTTP Proxy is served by --https-bind
+                SocketType::Tls => vec![http],
+                _ => {
+                    let mut try_list: Vec<DynProtocol> =
+                        vec![Box::new(Socks::new(Some(self.opts.bind()), socks_auth.clone()))];
+
+                    // socks4 has no way to authenticate, disable it when --socks-auth is set
+                    if socks_auth.is_none() {
+                        try_list.push(Box::<Socks4>::default());
+                    }
+
+                    try_list.push(http);
+                    try_list.push(Box::<Https>::default());
+
+                    if self.socket.is_udp() {
+                        try_list.push(Box::<Quic>::default());
+                        try_list.push(Box::new(Dns::new(self.opts.dns_server())));
+                    }
+
+                    try_list
+                }
+            };
 
             // check one by one
             for mut proto in try_list {
@@ -129,11 +259,32 @@ impl Inbound {
 
         // server side resolve
         if self.opts.is_server() {
-            let mut proto = init_protocol(self.opts.encryption(), self.opts.key(), self.opts.service_type());
-            self.resolve_dest_addr(&mut proto, false).await?;
+            let mut proto = init_server_protocol(&self.opts.server_opts());
+
+            if let Err(err) = self.resolve_dest_addr(&mut proto, false).await {
+                let addr = self.handle_handshake_failure(err).await?;
+                self.is_fallback = true;
+                return Ok(direct(&addr));
+            }
+
+            // streams of a mux session belong to the user identified by the session
+            if let Some(user) = proto.get_user() {
+                log::info!(
+                    "[{}] [{}] identified as user {}",
+                    self.peer_address,
+                    self.socket.socket_type(),
+                    user
+                );
+                self.user = Some(user);
+            }
 
             let resolved = proto.get_resolved_result().clone();
 
+            // mux session is requested by command
+            if matches!(resolved.protocol, ProtocolType::Mux) {
+                return Ok(proto);
+            }
+
             // check dns packet
             if let Some(buf) = resolved.pending_buf {
                 if Dns::check_dns_query(&buf[..]) {
@@ -142,6 +293,7 @@ impl Inbound {
                         address: self.opts.dns_server(),
                         protocol: ProtocolType::Dns,
                         pending_buf: Some(buf),
+                        client_hello: None,
                     });
                 }
             }
@@ -152,6 +304,60 @@ impl Inbound {
         Err(Error::msg("cannot find a protocol to parse incoming data"))
     }
 
+    /// Apply --handshake-failure on server side, return the fallback address to relay the connection to
+    async fn handle_handshake_failure(&self, err: Error) -> Result<Address> {
+        // udp packets are not bound to a connection, and reading more may steal packets of others
+        if self.socket.is_udp() {
+            return Err(err);
+        }
+
+        match self.opts.server_opts().handshake_failure {
+            HandshakeFailurePolicy::Close => Err(err),
+            HandshakeFailurePolicy::Drain => {
+                let limit = Crypto::random_u32() as usize % constants::HANDSHAKE_FAILURE_DRAIN_MAX_BYTES + 1;
+
+                log::warn!(
+                    "[{}] [{}] handshake failed, draining up to {} bytes before close",
+                    self.peer_address,
+                    self.socket.socket_type(),
+                    limit
+                );
+
+                let drain = async {
+                    let mut len = 0;
+
+                    while len < limit {
+                        len += self.socket.read_some().await?.len();
+                    }
+
+                    Ok::<_, Error>(())
+                };
+
+                let _ = timeout(
+                    Duration::from_secs(constants::HANDSHAKE_FAILURE_DRAIN_TIMEOUT_SECONDS),
+                    drain,
+                )
+                .await;
+
+                Err(err)
+            }
+            HandshakeFailurePolicy::Fallback(addr) => {
+                log::warn!(
+                    "[{}] [{}] handshake failed, relay to fallback address {}",
+                    self.peer_address,
+                    self.socket.socket_type(),
+                    addr
+                );
+
+                Ok(addr)
+            }
+        }
+    }
+
+    pub async fn reply_connect_status(&self, proto: &mut DynProtocol, status: ConnectStatus) -> Result<()> {
+        proto.reply_connect_status(&self.socket, status).await
+    }
+
     pub fn set_protocol_name(&mut self, name: String) {
         self.protocol_name = Some(name);
     }
@@ -223,8 +429,101 @@ impl Inbound {
         });
     }
 
+    /// Read plain HTTP requests one by one, see Http::read_request()
+    pub fn handle_http_requests(&self, mut http: Http, tx: Sender<Event>) {
+        let socket = self.socket.clone();
+        let is_closed = self.is_closed.clone();
+        let shutdown = self.shutdown.clone();
+
+        tokio::spawn(async move {
+            loop {
+                if is_closed.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let res = tokio::select! {
+                    v = http.read_request(&socket) => v,
+                    _ = shutdown.recv() => break,
+                };
+
+                if let Err(err) = res {
+                    let _ = tx.send(Event::InboundError(err)).await;
+                    break;
+                }
+
+                if tx.send(Event::HttpRequestDone(res.unwrap())).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Keep the socks5 UDP association alive until the control connection is closed
+    ///
+    /// DST.ADDR and DST.PORT of UDP ASSOCIATE are where the client is going to send datagrams from, all-zero fields
+    /// mean the client doesn't know them yet, so the ip of the control connection and any port are accepted.
+    pub async fn hold_udp_association(&mut self, client_addr: &Address) -> Result<()> {
+        let ip = Some(client_addr)
+            .filter(|addr| addr.is_ip())
+            .map(|addr| addr.as_socket_addr().ip())
+            .filter(|ip| !ip.is_unspecified())
+            .unwrap_or_else(|| self.peer_address.ip());
+        let client_addr = SocketAddr::new(ip, client_addr.port());
+
+        global::socks_udp_associate(client_addr);
+
+        log::info!(
+            "[{}] [{}] udp association established for {}, waiting for the connection to close",
+            self.peer_address,
+            self.socket.socket_type(),
+            client_addr,
+        );
+
+        loop {
+            let res = tokio::select! {
+                v = self.socket.read_some() => v,
+                _ = self.shutdown.recv() => break,
+            };
+
+            // any data on the control connection is meaningless, discard it
+            if res.is_err() {
+                break;
+            }
+        }
+
+        global::socks_udp_dissociate(client_addr);
+
+        log::info!(
+            "[{}] [{}] udp association terminated",
+            self.peer_address,
+            self.socket.socket_type(),
+        );
+
+        self.close().await
+    }
+
+    /// Stop relaying once the socks5 UDP association of this datagram is terminated
+    pub fn watch_udp_association(&self, tx: Sender<Event>) {
+        let association = match self.udp_association.clone() {
+            Some(association) => association,
+            None => return,
+        };
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = association.recv() => {
+                    let _ = tx.send(Event::InboundError(Error::msg("udp association terminated"))).await;
+                },
+                _ = tx.closed() => (),
+            }
+        });
+    }
+
     pub async fn send(&self, buf: bytes::Bytes) -> tokio::io::Result<()> {
-        self.socket.send(&buf).await
+        match &self.udp_reply_header {
+            Some(header) => self.socket.send(&[&header[..], &buf[..]].concat()).await,
+            None => self.socket.send(&buf).await,
+        }
     }
 
     pub async fn close(&mut self) -> Result<()> {
@@ -261,7 +560,8 @@ impl Inbound {
             future,
         );
 
-        match result.await? {
+        // data read before timeout should be restored as well
+        match result.await.unwrap_or_else(|elapsed| Err(elapsed.into())) {
             Ok(resolved) => {
                 log::info!(
                     "[{}] [{}] [{}] successfully resolved {}",
@@ -271,8 +571,11 @@ impl Inbound {
                     resolved.address,
                 );
 
-                // http & https request should restore buffer
-                if matches!(resolved.protocol, ProtocolType::Http | ProtocolType::Https) {
+                // http & https & quic request should restore buffer
+                if matches!(
+                    resolved.protocol,
+                    ProtocolType::Http | ProtocolType::Https | ProtocolType::Quic
+                ) {
                     socket.restore();
                 }
 
@@ -297,7 +600,8 @@ impl Inbound {
 
         use crate::net::linux::get_original_destination_addr;
 
-        if self.socket.is_udp() {
+        // only plain tcp connections can be redirected
+        if !matches!(self.socket.socket_type(), SocketType::Tcp) {
             return None;
         }
 
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Error, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{constants, global, utils::token_bucket::TokenBucket, Shutdown};
+
+pub fn get_user_limiter() -> Arc<UserLimiter> {
+    global::get_user_limiter()
+}
+
+/// Limits applied to each user individually
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserLimits {
+    /// Traffic of both directions allowed in a calendar month(UTC), in bytes
+    pub monthly_quota: Option<u64>,
+    /// Concurrent connections allowed
+    pub max_connections: Option<usize>,
+    /// Traffic of both directions allowed per second, in bytes
+    pub bandwidth: Option<u64>,
+}
+
+/// Traffic used by a user in a month, persisted across restarts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserUsage {
+    /// e.g, "2022-10"
+    pub month: String,
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct UserState {
+    usage: UserUsage,
+    connections: usize,
+    bucket: Option<TokenBucket>,
+}
+
+impl UserState {
+    /// Usage of last month is no longer counted
+    fn usage_of(&mut self, month: &str) -> &mut UserUsage {
+        if self.usage.month != month {
+            self.usage = UserUsage {
+                month: month.to_string(),
+                bytes: 0,
+            };
+        }
+        &mut self.usage
+    }
+}
+
+/// Enforce UserLimits on connections of identified users
+#[derive(Default)]
+pub struct UserLimiter {
+    limits: Mutex<HashMap<String, UserLimits>>,
+    users: Mutex<HashMap<String, UserState>>,
+}
+
+impl UserLimiter {
+    /// Set limits keyed by user name, users not in the map are not limited
+    pub fn set_limits(&self, limits: HashMap<String, UserLimits>) {
+        *self.limits.lock() = limits;
+    }
+
+    fn limits_of(&self, user: &str) -> UserLimits {
+        self.limits.lock().get(user).copied().unwrap_or_default()
+    }
+
+    /// Start a connection of the user, the connection should be ended by release() once closed
+    pub fn acquire(&self, user: &str) -> Result<()> {
+        let limits = self.limits_of(user);
+        let mut users = self.users.lock();
+        let state = users.entry(user.to_string()).or_default();
+
+        if let Some(max_connections) = limits.max_connections {
+            if state.connections >= max_connections {
+                return Err(Error::msg(format!(
+                    "user {} reached the max connections {}",
+                    user, max_connections
+                )));
+            }
+        }
+
+        if let Some(quota) = limits.monthly_quota {
+            if state.usage_of(&current_month()).bytes >= quota {
+                return Err(Error::msg(format!("user {} used up the monthly quota {}", user, quota)));
+            }
+        }
+
+        state.connections += 1;
+
+        Ok(())
+    }
+
+    pub fn release(&self, user: &str) {
+        if let Some(state) = self.users.lock().get_mut(user) {
+            state.connections = state.connections.saturating_sub(1);
+        }
+    }
+
+    /// Count n bytes relayed for the user, wait until the bandwidth allows
+    pub async fn consume(&self, user: &str, n: usize) -> Result<()> {
+        let limits = self.limits_of(user);
+
+        let wait = {
+            let mut users = self.users.lock();
+            let state = users.entry(user.to_string()).or_default();
+
+            let usage = state.usage_of(&current_month());
+            usage.bytes += n as u64;
+
+            if let Some(quota) = limits.monthly_quota {
+                if usage.bytes > quota {
+                    return Err(Error::msg(format!("user {} used up the monthly quota {}", user, quota)));
+                }
+            }
+
+            // a bucket is shared by all connections of the user
+            match limits.bandwidth {
+                Some(rate) => state.bucket.get_or_insert_with(|| TokenBucket::new(rate)).take(n),
+                None => Duration::ZERO,
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        Ok(())
+    }
+
+    /// Current usage of the user in this month
+    pub fn usage(&self, user: &str) -> u64 {
+        self.users
+            .lock()
+            .get_mut(user)
+            .map(|state| state.usage_of(&current_month()).bytes)
+            .unwrap_or_default()
+    }
+
+    pub fn load_from_file(&self, path: &str) -> Result<()> {
+        if !Path::new(path).exists() {
+            return Ok(());
+        }
+
+        log::info!("loading usage of users from {}", path);
+
+        let content = fs::read_to_string(path)?;
+        let usages: HashMap<String, UserUsage> = serde_json::from_str(&content)?;
+
+        let mut users = self.users.lock();
+
+        for (user, usage) in usages {
+            users.entry(user).or_default().usage = usage;
+        }
+
+        Ok(())
+    }
+
+    pub fn save_to_file(&self, path: &str) -> Result<()> {
+        let usages: HashMap<String, UserUsage> = self
+            .users
+            .lock()
+            .iter()
+            .map(|(user, state)| (user.clone(), state.usage.clone()))
+            .collect();
+
+        // write to a temporary file first, in case of being interrupted
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, serde_json::to_string_pretty(&usages)?)?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Save usage of users to the file periodically, and once more before shutdown
+    pub async fn persist(&self, path: &str, shutdown: Shutdown) {
+        let mut interval = tokio::time::interval(Duration::from_secs(constants::USER_USAGE_SAVE_INTERVAL_SECONDS));
+
+        loop {
+            let is_shutdown = tokio::select! {
+                _ = interval.tick() => false,
+                _ = shutdown.recv() => true,
+            };
+
+            if let Err(err) = self.save_to_file(path) {
+                log::error!("cannot save usage of users to {} due to: {}", path, err);
+            }
+
+            if is_shutdown {
+                break;
+            }
+        }
+    }
+}
+
+/// A connection of the user, which is ended when dropped
+pub struct UserGuard {
+    user: String,
+}
+
+impl UserGuard {
+    pub fn acquire(user: &str) -> Result<Self> {
+        get_user_limiter().acquire(user)?;
+
+        Ok(Self { user: user.to_string() })
+    }
+
+    pub async fn consume(&self, n: usize) -> Result<()> {
+        get_user_limiter().consume(&self.user, n).await
+    }
+}
+
+impl Drop for UserGuard {
+    fn drop(&mut self) {
+        get_user_limiter().release(&self.user);
+    }
+}
+
+/// The calendar month in UTC, e.g, "2022-10"
+fn current_month() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let (year, month) = year_month_from_days((secs / 86400) as i64);
+
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Convert days since 1970-01-01 to the year and month in proleptic Gregorian calendar, see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn year_month_from_days(days: i64) -> (i64, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month)
+}
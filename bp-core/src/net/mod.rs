@@ -1,10 +1,15 @@
 pub mod address;
 pub mod connection;
 pub mod dns;
+pub mod h2;
 pub mod inbound;
+pub mod limiter;
 #[cfg(target_os = "linux")]
 pub mod linux;
+pub mod mux;
 pub mod outbound;
 pub mod quic;
 pub mod socket;
 pub mod tls;
+pub mod transport;
+pub mod upstream;
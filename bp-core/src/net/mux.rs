@@ -0,0 +1,585 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use anyhow::{Error, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use parking_lot::Mutex;
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::{
+        mpsc::{channel, error::TrySendError, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        OnceCell, Semaphore,
+    },
+    time::{self, Duration},
+};
+
+use crate::{constants, net::socket::Socket, protos::DynProtocol, ServiceType, Shutdown};
+
+const FRAME_HEADER_SIZE: usize = 7;
+
+const FRAME_OPEN: u8 = 0x01;
+const FRAME_DATA: u8 = 0x02;
+const FRAME_CLOSE: u8 = 0x03;
+const FRAME_WINDOW: u8 = 0x04;
+
+/// # Frame
+///
+/// +------+-----------+--------+----------+
+/// | Type | Stream ID | Length |   Data   |
+/// +------+-----------+--------+----------+
+/// |  1   |     4     |   2    | Variable |
+/// +------+-----------+--------+----------+
+///
+/// # Explain
+///
+/// * OPEN(0x01) is sent by client to open a stream, the stream carries plain protocol, so the dest address is at
+///   the beginning of the first DATA.
+/// * DATA(0x02) carries data of a stream, the length of Data must <= MUX_MAX_FRAME_SIZE, and must not exceed the
+///   window of the stream.
+/// * CLOSE(0x03) closes a stream in both directions, data sent before it is delivered.
+/// * WINDOW(0x04) allows peer to send more bytes of a stream, Data is the increment in u32, each stream starts
+///   with MUX_WINDOW_SIZE bytes, and the window never exceeds it.
+/// * Frames of any length > MUX_MAX_FRAME_SIZE, or violating the window, break the session.
+#[derive(Debug)]
+struct Frame {
+    kind: u8,
+    stream_id: u32,
+    data: Bytes,
+}
+
+impl Frame {
+    fn new(kind: u8, stream_id: u32, data: Bytes) -> Self {
+        Self { kind, stream_id, data }
+    }
+
+    fn encode_to(&self, buf: &mut BytesMut) {
+        buf.put_u8(self.kind);
+        buf.put_u32(self.stream_id);
+        buf.put_u16(self.data.len() as u16);
+        buf.put_slice(&self.data);
+    }
+
+    /// Take a complete frame from the beginning of buffer, return None if more data is required
+    fn parse(buf: &mut BytesMut) -> Result<Option<Self>> {
+        if buf.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let len = u16::from_be_bytes([buf[5], buf[6]]) as usize;
+
+        if len > constants::MUX_MAX_FRAME_SIZE {
+            return Err(Error::msg(format!("mux frame length {} is too large", len)));
+        }
+
+        if buf.len() < FRAME_HEADER_SIZE + len {
+            return Ok(None);
+        }
+
+        let kind = buf.get_u8();
+        let stream_id = buf.get_u32();
+        buf.advance(2);
+
+        Ok(Some(Self::new(kind, stream_id, buf.split_to(len).freeze())))
+    }
+}
+
+struct StreamHandle {
+    /// Data received from peer, written to the stream in order
+    data_tx: Sender<Bytes>,
+    /// Bytes allowed to be received from peer
+    recv_window: usize,
+    /// Bytes allowed to be sent to peer
+    credits: Arc<Semaphore>,
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        // wake up the sending task waiting for credits
+        self.credits.close();
+    }
+}
+
+#[derive(Default)]
+struct Streams {
+    handles: HashMap<u32, StreamHandle>,
+    /// Since when the session has no streams
+    idle_since: Option<Instant>,
+}
+
+/// Mux sessions to a bp server, a new session is started by one connection while others wait for it
+#[derive(Default)]
+pub struct MuxSessions {
+    pub established: Vec<Arc<MuxSession>>,
+    pub starting: Option<Arc<OnceCell<Arc<MuxSession>>>>,
+}
+
+/// Many logical streams carried by one bp transport connection, each stream is exposed as a Socket
+pub struct MuxSession {
+    socket: Arc<Socket>,
+    service_type: ServiceType,
+    streams: Mutex<Streams>,
+    next_stream_id: AtomicU32,
+    frame_tx: UnboundedSender<Frame>,
+    incoming_tx: Mutex<Option<UnboundedSender<Socket>>>,
+    is_closed: AtomicBool,
+    shutdown: Shutdown,
+}
+
+impl MuxSession {
+    /// Start a session on a connection to bp server, the header of proto is sent along with the first frame
+    pub fn client(socket: Arc<Socket>, proto: DynProtocol) -> Arc<Self> {
+        let (session, _) = Self::new(socket, proto, ServiceType::Client, None);
+        session
+    }
+
+    /// Start a session on a connection from bp client, streams opened by client are received from the receiver
+    pub fn server(
+        socket: Arc<Socket>,
+        proto: DynProtocol,
+        pending_buf: Option<Bytes>,
+    ) -> (Arc<Self>, UnboundedReceiver<Socket>) {
+        Self::new(socket, proto, ServiceType::Server, pending_buf)
+    }
+
+    fn new(
+        socket: Arc<Socket>,
+        proto: DynProtocol,
+        service_type: ServiceType,
+        pending_buf: Option<Bytes>,
+    ) -> (Arc<Self>, UnboundedReceiver<Socket>) {
+        let (frame_tx, frame_rx) = unbounded_channel();
+        let (incoming_tx, incoming_rx) = unbounded_channel();
+
+        let session = Arc::new(Self {
+            socket,
+            service_type,
+            streams: Mutex::new(Streams {
+                idle_since: Some(Instant::now()),
+                ..Default::default()
+            }),
+            next_stream_id: AtomicU32::new(1),
+            frame_tx,
+            incoming_tx: Mutex::new(Some(incoming_tx)),
+            is_closed: AtomicBool::new(false),
+            shutdown: Shutdown::new(),
+        });
+
+        // bp client closes idle sessions first, otherwise a new stream may be opened on a session being closed
+        let idle_timeout = match service_type {
+            ServiceType::Client => constants::MUX_IDLE_TIMEOUT_SECONDS,
+            ServiceType::Server => constants::MUX_IDLE_TIMEOUT_SECONDS * 2,
+        };
+
+        session.clone().start_writing(proto.clone(), frame_rx);
+        session.clone().start_reading(proto, pending_buf);
+        session.clone().start_idle_check(Duration::from_secs(idle_timeout));
+
+        (session, incoming_rx)
+    }
+
+    /// Open a new stream to peer
+    pub fn open_stream(self: &Arc<Self>, peer_addr: SocketAddr) -> Result<Socket> {
+        let stream_id = self.next_stream_id.fetch_add(1, Ordering::Relaxed);
+        let socket = self.add_stream(stream_id, peer_addr)?;
+
+        self.send_frame(Frame::new(FRAME_OPEN, stream_id, Bytes::new()));
+
+        Ok(socket)
+    }
+
+    /// Whether more streams can be opened on this session
+    pub fn has_capacity(&self) -> bool {
+        !self.is_closed() && self.num_streams() < constants::MUX_MAX_STREAMS_PER_SESSION
+    }
+
+    pub fn num_streams(&self) -> usize {
+        self.streams.lock().handles.len()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.is_closed.load(Ordering::Relaxed)
+    }
+
+    /// Close the session along with all streams on it
+    pub async fn close(&self) {
+        if self.mark_closed(|_| true) {
+            self.close_transport().await;
+        }
+    }
+
+    /// Stop all tasks of the session and close the transport connection, after the session is marked closed
+    async fn close_transport(&self) {
+        self.incoming_tx.lock().take();
+        self.shutdown.broadcast();
+
+        let _ = self.socket.close().await;
+    }
+
+    /// Mark the session closed and drop all streams if the condition is met, new streams are never added after it
+    fn mark_closed<F: FnOnce(&Streams) -> bool>(&self, condition: F) -> bool {
+        let mut streams = self.streams.lock();
+
+        if self.is_closed() || !condition(&streams) {
+            return false;
+        }
+
+        self.is_closed.store(true, Ordering::Relaxed);
+        streams.handles.clear();
+
+        true
+    }
+
+    fn add_stream(self: &Arc<Self>, stream_id: u32, peer_addr: SocketAddr) -> Result<Socket> {
+        let (stream, local) = io::duplex(constants::MUX_STREAM_BUFFER_SIZE);
+        let (data_tx, data_rx) = channel(constants::MUX_STREAM_QUEUE_SIZE);
+        let credits = Arc::new(Semaphore::new(constants::MUX_WINDOW_SIZE));
+
+        {
+            let mut streams = self.streams.lock();
+
+            if self.is_closed() {
+                return Err(Error::msg("mux session is closed"));
+            }
+
+            let handle = StreamHandle {
+                data_tx,
+                recv_window: constants::MUX_WINDOW_SIZE,
+                credits: credits.clone(),
+            };
+
+            if streams.handles.insert(stream_id, handle).is_some() {
+                return Err(Error::msg(format!("mux stream {} is already opened", stream_id)));
+            }
+
+            streams.idle_since = None;
+        }
+
+        let (reader, writer) = io::split(local);
+
+        self.clone().start_sending(stream_id, reader, credits);
+        self.clone().start_receiving(stream_id, writer, data_rx);
+
+        Ok(Socket::from_mux(peer_addr, stream))
+    }
+
+    /// Return false if the stream is removed already
+    fn remove_stream(&self, stream_id: u32) -> bool {
+        let mut streams = self.streams.lock();
+        let removed = streams.handles.remove(&stream_id).is_some();
+
+        if removed && streams.handles.is_empty() {
+            streams.idle_since = Some(Instant::now());
+        }
+
+        removed
+    }
+
+    fn send_frame(&self, frame: Frame) {
+        // the session is closed if the writing task is gone
+        let _ = self.frame_tx.send(frame);
+    }
+
+    fn handle_frame(self: &Arc<Self>, frame: Frame) -> Result<()> {
+        let Frame { kind, stream_id, data } = frame;
+
+        match kind {
+            FRAME_OPEN => self.accept_stream(stream_id)?,
+            FRAME_DATA => self.receive_data(stream_id, data)?,
+            FRAME_CLOSE => {
+                self.remove_stream(stream_id);
+            }
+            FRAME_WINDOW => {
+                if data.len() != 4 {
+                    return Err(Error::msg(format!("invalid mux WINDOW frame length {}", data.len())));
+                }
+
+                let increment = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+
+                if let Some(handle) = self.streams.lock().handles.get(&stream_id) {
+                    // peer never gives back more than sent
+                    if handle.credits.available_permits() + increment as usize > constants::MUX_WINDOW_SIZE {
+                        return Err(Error::msg(format!(
+                            "mux stream {} window exceeds {} bytes",
+                            stream_id,
+                            constants::MUX_WINDOW_SIZE
+                        )));
+                    }
+
+                    handle.credits.add_permits(increment as usize);
+                }
+            }
+            _ => return Err(Error::msg(format!("unknown mux frame type {:#04x}", kind))),
+        }
+
+        Ok(())
+    }
+
+    /// Queue data for the stream within its window, the stream is reset if data is not written in time
+    fn receive_data(&self, stream_id: u32, data: Bytes) -> Result<()> {
+        {
+            let mut streams = self.streams.lock();
+
+            // data of a stream closed locally is discarded
+            let handle = match streams.handles.get_mut(&stream_id) {
+                Some(handle) => handle,
+                None => return Ok(()),
+            };
+
+            if data.len() > handle.recv_window {
+                return Err(Error::msg(format!(
+                    "mux stream {} receives {} bytes exceeding its window",
+                    stream_id,
+                    data.len()
+                )));
+            }
+
+            handle.recv_window -= data.len();
+
+            // the stream is gone if the receiving task is ended
+            match handle.data_tx.try_send(data) {
+                Ok(_) | Err(TrySendError::Closed(_)) => return Ok(()),
+                Err(TrySendError::Full(_)) => {}
+            }
+        }
+
+        log::warn!(
+            "[{}] [{}] mux stream {} has {} frames not written, reset",
+            self.socket.peer_addr(),
+            self.socket.socket_type(),
+            stream_id,
+            constants::MUX_STREAM_QUEUE_SIZE
+        );
+
+        if self.remove_stream(stream_id) {
+            self.send_frame(Frame::new(FRAME_CLOSE, stream_id, Bytes::new()));
+        }
+
+        Ok(())
+    }
+
+    fn accept_stream(self: &Arc<Self>, stream_id: u32) -> Result<()> {
+        if self.num_streams() >= constants::MUX_MAX_STREAMS_PER_SESSION {
+            log::warn!(
+                "[{}] [{}] mux session has {} streams already, stream {} is rejected",
+                self.socket.peer_addr(),
+                self.socket.socket_type(),
+                constants::MUX_MAX_STREAMS_PER_SESSION,
+                stream_id
+            );
+            self.send_frame(Frame::new(FRAME_CLOSE, stream_id, Bytes::new()));
+            return Ok(());
+        }
+
+        let socket = self.add_stream(stream_id, self.socket.peer_addr())?;
+
+        // the stream is closed by its sending task once dropped, e.g, bp client never accepts streams
+        if let Some(incoming_tx) = self.incoming_tx.lock().as_ref() {
+            let _ = incoming_tx.send(socket);
+        }
+
+        Ok(())
+    }
+
+    /// Relay data written to the stream to peer, as long as peer has room for it
+    fn start_sending(self: Arc<Self>, stream_id: u32, mut reader: ReadHalf<io::DuplexStream>, credits: Arc<Semaphore>) {
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; constants::MUX_MAX_FRAME_SIZE];
+
+            loop {
+                let n = match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+
+                match credits.acquire_many(n as u32).await {
+                    Ok(permits) => permits.forget(),
+                    // the stream is closed by peer
+                    Err(_) => break,
+                }
+
+                self.send_frame(Frame::new(FRAME_DATA, stream_id, Bytes::copy_from_slice(&buf[..n])));
+            }
+
+            if self.remove_stream(stream_id) {
+                self.send_frame(Frame::new(FRAME_CLOSE, stream_id, Bytes::new()));
+            }
+        });
+    }
+
+    /// Write data received from peer to the stream, and give credits back to peer
+    fn start_receiving(
+        self: Arc<Self>,
+        stream_id: u32,
+        mut writer: WriteHalf<io::DuplexStream>,
+        mut data_rx: Receiver<Bytes>,
+    ) {
+        tokio::spawn(async move {
+            while let Some(buf) = data_rx.recv().await {
+                if writer.write_all(&buf).await.is_err() {
+                    break;
+                }
+
+                // the window must be restored before peer knows it, data after the stream is closed needs no credits
+                match self.streams.lock().handles.get_mut(&stream_id) {
+                    Some(handle) => handle.recv_window += buf.len(),
+                    None => continue,
+                }
+
+                let increment = Bytes::copy_from_slice(&(buf.len() as u32).to_be_bytes());
+                self.send_frame(Frame::new(FRAME_WINDOW, stream_id, increment));
+            }
+
+            // the stream reads EOF after data received
+            let _ = writer.shutdown().await;
+        });
+    }
+
+    /// Encode frames by proto and write them to the transport connection
+    fn start_writing(self: Arc<Self>, mut proto: DynProtocol, mut frame_rx: UnboundedReceiver<Frame>) {
+        tokio::spawn(async move {
+            loop {
+                if self.is_closed() {
+                    break;
+                }
+
+                let frame = tokio::select! {
+                    frame = frame_rx.recv() => frame,
+                    _ = self.shutdown.recv() => None,
+                };
+
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => break,
+                };
+
+                // frames queued so far are written at once
+                let mut buf = BytesMut::with_capacity(FRAME_HEADER_SIZE + frame.data.len());
+                frame.encode_to(&mut buf);
+
+                while buf.len() < constants::MUX_MAX_FRAME_SIZE {
+                    match frame_rx.try_recv() {
+                        Ok(frame) => frame.encode_to(&mut buf),
+                        Err(_) => break,
+                    }
+                }
+
+                let res = match self.service_type {
+                    ServiceType::Client => proto.client_encode_buf(buf.freeze()),
+                    ServiceType::Server => proto.server_encode_buf(buf.freeze()),
+                };
+
+                let res = match res {
+                    Ok(buf) => self.socket.send(&buf).await.map_err(Error::from),
+                    Err(err) => Err(err),
+                };
+
+                if let Err(err) = res {
+                    log::error!(
+                        "[{}] [{}] mux session write failed due to: {}",
+                        self.socket.peer_addr(),
+                        self.socket.socket_type(),
+                        err
+                    );
+                    break;
+                }
+            }
+
+            self.close().await;
+        });
+    }
+
+    /// Read frames decoded by proto from the transport connection, pending_buf is data read along with the header
+    fn start_reading(self: Arc<Self>, mut proto: DynProtocol, pending_buf: Option<Bytes>) {
+        tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+
+            if let Some(pending_buf) = pending_buf {
+                buf.put(pending_buf);
+            }
+
+            loop {
+                loop {
+                    let res = match Frame::parse(&mut buf) {
+                        Ok(Some(frame)) => self.handle_frame(frame),
+                        Ok(None) => break,
+                        Err(err) => Err(err),
+                    };
+
+                    if let Err(err) = res {
+                        log::error!(
+                            "[{}] [{}] mux session is broken due to: {}",
+                            self.socket.peer_addr(),
+                            self.socket.socket_type(),
+                            err
+                        );
+                        self.close().await;
+                        return;
+                    }
+                }
+
+                if self.is_closed() {
+                    break;
+                }
+
+                let fut = match self.service_type {
+                    ServiceType::Client => proto.client_decode(&self.socket),
+                    ServiceType::Server => proto.server_decode(&self.socket),
+                };
+
+                let res = tokio::select! {
+                    res = fut => res,
+                    _ = self.shutdown.recv() => break,
+                };
+
+                match res {
+                    Ok(chunk) => buf.put(chunk),
+                    // closed by peer
+                    Err(_) => break,
+                }
+            }
+
+            self.close().await;
+        });
+    }
+
+    /// Close the session once it has no streams for idle_timeout
+    fn start_idle_check(self: Arc<Self>, idle_timeout: Duration) {
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = time::sleep(Duration::from_secs(1)) => {},
+                    _ = self.shutdown.recv() => break,
+                }
+
+                if self.is_closed() {
+                    break;
+                }
+
+                let is_idle = |streams: &Streams| {
+                    streams
+                        .idle_since
+                        .map(|since| since.elapsed() >= idle_timeout)
+                        .unwrap_or(false)
+                };
+
+                if self.mark_closed(is_idle) {
+                    log::info!(
+                        "[{}] [{}] mux session has no streams for {} seconds, closed",
+                        self.socket.peer_addr(),
+                        self.socket.socket_type(),
+                        idle_timeout.as_secs()
+                    );
+                    self.close_transport().await;
+                    break;
+                }
+            }
+        });
+    }
+}
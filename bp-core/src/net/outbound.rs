@@ -1,5 +1,7 @@
 use std::{
-    net::SocketAddr,
+    fmt::Display,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -10,22 +12,116 @@ use anyhow::{Error, Result};
 use bytes::Bytes;
 use rustls;
 use tokio::{
-    net::TcpSocket,
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpSocket, TcpStream},
     sync::mpsc::Sender,
-    time::{timeout, Duration},
+    time::{error::Elapsed, timeout, Duration},
 };
-use tokio_rustls::{TlsConnector, TlsStream};
+use tokio_rustls::{client, TlsConnector, TlsStream};
+use tokio_tungstenite::client_async;
 
 use super::socket::SocketType;
 use crate::{
     constants,
     event::Event,
     global::{self, get_tls_client_config},
-    net::{address::Address, dns::dns_resolve, quic::RandomEndpoint, socket::Socket},
-    protos::{DynProtocol, ResolvedResult},
+    net::{
+        address::{Address, Host},
+        dns::dns_resolve,
+        h2::H2Connection,
+        mux::MuxSession,
+        quic::{ConnectionPool, QuicDatagramSession, QuicStream},
+        socket::Socket,
+        upstream::UpstreamProxy,
+    },
+    protos::{init_protocol, DynProtocol, ProtocolType, ResolvedResult},
+    utils::proxy_protocol,
     Options, ServiceType, Shutdown,
 };
 
+/// The status of connecting to remote, some inbound protocols should reply it to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectStatus {
+    Succeeded,
+    GeneralFailure,
+    NotAllowed,
+    NetworkUnreachable,
+    HostUnreachable,
+    ConnectionRefused,
+    TimedOut,
+}
+
+impl ConnectStatus {
+    pub fn from_error(err: &Error) -> Self {
+        if let Some(ConnectStatusError(status)) = err.downcast_ref::<ConnectStatusError>() {
+            return *status;
+        }
+
+        if err.downcast_ref::<Elapsed>().is_some() {
+            return Self::TimedOut;
+        }
+
+        let err = match err.downcast_ref::<std::io::Error>() {
+            Some(err) => err,
+            None => return Self::GeneralFailure,
+        };
+
+        match err.kind() {
+            ErrorKind::ConnectionRefused => return Self::ConnectionRefused,
+            ErrorKind::TimedOut => return Self::TimedOut,
+            _ => {}
+        }
+
+        // ErrorKind::{NetworkUnreachable, HostUnreachable} are unstable yet
+        match err.raw_os_error() {
+            Some(libc::ENETUNREACH) => Self::NetworkUnreachable,
+            Some(libc::EHOSTUNREACH) => Self::HostUnreachable,
+            _ => Self::GeneralFailure,
+        }
+    }
+}
+
+// The same as REP in Socks5 Protocol
+impl From<ConnectStatus> for u8 {
+    fn from(value: ConnectStatus) -> u8 {
+        match value {
+            ConnectStatus::Succeeded => 0,
+            ConnectStatus::GeneralFailure => 1,
+            ConnectStatus::NotAllowed => 2,
+            ConnectStatus::NetworkUnreachable => 3,
+            ConnectStatus::HostUnreachable => 4,
+            ConnectStatus::ConnectionRefused => 5,
+            ConnectStatus::TimedOut => 6,
+        }
+    }
+}
+
+impl From<u8> for ConnectStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ConnectStatus::Succeeded,
+            2 => ConnectStatus::NotAllowed,
+            3 => ConnectStatus::NetworkUnreachable,
+            4 => ConnectStatus::HostUnreachable,
+            5 => ConnectStatus::ConnectionRefused,
+            6 => ConnectStatus::TimedOut,
+            _ => ConnectStatus::GeneralFailure,
+        }
+    }
+}
+
+/// A failure status replied by bp server, which connects to dest address on behalf of bp client
+#[derive(Debug)]
+pub struct ConnectStatusError(pub ConnectStatus);
+
+impl Display for ConnectStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bp server replied {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ConnectStatusError {}
+
 pub struct Outbound {
     opts: Options,
     socket: Option<Arc<Socket>>,
@@ -33,8 +129,11 @@ pub struct Outbound {
     peer_address: SocketAddr,
     remote_addr: Option<Address>,
     protocol_name: Option<String>,
+    connect_status: ConnectStatus,
     is_closed: Arc<AtomicBool>,
     is_allow_proxy: bool,
+    is_mux: bool,
+    is_quic_datagram: bool,
     shutdown: Shutdown,
 }
 
@@ -47,12 +146,20 @@ impl Outbound {
             peer_address,
             remote_addr: None,
             protocol_name: None,
+            connect_status: ConnectStatus::GeneralFailure,
             is_closed: Arc::new(AtomicBool::new(false)),
             is_allow_proxy: true,
+            is_mux: false,
+            is_quic_datagram: false,
             shutdown,
         }
     }
 
+    /// Create a new outbound for the same peer, e.g, HTTP keep-alive connection requests another dest address
+    pub fn fork(&self) -> Self {
+        Self::new(self.peer_address, self.opts.clone(), self.shutdown.clone())
+    }
+
     pub fn set_socket_type(&mut self, socket_type: SocketType) {
         self.socket_type = Some(socket_type);
     }
@@ -65,8 +172,35 @@ impl Outbound {
         self.is_allow_proxy = allow;
     }
 
+    pub fn set_mux(&mut self, mux: bool) {
+        self.is_mux = mux;
+    }
+
+    /// Whether to open a stream on a shared mux session to bp server rather than a new connection
+    pub fn is_mux(&self) -> bool {
+        self.is_mux
+            && !self.is_direct()
+            && matches!(
+                self.socket_type,
+                Some(SocketType::Tcp | SocketType::Tls | SocketType::Ws)
+            )
+    }
+
+    pub fn set_quic_datagram(&mut self, quic_datagram: bool) {
+        self.is_quic_datagram = quic_datagram;
+    }
+
+    /// Whether to relay UDP packets by datagrams of a shared QUIC connection to bp server rather than a stream
+    pub fn is_quic_datagram(&self) -> bool {
+        self.is_quic_datagram && !self.is_direct() && matches!(self.socket_type, Some(SocketType::Quic))
+    }
+
+    pub fn connect_status(&self) -> ConnectStatus {
+        self.connect_status
+    }
+
     pub async fn start_connect(&mut self, resolved: &ResolvedResult) -> Result<()> {
-        let socket_type = self.socket_type.as_ref().unwrap();
+        let socket_type = self.socket_type.unwrap();
         let protocol_name = self.protocol_name.as_ref().unwrap();
         let peer_address = self.peer_address;
 
@@ -82,41 +216,176 @@ impl Outbound {
         self.protocol_name = Some(protocol_name.to_string());
         self.remote_addr = Some(remote_addr.clone());
 
-        // resolve dest ip address
-        let remote_ip_addr = dns_resolve(&remote_addr).await.map_err(|err| {
+        // UDP cannot be relayed through upstream proxy, refuse it rather than going out directly
+        if self.opts.is_client()
+            && self.opts.client_opts().upstream_proxy.is_some()
+            && matches!(socket_type, SocketType::Udp)
+        {
             let msg = format!(
-                "[{}] [{}] resolve ip address of {} failed due to: {}",
-                peer_address, socket_type, remote_addr, err
+                "[{}] [{}] UDP cannot be relayed via --upstream-proxy, use --udp-over-tcp instead",
+                peer_address, socket_type
             );
             log::error!("{}", msg);
-            Error::msg(msg)
-        })?;
+            self.connect_status = ConnectStatus::NotAllowed;
+            return Err(Error::msg(msg));
+        }
 
-        let target_str = if remote_addr.is_hostname() {
-            format!("{}({})", remote_addr, remote_ip_addr)
-        } else {
-            format!("{}", remote_addr)
+        let upstream_proxy = self.get_upstream_proxy();
+
+        // resolve dest ip address, leave it to upstream proxy if set
+        let remote_ip_addr = match upstream_proxy {
+            Some(_) => None,
+            None => Some(dns_resolve(&remote_addr).await.map_err(|err| {
+                let msg = format!(
+                    "[{}] [{}] resolve ip address of {} failed due to: {}",
+                    peer_address, socket_type, remote_addr, err
+                );
+                log::error!("{}", msg);
+                self.connect_status = ConnectStatus::HostUnreachable;
+                Error::msg(msg)
+            })?),
+        };
+
+        let target_str = match (upstream_proxy, remote_ip_addr) {
+            (Some(proxy), _) => format!("{} via upstream proxy {}", remote_addr, proxy),
+            (None, Some(ip_addr)) if remote_addr.is_hostname() => format!("{}({})", remote_addr, ip_addr),
+            _ => format!("{}", remote_addr),
         };
 
         log::info!("[{}] [{}] connecting to {}...", peer_address, socket_type, target_str);
 
-        // make connection
-        let socket = self.connect(&remote_addr, remote_ip_addr).await.map_err(|err| {
+        // make connection, or open a stream on a mux session
+        let socket = if self.is_mux() {
+            self.connect_mux(&remote_addr, remote_ip_addr).await
+        } else {
+            self.connect(&remote_addr, remote_ip_addr).await
+        };
+
+        let socket = socket.map_err(|err| {
             let msg = format!(
                 "[{}] [{}] connect to {} failed due to: {}",
                 peer_address, socket_type, target_str, err
             );
             log::error!("{}", msg);
+            self.connect_status = ConnectStatus::from_error(&err);
             Error::msg(msg)
         })?;
 
         self.socket = Some(socket);
+        self.connect_status = ConnectStatus::Succeeded;
 
         log::info!("[{}] [{}] connected to {}", peer_address, socket_type, target_str);
 
         Ok(())
     }
 
+    /// Receive the status of connecting to dest address replied from bp server, only when relaying via bp server
+    pub async fn recv_connect_status(&mut self, out_proto: &mut DynProtocol) -> Result<()> {
+        let socket = self.socket.as_ref().unwrap();
+
+        if self.is_direct() || socket.is_udp() {
+            return Ok(());
+        }
+
+        if let Err(err) = out_proto.recv_connect_status(socket).await {
+            self.connect_status = ConnectStatus::from_error(&err);
+
+            let msg = format!(
+                "[{}] [{}] connect to {} failed on bp server due to: {}",
+                self.peer_address,
+                self.socket_type.unwrap(),
+                out_proto.get_resolved_result().address,
+                err
+            );
+            log::error!("{}", msg);
+
+            return Err(Error::msg(msg));
+        }
+
+        Ok(())
+    }
+
+    /// Listen on a random port, for Socks5 BIND
+    pub async fn listen(&self, ip: IpAddr) -> Result<TcpListener> {
+        let listener = TcpListener::bind(SocketAddr::new(ip, 0)).await?;
+
+        log::info!(
+            "[{}] [{}] listening at {} for incoming connection...",
+            self.peer_address,
+            SocketType::Tcp,
+            listener.local_addr()?
+        );
+
+        Ok(listener)
+    }
+
+    /// Accept one incoming connection from the expected address as outbound socket, for Socks5 BIND.
+    /// Unspecified ip or port 0 of the expected address matches any, connections from others are rejected.
+    pub async fn accept(&mut self, listener: TcpListener, expected: &Address) -> Result<SocketAddr> {
+        let expected_ip = expected.resolve().await?.ip();
+        let expected_port = expected.port();
+
+        let future = async {
+            loop {
+                let (stream, addr) = listener.accept().await?;
+
+                let is_ip_matched = expected_ip.is_unspecified() || expected_ip == addr.ip();
+                let is_port_matched = expected_port == 0 || expected_port == addr.port();
+
+                if is_ip_matched && is_port_matched {
+                    return Ok::<_, std::io::Error>((stream, addr));
+                }
+
+                log::warn!(
+                    "[{}] [{}] rejected incoming connection from {}, expect {}",
+                    self.peer_address,
+                    SocketType::Tcp,
+                    addr,
+                    expected
+                );
+            }
+        };
+
+        let (stream, addr) = timeout(Duration::from_secs(constants::BIND_ACCEPT_TIMEOUT_SECONDS), future)
+            .await
+            .map_err(|_| {
+                Error::msg(format!(
+                    "[{}] [{}] no incoming connection accepted within {} seconds",
+                    self.peer_address,
+                    SocketType::Tcp,
+                    constants::BIND_ACCEPT_TIMEOUT_SECONDS
+                ))
+            })??;
+
+        log::info!(
+            "[{}] [{}] accepted incoming connection from {}",
+            self.peer_address,
+            SocketType::Tcp,
+            addr
+        );
+
+        self.socket_type = Some(SocketType::Tcp);
+        self.remote_addr = Some(addr.into());
+        self.socket = Some(Arc::new(Socket::from_tcp_stream(stream)));
+
+        Ok(addr)
+    }
+
+    /// Receive an address replied from bp server, for Socks5 BIND
+    pub async fn recv_address(&self, out_proto: &mut DynProtocol) -> Result<Address> {
+        let socket = self.socket.as_ref().unwrap();
+        let buf = out_proto.client_decode(socket).await?;
+
+        let (addr, pending_buf) = Address::from_bytes(buf)?;
+
+        // plain protocol has no framing, data followed by the address should be read again
+        if let Some(buf) = pending_buf {
+            socket.cache(buf);
+        }
+
+        Ok(addr)
+    }
+
     pub fn handle_incoming_data(&self, mut in_proto: DynProtocol, mut out_proto: DynProtocol, tx: Sender<Event>) {
         let socket_type = self.socket_type.as_ref().unwrap();
         let peer_address = self.peer_address;
@@ -155,7 +424,10 @@ impl Outbound {
                 };
 
                 if let Err(err) = res {
-                    let _ = tx.send(Event::OutboundError(err)).await;
+                    // closed on purpose, e.g, reconnect to another dest address
+                    if !is_closed.load(Ordering::Relaxed) {
+                        let _ = tx.send(Event::OutboundError(err)).await;
+                    }
                     break;
                 }
 
@@ -190,88 +462,281 @@ impl Outbound {
         Ok(())
     }
 
+    /// Whether connect to the destination directly rather than bp server
+    fn is_direct(&self) -> bool {
+        self.opts.is_server() || self.opts.client_opts().server_bind.is_none() || !self.is_allow_proxy
+    }
+
     fn get_actual_remote_addr(&self, resolved: &ResolvedResult) -> Address {
-        if self.opts.is_server() || self.opts.client_opts().server_bind.is_none() || !self.is_allow_proxy {
+        if self.is_direct() {
             resolved.address.clone()
         } else {
             self.opts.client_opts().server_bind.unwrap()
         }
     }
 
-    async fn connect(&self, addr: &Address, ip_addr: SocketAddr) -> Result<Arc<Socket>> {
+    /// Upstream proxy is only for TCP, TLS, WebSocket and HTTP/2 connections made by client
+    fn get_upstream_proxy(&self) -> Option<UpstreamProxy> {
+        if !self.opts.is_client()
+            || !matches!(
+                self.socket_type,
+                Some(SocketType::Tcp | SocketType::Tls | SocketType::Ws | SocketType::H2)
+            )
+        {
+            return None;
+        }
+        self.opts.client_opts().upstream_proxy
+    }
+
+    async fn connect(&self, addr: &Address, ip_addr: Option<SocketAddr>) -> Result<Arc<Socket>> {
         let socket_type = self.socket_type.as_ref().unwrap();
         let peer_address = self.peer_address;
 
         let socket = match socket_type {
-            SocketType::Tcp | SocketType::Tls => {
-                #[cfg(target_os = "linux")]
-                use std::os::unix::io::AsRawFd;
-
-                let socket = match ip_addr {
-                    SocketAddr::V4(..) => TcpSocket::new_v4()?,
-                    SocketAddr::V6(..) => TcpSocket::new_v6()?,
-                };
-
-                #[cfg(target_os = "linux")]
-                if self.opts.is_client() {
-                    if let Err(err) = self.mark_socket(socket.as_raw_fd(), 0xff) {
-                        log::error!("set SO_MARK error due to: {}", err);
-                    }
-                }
-
-                let future = socket.connect(ip_addr);
-                let tcp_stream = timeout(Duration::from_secs(constants::TCP_CONNECT_TIMEOUT_SECONDS), future).await??;
+            SocketType::Tcp | SocketType::Tls | SocketType::Ws => {
+                let mut tcp_stream = self.connect_tcp_stream(addr, ip_addr).await?;
 
                 match socket_type {
-                    SocketType::Tcp => Arc::new(Socket::from_tcp_stream(tcp_stream)),
+                    SocketType::Tcp => {
+                        // tell the destination who is the real client, bp server doesn't expect it
+                        if let Some(version) = self.opts.send_proxy_protocol() {
+                            if self.is_direct() {
+                                let header = proxy_protocol::encode(version, peer_address, tcp_stream.peer_addr()?);
+                                tcp_stream.write_all(&header).await?;
+                            }
+                        }
+
+                        Arc::new(Socket::from_tcp_stream(tcp_stream))
+                    }
                     SocketType::Tls => {
-                        // create TlsStream from TcpStream
-                        let connector = TlsConnector::from(Arc::new(get_tls_client_config()));
-                        let domain = rustls::ServerName::try_from(addr.host().as_str())?;
-
-                        let tls_stream = connector.connect(domain, tcp_stream).await?;
-
+                        let tls_stream = self.connect_tls(addr, tcp_stream).await?;
                         Arc::new(Socket::from_tls_stream(TlsStream::Client(tls_stream)))
                     }
+                    SocketType::Ws => self.connect_websocket(addr, tcp_stream).await?,
                     _ => unreachable!(),
                 }
             }
+            SocketType::H2 => self.connect_h2(addr, ip_addr).await?,
+            // udp and quic never go through upstream proxy, so ip_addr is always resolved
             SocketType::Udp => {
-                let socket = Socket::bind_udp_random_port(ip_addr).await?;
+                let socket = Socket::bind_udp_random_port(ip_addr.unwrap()).await?;
                 Arc::new(socket)
             }
-            SocketType::Quic => {
-                let RandomEndpoint { inner: endpoint, reuse } = global::get_quic_random_endpoint()?;
+            SocketType::Quic if self.is_quic_datagram() => {
+                let QuicDatagramSession { conn, socket, reuse } =
+                    ConnectionPool::open_datagram_session(ip_addr.unwrap(), &addr.host()).await?;
 
-                if reuse {
-                    log::info!(
-                        "[{}] [{}] reuse endpoint, local_port = {}",
-                        peer_address,
-                        socket_type,
-                        endpoint.local_addr()?.port()
-                    );
-                }
-
-                let future = endpoint.connect(ip_addr, &addr.host())?;
-                let conn = timeout(Duration::from_secs(constants::QUIC_CONNECT_TIMEOUT_SECONDS), future).await??;
+                log::info!(
+                    "[{}] [{}] {} connection {} for datagrams, RTT = {}ms",
+                    peer_address,
+                    socket_type,
+                    if reuse { "reuse" } else { "new" },
+                    conn.stable_id(),
+                    conn.rtt().as_millis()
+                );
 
-                let conn = conn.connection;
-                let stream = conn.open_bi().await.unwrap();
-                let peer_addr = conn.remote_address();
+                Arc::new(socket)
+            }
+            SocketType::Quic => {
+                let QuicStream { conn, stream, reuse } =
+                    ConnectionPool::open_stream(ip_addr.unwrap(), &addr.host()).await?;
 
                 log::info!(
-                    "[{}] [{}] connection RTT = {}ms",
+                    "[{}] [{}] {} connection {}, RTT = {}ms",
                     peer_address,
                     socket_type,
+                    if reuse { "reuse" } else { "new" },
+                    conn.stable_id(),
                     conn.rtt().as_millis()
                 );
 
-                Arc::new(Socket::from_quic(peer_addr, stream))
+                Arc::new(Socket::from_quic(conn.remote_address(), stream))
             }
+            // streams of mux session are opened by connect_mux()
+            SocketType::Mux => unreachable!(),
         };
         Ok(socket)
     }
 
+    /// Make TCP connection to the address, through upstream proxy if ip_addr is not resolved
+    async fn connect_tcp_stream(&self, addr: &Address, ip_addr: Option<SocketAddr>) -> Result<TcpStream> {
+        match ip_addr {
+            Some(ip_addr) => self.connect_tcp(ip_addr).await,
+            None => {
+                let proxy = self.get_upstream_proxy().unwrap();
+                let proxy_ip_addr = dns_resolve(&proxy.address).await?;
+
+                let mut tcp_stream = self.connect_tcp(proxy_ip_addr).await?;
+                let future = proxy.handshake(&mut tcp_stream, addr);
+
+                timeout(Duration::from_secs(constants::TCP_CONNECT_TIMEOUT_SECONDS), future).await??;
+                Ok(tcp_stream)
+            }
+        }
+    }
+
+    /// Create TlsStream from TcpStream
+    async fn connect_tls(&self, addr: &Address, tcp_stream: TcpStream) -> Result<client::TlsStream<TcpStream>> {
+        let mut config = get_tls_client_config();
+
+        // HTTP/2 over TLS is negotiated by ALPN, as browsers do
+        if matches!(self.socket_type, Some(SocketType::H2)) {
+            config.alpn_protocols = vec![constants::H2_ALPN_PROTOCOL.to_vec()];
+        }
+
+        let connector = TlsConnector::from(Arc::new(config));
+        let domain = rustls::ServerName::try_from(addr.host().as_str())?;
+
+        Ok(connector.connect(domain, tcp_stream).await?)
+    }
+
+    /// Upgrade TcpStream to WebSocket, over TLS if --tls is on
+    async fn connect_websocket(&self, addr: &Address, tcp_stream: TcpStream) -> Result<Arc<Socket>> {
+        let client_opts = self.opts.client_opts();
+
+        let scheme = if self.opts.tls() { "wss" } else { "ws" };
+        let host = client_opts.transport_host.unwrap_or_else(|| addr.as_string());
+        let request = format!("{}://{}{}", scheme, host, client_opts.transport_path);
+
+        let socket = if self.opts.tls() {
+            let tls_stream = self.connect_tls(addr, tcp_stream).await?;
+            let (stream, _) = client_async(request, TlsStream::Client(tls_stream)).await?;
+            Socket::from_wss_stream(stream)
+        } else {
+            let (stream, _) = client_async(request, tcp_stream).await?;
+            Socket::from_ws_stream(stream)
+        };
+
+        Ok(Arc::new(socket))
+    }
+
+    /// Open a stream on the HTTP/2 connection to bp server, a new connection is made if there is none or it's lost
+    async fn connect_h2(&self, addr: &Address, ip_addr: Option<SocketAddr>) -> Result<Arc<Socket>> {
+        let client_opts = self.opts.client_opts();
+
+        // the lock is never held while connecting, connections wait for the connection being made instead
+        let cell = {
+            let all_connections = global::get_h2_connections();
+            let mut all_connections = all_connections.lock();
+
+            let cell = all_connections.entry(addr.as_string()).or_default();
+
+            if cell.get().map_or(false, |conn| conn.is_closed()) {
+                *cell = Default::default();
+            }
+
+            cell.clone()
+        };
+
+        let reuse = cell.initialized();
+
+        let conn = cell
+            .get_or_try_init(|| async move {
+                let tcp_stream = self.connect_tcp_stream(addr, ip_addr).await?;
+                let tls_stream = self.connect_tls(addr, tcp_stream).await?;
+
+                H2Connection::handshake(tls_stream).await
+            })
+            .await?
+            .clone();
+
+        log::info!(
+            "[{}] [{}] {} h2 connection to {}",
+            self.peer_address,
+            self.socket_type.unwrap(),
+            if reuse { "reuse" } else { "new" },
+            addr
+        );
+
+        let host = client_opts.transport_host.unwrap_or_else(|| addr.as_string());
+        let uri = format!("https://{}{}", host, client_opts.transport_path);
+
+        let stream = conn.open_stream(&uri).await?;
+
+        Ok(Arc::new(Socket::from_h2(conn.peer_addr(), stream)))
+    }
+
+    /// Open a stream on a mux session which has capacity, a new session is made to bp server if there is none
+    async fn connect_mux(&self, addr: &Address, ip_addr: Option<SocketAddr>) -> Result<Arc<Socket>> {
+        let all_sessions = global::get_mux_sessions();
+
+        loop {
+            // the lock is never held while connecting, connections wait for the session being started instead
+            let starting = {
+                let mut all_sessions = all_sessions.lock();
+                let sessions = all_sessions.entry(addr.as_string()).or_default();
+                sessions.established.retain(|session| !session.is_closed());
+
+                if let Some(session) = sessions.established.iter().find(|session| session.has_capacity()) {
+                    let socket = session.open_stream(self.peer_address)?;
+                    return Ok(Arc::new(socket));
+                }
+
+                sessions.starting.get_or_insert_with(Default::default).clone()
+            };
+
+            let session = starting
+                .get_or_try_init(|| self.start_mux_session(addr, ip_addr))
+                .await?
+                .clone();
+
+            // the first connection finished waiting publishes the session, then all of them try again
+            let mut all_sessions = all_sessions.lock();
+            let sessions = all_sessions.entry(addr.as_string()).or_default();
+
+            if let Some(cell) = &sessions.starting {
+                if Arc::ptr_eq(cell, &starting) {
+                    sessions.starting = None;
+                    sessions.established.push(session);
+
+                    log::info!(
+                        "[{}] [{}] mux session to {} started, {} sessions in total",
+                        self.peer_address,
+                        self.socket_type.unwrap(),
+                        addr,
+                        sessions.established.len()
+                    );
+                }
+            }
+        }
+    }
+
+    async fn start_mux_session(&self, addr: &Address, ip_addr: Option<SocketAddr>) -> Result<Arc<MuxSession>> {
+        let socket = self.connect(addr, ip_addr).await?;
+
+        let mut proto = init_protocol(self.opts.encryption(), self.opts.key(), self.opts.service_type());
+        proto.set_resolved_result(ResolvedResult {
+            protocol: ProtocolType::Mux,
+            address: Address::new(Host::Ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), 0),
+            pending_buf: None,
+            client_hello: None,
+        });
+
+        Ok(MuxSession::client(socket, proto))
+    }
+
+    async fn connect_tcp(&self, ip_addr: SocketAddr) -> Result<TcpStream> {
+        #[cfg(target_os = "linux")]
+        use std::os::unix::io::AsRawFd;
+
+        let socket = match ip_addr {
+            SocketAddr::V4(..) => TcpSocket::new_v4()?,
+            SocketAddr::V6(..) => TcpSocket::new_v6()?,
+        };
+
+        #[cfg(target_os = "linux")]
+        if self.opts.is_client() {
+            if let Err(err) = self.mark_socket(socket.as_raw_fd(), 0xff) {
+                log::error!("set SO_MARK error due to: {}", err);
+            }
+        }
+
+        let future = socket.connect(ip_addr);
+        let tcp_stream = timeout(Duration::from_secs(constants::TCP_CONNECT_TIMEOUT_SECONDS), future).await??;
+
+        Ok(tcp_stream)
+    }
+
     #[cfg(target_os = "linux")]
     fn mark_socket(&self, fd: i32, mark: u8) -> Result<()> {
         let mark: libc::c_uint = mark.into();
@@ -1,9 +1,28 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+};
+
 use anyhow::Result;
-use quinn::{ClientConfig, Endpoint, ServerConfig};
+use bytes::{Buf, Bytes};
+use futures_util::StreamExt;
+use quinn::{
+    ClientConfig, Connection, Datagrams, Endpoint, IncomingUniStreams, NewConnection, RecvStream, SendStream,
+    ServerConfig, TransportConfig, VarInt,
+};
 use rustls::RootCertStore;
+use tokio::{
+    sync::mpsc::{channel, error::TrySendError, Sender},
+    time::{timeout, Duration},
+};
 
 use crate::{
-    global,
+    constants, global,
+    net::socket::Socket,
     utils::{crypto::Crypto, tls},
 };
 
@@ -11,7 +30,13 @@ pub fn init_quinn_server_config(cert_path: &str, key_path: &str) -> Result<()> {
     let cert = tls::read_cert_from_file(cert_path)?;
     let key = tls::read_key_from_file(key_path)?;
 
-    let config = ServerConfig::with_single_cert(vec![cert], key)?;
+    let mut config = ServerConfig::with_single_cert(vec![cert], key)?;
+
+    // proxied connections of a bp client are carried by streams of a few connections
+    let mut transport = TransportConfig::default();
+    transport.max_concurrent_bidi_streams(VarInt::from_u32(constants::QUIC_MAX_CONCURRENT_STREAMS));
+    config.transport = Arc::new(transport);
+
     global::set_quinn_server_config(config);
 
     Ok(())
@@ -41,6 +66,18 @@ pub fn init_quic_endpoint_pool(max_concurrency: Option<u16>) -> Result<()> {
     Ok(())
 }
 
+pub fn init_quic_connection_pool(max_concurrency: Option<u16>) -> Result<()> {
+    let mut pool = ConnectionPool::default();
+
+    if let Some(cap) = max_concurrency {
+        pool.set_capacity(cap);
+    }
+
+    global::set_quic_connection_pool(pool);
+
+    Ok(())
+}
+
 #[derive(Default)]
 pub struct EndpointPool {
     capacity: Option<u16>,
@@ -85,3 +122,331 @@ pub struct RandomEndpoint {
     pub inner: Endpoint,
     pub reuse: bool,
 }
+
+/// Established QUIC connections to each bp server, a proxied connection is carried by a bidirectional stream,
+/// or a datagram session for UDP
+///
+/// The pool is locked only to pick or insert connections, handshakes and streams are made without the lock.
+#[derive(Default)]
+pub struct ConnectionPool {
+    capacity: Option<u16>,
+    data: HashMap<SocketAddr, PooledConnections>,
+}
+
+#[derive(Default)]
+struct PooledConnections {
+    established: Vec<PooledConnection>,
+    /// Number of connections being made
+    connecting: usize,
+}
+
+enum Picked {
+    Reuse(PooledConnection),
+    Connect(Reservation),
+}
+
+/// A slot reserved for a connection being made, released once dropped
+struct Reservation {
+    addr: SocketAddr,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        global::get_quic_connection_pool().lock().release(self.addr);
+    }
+}
+
+impl ConnectionPool {
+    /// The max number of connections to each bp server, 1 if not set
+    pub fn set_capacity(&mut self, capacity: u16) {
+        self.capacity = Some(capacity);
+    }
+
+    /// Open a stream on an established connection, a new connection is made if there are not enough connections,
+    /// or the chosen one is lost
+    pub async fn open_stream(addr: SocketAddr, server_name: &str) -> Result<QuicStream> {
+        let pool = global::get_quic_connection_pool();
+        let picked = pool.lock().pick(addr);
+
+        let reservation = match picked {
+            Picked::Reuse(pooled) => {
+                let conn = pooled.conn;
+
+                match conn.open_bi().await {
+                    Ok(stream) => {
+                        return Ok(QuicStream {
+                            conn,
+                            stream,
+                            reuse: true,
+                        })
+                    }
+                    Err(err) => {
+                        // e.g, closed after idle timeout
+                        log::info!("quic connection to {} is lost due to: {}, reconnecting...", addr, err);
+
+                        let mut pool = pool.lock();
+                        pool.remove(addr, &conn);
+                        pool.reserve(addr)
+                    }
+                }
+            }
+            Picked::Connect(reservation) => reservation,
+        };
+
+        let pooled = Self::connect_pooled(addr, server_name, reservation).await?;
+        let conn = pooled.conn;
+        let stream = conn.open_bi().await?;
+
+        Ok(QuicStream {
+            conn,
+            stream,
+            reuse: false,
+        })
+    }
+
+    /// Open a datagram session on an established connection, a new connection is made if there are not enough
+    /// connections
+    pub async fn open_datagram_session(addr: SocketAddr, server_name: &str) -> Result<QuicDatagramSession> {
+        let picked = global::get_quic_connection_pool().lock().pick(addr);
+
+        let (pooled, reuse) = match picked {
+            Picked::Reuse(pooled) => (pooled, true),
+            Picked::Connect(reservation) => (Self::connect_pooled(addr, server_name, reservation).await?, false),
+        };
+
+        Ok(QuicDatagramSession {
+            socket: pooled.datagram_sessions.open(),
+            conn: pooled.conn,
+            reuse,
+        })
+    }
+
+    /// Choose an established connection if there are enough connections, otherwise reserve a slot for a new one
+    fn pick(&mut self, addr: SocketAddr) -> Picked {
+        let capacity = self.capacity.unwrap_or(1) as usize;
+        let connections = self.data.entry(addr).or_default();
+        connections
+            .established
+            .retain(|pooled| !pooled.datagram_sessions.is_closed());
+
+        // connections being made cannot be used yet, a new one is made if there is no established connection
+        if connections.established.len() + connections.connecting >= capacity {
+            if let Some(pooled) = Crypto::random_choose(&connections.established) {
+                return Picked::Reuse(pooled.clone());
+            }
+        }
+
+        connections.connecting += 1;
+        Picked::Connect(Reservation { addr })
+    }
+
+    fn reserve(&mut self, addr: SocketAddr) -> Reservation {
+        self.data.entry(addr).or_default().connecting += 1;
+        Reservation { addr }
+    }
+
+    fn release(&mut self, addr: SocketAddr) {
+        if let Some(connections) = self.data.get_mut(&addr) {
+            connections.connecting = connections.connecting.saturating_sub(1);
+        }
+    }
+
+    fn insert(&mut self, addr: SocketAddr, pooled: PooledConnection) {
+        self.data.entry(addr).or_default().established.push(pooled);
+    }
+
+    fn remove(&mut self, addr: SocketAddr, conn: &Connection) {
+        if let Some(connections) = self.data.get_mut(&addr) {
+            connections
+                .established
+                .retain(|pooled| pooled.conn.stable_id() != conn.stable_id());
+        }
+    }
+
+    /// Make a new connection in the reserved slot, and put it into the pool
+    async fn connect_pooled(addr: SocketAddr, server_name: &str, reservation: Reservation) -> Result<PooledConnection> {
+        let pooled = Self::connect(addr, server_name).await?;
+
+        global::get_quic_connection_pool().lock().insert(addr, pooled.clone());
+        drop(reservation);
+
+        Ok(pooled)
+    }
+
+    async fn connect(addr: SocketAddr, server_name: &str) -> Result<PooledConnection> {
+        let RandomEndpoint { inner: endpoint, .. } = global::get_quic_random_endpoint()?;
+
+        let future = endpoint.connect(addr, server_name)?;
+        let NewConnection {
+            connection,
+            datagrams,
+            uni_streams,
+            ..
+        } = timeout(Duration::from_secs(constants::QUIC_CONNECT_TIMEOUT_SECONDS), future).await??;
+
+        // replies of datagram sessions, the dispatching ends once the connection is lost
+        let datagram_sessions = DatagramSessions::new(connection.clone());
+        tokio::spawn(datagram_sessions.clone().dispatch(datagrams, uni_streams, None));
+
+        Ok(PooledConnection {
+            conn: connection,
+            datagram_sessions,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct PooledConnection {
+    conn: Connection,
+    datagram_sessions: Arc<DatagramSessions>,
+}
+
+pub struct QuicStream {
+    pub conn: Connection,
+    pub stream: (SendStream, RecvStream),
+    /// Whether the connection is established before
+    pub reuse: bool,
+}
+
+pub struct QuicDatagramSession {
+    pub conn: Connection,
+    pub socket: Socket,
+    /// Whether the connection is established before
+    pub reuse: bool,
+}
+
+/// UDP packets carried by QUIC datagrams (RFC 9221) of a connection
+///
+/// # Protocol
+///
+/// +------------+----------+
+/// | Session ID |  Packet  |
+/// +------------+----------+
+/// |     4      | Variable |
+/// +------------+----------+
+///
+/// # Explain
+///
+/// * Session ID is chosen by bp client for each UDP packet it relays, the packet is encoded as usual.
+/// * bp server handles a new session as a new connection, and sends replies back with the same Session ID.
+/// * Datagrams are neither reliable nor ordered, packets exceed the max datagram size of the path are sent by
+///   unidirectional streams in the same format, one packet per stream.
+pub struct DatagramSessions {
+    conn: Connection,
+    next_id: AtomicU32,
+    sessions: parking_lot::Mutex<HashMap<u32, Sender<Bytes>>>,
+    is_closed: AtomicBool,
+}
+
+impl DatagramSessions {
+    pub fn new(conn: Connection) -> Arc<Self> {
+        Arc::new(Self {
+            conn,
+            next_id: AtomicU32::new(0),
+            sessions: parking_lot::Mutex::new(HashMap::new()),
+            is_closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Open a new session on client side
+    pub fn open(&self) -> Socket {
+        let session_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.create(session_id).0
+    }
+
+    /// Whether the connection is lost
+    pub fn is_closed(&self) -> bool {
+        self.is_closed.load(Ordering::Relaxed)
+    }
+
+    /// Dispatch received datagrams and packets of unidirectional streams to sessions until the connection is lost.
+    /// Datagrams of unknown sessions are accepted as new sessions on server side, or dropped on client side.
+    pub async fn dispatch(
+        self: Arc<Self>,
+        mut datagrams: Datagrams,
+        mut uni_streams: IncomingUniStreams,
+        new_sessions: Option<Sender<Option<Socket>>>,
+    ) {
+        let peer_addr = self.conn.remote_address();
+        let conn_id = self.conn.stable_id();
+
+        // packets too large for datagrams, read from unidirectional streams
+        let (packet_sender, mut packet_receiver) = channel(constants::QUIC_DATAGRAM_SESSION_QUEUE_SIZE);
+
+        loop {
+            let mut datagram = tokio::select! {
+                v = datagrams.next() => match v {
+                    Some(Ok(datagram)) => datagram,
+                    _ => break,
+                },
+                v = uni_streams.next() => match v {
+                    Some(Ok(stream)) => {
+                        tokio::spawn(Self::read_packet(stream, packet_sender.clone()));
+                        continue;
+                    }
+                    _ => break,
+                },
+                Some(packet) = packet_receiver.recv() => packet,
+            };
+
+            if datagram.len() < 4 {
+                continue;
+            }
+
+            let session_id = datagram.get_u32();
+            let sender = self.sessions.lock().get(&session_id).cloned();
+
+            let datagram = match sender {
+                Some(sender) => match sender.try_send(datagram) {
+                    Ok(_) | Err(TrySendError::Full(_)) => continue,
+                    // the session is ended, start over if it's sent again
+                    Err(TrySendError::Closed(datagram)) => datagram,
+                },
+                None => datagram,
+            };
+
+            if let Some(new_sessions) = &new_sessions {
+                log::info!(
+                    "[{}] [{}] create new quic datagram session {}",
+                    peer_addr,
+                    conn_id,
+                    session_id
+                );
+
+                let (socket, sender) = self.create(session_id);
+                let _ = sender.try_send(datagram);
+
+                if new_sessions.send(Some(socket)).await.is_err() {
+                    break;
+                }
+            }
+        }
+
+        self.is_closed.store(true, Ordering::Relaxed);
+
+        // wake up readers of all sessions
+        self.sessions.lock().clear();
+    }
+
+    /// Read a whole packet from a unidirectional stream
+    async fn read_packet(stream: RecvStream, sender: Sender<Bytes>) {
+        // no UDP packet is larger than this
+        if let Ok(packet) = stream.read_to_end(u16::MAX as usize + 4).await {
+            let _ = sender.send(packet.into()).await;
+        }
+    }
+
+    fn create(&self, session_id: u32) -> (Socket, Sender<Bytes>) {
+        let (sender, receiver) = channel(constants::QUIC_DATAGRAM_SESSION_QUEUE_SIZE);
+
+        let mut sessions = self.sessions.lock();
+
+        // forget ended sessions
+        sessions.retain(|_, sender| !sender.is_closed());
+        sessions.insert(session_id, sender.clone());
+
+        let socket = Socket::from_quic_datagram(self.conn.clone(), session_id, receiver);
+
+        (socket, sender)
+    }
+}
@@ -4,13 +4,20 @@ use std::{fmt::Display, net::SocketAddr, sync::Arc};
 
 use anyhow::Result;
 use bytes::Bytes;
-use tokio::net::{TcpStream, UdpSocket};
+use tokio::{
+    io::DuplexStream,
+    net::{TcpStream, UdpSocket},
+    sync::mpsc::Receiver,
+};
 use tokio_rustls::TlsStream;
+use tokio_tungstenite::WebSocketStream;
 
 use crate::{
     io::{
         reader::SocketReader,
-        utils::{split_quic, split_tcp, split_tls, split_udp},
+        utils::{
+            split_h2, split_mux, split_quic, split_quic_datagram, split_tcp, split_tls, split_udp, split_ws, split_wss,
+        },
         writer::SocketWriter,
     },
     utils::net::create_udp_client_with_random_port,
@@ -62,7 +69,51 @@ impl Socket {
         Self {
             #[cfg(not(target_os = "windows"))]
             fd: Some(fd),
-            socket_type: SocketType::Tcp,
+            socket_type: SocketType::Tls,
+            reader: split.0,
+            writer: split.1,
+            local_addr: Some(local_addr),
+            peer_addr,
+        }
+    }
+
+    pub fn from_ws_stream(stream: WebSocketStream<TcpStream>) -> Self {
+        let tcp_stream = stream.get_ref();
+
+        let peer_addr = tcp_stream.peer_addr().unwrap();
+        let local_addr = tcp_stream.local_addr().unwrap();
+
+        #[cfg(not(target_os = "windows"))]
+        let fd = tcp_stream.as_raw_fd();
+
+        let split = split_ws(stream);
+
+        Self {
+            #[cfg(not(target_os = "windows"))]
+            fd: Some(fd),
+            socket_type: SocketType::Ws,
+            reader: split.0,
+            writer: split.1,
+            local_addr: Some(local_addr),
+            peer_addr,
+        }
+    }
+
+    pub fn from_wss_stream(stream: WebSocketStream<TlsStream<TcpStream>>) -> Self {
+        let (tcp_stream, _state) = stream.get_ref().get_ref();
+
+        let peer_addr = tcp_stream.peer_addr().unwrap();
+        let local_addr = tcp_stream.local_addr().unwrap();
+
+        #[cfg(not(target_os = "windows"))]
+        let fd = tcp_stream.as_raw_fd();
+
+        let split = split_wss(stream);
+
+        Self {
+            #[cfg(not(target_os = "windows"))]
+            fd: Some(fd),
+            socket_type: SocketType::Ws,
             reader: split.0,
             writer: split.1,
             local_addr: Some(local_addr),
@@ -83,6 +134,52 @@ impl Socket {
         }
     }
 
+    /// A stream of HTTP/2 connection, data is carried by the request and response bodies
+    pub fn from_h2(peer_addr: SocketAddr, stream: (h2::SendStream<Bytes>, h2::RecvStream)) -> Self {
+        let (reader, writer) = split_h2(stream);
+
+        Self {
+            #[cfg(not(target_os = "windows"))]
+            fd: None,
+            socket_type: SocketType::H2,
+            reader,
+            writer,
+            local_addr: None,
+            peer_addr,
+        }
+    }
+
+    /// A logical stream of mux session, the other end of stream is relayed over the session
+    pub fn from_mux(peer_addr: SocketAddr, stream: DuplexStream) -> Self {
+        let (reader, writer) = split_mux(stream);
+
+        Self {
+            #[cfg(not(target_os = "windows"))]
+            fd: None,
+            socket_type: SocketType::Mux,
+            reader,
+            writer,
+            local_addr: None,
+            peer_addr,
+        }
+    }
+
+    /// A session of QUIC datagrams, which is handled as UDP since datagrams are unreliable as well
+    pub fn from_quic_datagram(conn: quinn::Connection, session_id: u32, receiver: Receiver<Bytes>) -> Self {
+        let peer_addr = conn.remote_address();
+        let (reader, writer) = split_quic_datagram(conn, session_id, receiver);
+
+        Self {
+            #[cfg(not(target_os = "windows"))]
+            fd: None,
+            socket_type: SocketType::Udp,
+            reader,
+            writer,
+            local_addr: None,
+            peer_addr,
+        }
+    }
+
     pub fn from_udp_socket(socket: Arc<UdpSocket>, peer_addr: SocketAddr) -> Self {
         let local_addr = socket.local_addr().unwrap();
         let split = split_udp(socket);
@@ -113,6 +210,12 @@ impl Socket {
         self.peer_addr
     }
 
+    /// Overwrite the peer address, e.g, the real client address carried by PROXY protocol
+    #[inline]
+    pub fn set_peer_addr(&mut self, peer_addr: SocketAddr) {
+        self.peer_addr = peer_addr;
+    }
+
     #[inline]
     pub fn is_udp(&self) -> bool {
         matches!(self.socket_type, SocketType::Udp)
@@ -188,6 +291,9 @@ pub enum SocketType {
     Udp,
     Tls,
     Quic,
+    Mux,
+    Ws,
+    H2,
 }
 
 impl Display for SocketType {
@@ -197,6 +303,9 @@ impl Display for SocketType {
             SocketType::Udp => "udp",
             SocketType::Tls => "tls",
             SocketType::Quic => "quic",
+            SocketType::Mux => "mux",
+            SocketType::Ws => "ws",
+            SocketType::H2 => "h2",
         };
         write!(f, "{}", s)
     }
@@ -0,0 +1,52 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The protocol carrying connections between bp client and bp server over TCP or TLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Each connection is a WebSocket, data is sent in binary frames, wss:// when --tls is on
+    WebSocket,
+    /// Each connection is a stream of one HTTP/2 connection over TLS, data is sent in the body of a POST request
+    H2,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ws" | "websocket" => Ok(Self::WebSocket),
+            "h2" => Ok(Self::H2),
+            _ => Err(format!("{} is not supported, available transports are: ws, h2", s)),
+        }
+    }
+}
+
+impl ToString for Transport {
+    fn to_string(&self) -> String {
+        match self {
+            Self::WebSocket => "ws".to_string(),
+            Self::H2 => "h2".to_string(),
+        }
+    }
+}
+
+impl Serialize for Transport {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Transport {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
@@ -1,6 +1,10 @@
 use anyhow::Result;
 
-use crate::{options_from_file, Address, ClientOptions, EncryptionMethod, ServerOptions};
+use crate::{
+    options_from_file,
+    utils::{kdf::KeyDerivation, proxy_protocol::ProxyProtocolVersion},
+    Address, ClientOptions, EncryptionMethod, ServerOptions, Transport,
+};
 
 #[derive(Clone, Copy)]
 pub enum ServiceType {
@@ -9,6 +13,7 @@ pub enum ServiceType {
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Options {
     Client(ClientOptions),
     Server(ServerOptions),
@@ -90,6 +95,37 @@ impl Options {
         }
     }
 
+    pub fn key_derivation(&self) -> KeyDerivation {
+        match self {
+            Self::Client(opts) => opts.key_derivation,
+            Self::Server(opts) => opts.key_derivation,
+        }
+    }
+
+    /// Replace --key and keys of users with keys derived by --key-derivation, should be called only once
+    pub fn derive_keys(&mut self) -> Result<()> {
+        let kdf = self.key_derivation();
+
+        if kdf.is_none() {
+            return Ok(());
+        }
+
+        match self {
+            Self::Client(opts) => {
+                opts.key = opts.key.as_deref().map(|key| kdf.derive(key)).transpose()?;
+            }
+            Self::Server(opts) => {
+                opts.key = opts.key.as_deref().map(|key| kdf.derive(key)).transpose()?;
+
+                for user in opts.users.iter_mut() {
+                    user.key = kdf.derive(&user.key)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn acl(&self) -> Option<String> {
         match self {
             Self::Client(opts) => opts.acl.clone(),
@@ -104,6 +140,20 @@ impl Options {
         }
     }
 
+    pub fn accept_proxy_protocol(&self) -> bool {
+        match self {
+            Self::Client(opts) => opts.accept_proxy_protocol,
+            Self::Server(opts) => opts.accept_proxy_protocol,
+        }
+    }
+
+    pub fn send_proxy_protocol(&self) -> Option<ProxyProtocolVersion> {
+        match self {
+            Self::Client(opts) => opts.send_proxy_protocol,
+            Self::Server(opts) => opts.send_proxy_protocol,
+        }
+    }
+
     pub fn quic(&self) -> bool {
         match self {
             Self::Client(opts) => opts.quic,
@@ -118,6 +168,20 @@ impl Options {
         }
     }
 
+    pub fn transport(&self) -> Option<Transport> {
+        match self {
+            Self::Client(opts) => opts.transport,
+            Self::Server(opts) => opts.transport,
+        }
+    }
+
+    pub fn transport_path(&self) -> String {
+        match self {
+            Self::Client(opts) => opts.transport_path.clone(),
+            Self::Server(opts) => opts.transport_path.clone(),
+        }
+    }
+
     pub fn bind(&self) -> Address {
         match self {
             Self::Client(opts) => opts.bind.clone(),
@@ -1,4 +1,5 @@
 pub mod client;
 pub mod common;
 pub mod server;
+pub mod user;
 pub mod utils;
@@ -1,10 +1,14 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    constants::{DEFAULT_DNS_SERVER_ADDRESS, DEFAULT_SERVER_SERVICE_ADDRESS},
-    net::address::Address,
+    constants::{DEFAULT_DNS_SERVER_ADDRESS, DEFAULT_SERVER_SERVICE_ADDRESS, DEFAULT_TRANSPORT_PATH},
+    net::{address::Address, inbound::HandshakeFailurePolicy, limiter::UserLimits, transport::Transport},
+    options::user::User,
     protos::EncryptionMethod,
+    utils::{kdf::KeyDerivation, proxy_protocol::ProxyProtocolVersion},
 };
 
 // The following getters are for serde deserializing
@@ -14,13 +18,17 @@ fn get_default_bind() -> Address {
 }
 
 fn get_default_encryption() -> EncryptionMethod {
-    EncryptionMethod::EncryptRandomPadding
+    EncryptionMethod::default()
 }
 
 fn get_default_dns_server() -> Address {
     DEFAULT_DNS_SERVER_ADDRESS.parse().unwrap()
 }
 
+fn get_default_transport_path() -> String {
+    DEFAULT_TRANSPORT_PATH.to_string()
+}
+
 #[derive(clap::Args, Deserialize, Serialize, Debug, Clone)]
 pub struct ServerOptions {
     /// Configuration file in YAML/JSON format [default: <empty>]
@@ -37,11 +45,44 @@ pub struct ServerOptions {
     #[clap(short, long)]
     pub key: Option<String>,
 
+    /// User with its own key, e.g, "alice:secret", can be set multiple times, only works with erp [default: <empty>]
+    #[clap(long = "user", value_name = "NAME:KEY")]
+    #[serde(default)]
+    pub users: Vec<User>,
+
+    /// Monthly traffic quota of each user in bytes, counting both directions [default: <empty>]
+    #[clap(long)]
+    pub user_quota: Option<u64>,
+
+    /// Max concurrent connections of each user [default: <empty>]
+    #[clap(long)]
+    pub user_max_connections: Option<usize>,
+
+    /// Bandwidth limit of each user in bytes per second, counting both directions [default: <empty>]
+    #[clap(long)]
+    pub user_bandwidth: Option<u64>,
+
+    /// File to save traffic usage of users across restarts, e.g, "usage.json" [default: <empty>]
+    #[clap(long)]
+    pub user_usage_file: Option<String>,
+
     /// Data encryption method, e.g, "plain" or "erp"
     #[clap(short, long, default_value = "erp")]
     #[serde(default = "get_default_encryption")]
     pub encryption: EncryptionMethod,
 
+    /// Strengthen the key by a memory-hard function once at startup, "none", "argon2id" or "scrypt" with salt
+    /// generated by `bp generate --key-derivation`, e.g, "argon2id:m=19456,t=2,p=1,salt=<base64>", must be the same on
+    /// both sides
+    #[clap(long, default_value = "none")]
+    #[serde(default)]
+    pub key_derivation: KeyDerivation,
+
+    /// What to do when handshake fails, "close", "drain" or "fallback:<host:port>"
+    #[clap(long, default_value = "close")]
+    #[serde(default)]
+    pub handshake_failure: HandshakeFailurePolicy,
+
     /// Check ACL before proxy, pass a file path [default: <empty>]
     #[clap(long)]
     pub acl: Option<String>,
@@ -51,6 +92,15 @@ pub struct ServerOptions {
     #[serde(default = "get_default_dns_server")]
     pub dns_server: Address,
 
+    /// Accept PROXY protocol v1/v2 header from load balancer [default: false]
+    #[clap(long)]
+    #[serde(default)]
+    pub accept_proxy_protocol: bool,
+
+    /// Send PROXY protocol header to destination, e.g, "v1" or "v2" [default: <empty>]
+    #[clap(long)]
+    pub send_proxy_protocol: Option<ProxyProtocolVersion>,
+
     /// Enable TLS for Transport Layer [default: false]
     #[clap(long)]
     #[serde(default)]
@@ -61,6 +111,15 @@ pub struct ServerOptions {
     #[serde(default)]
     pub quic: bool,
 
+    /// Accept connections carried by another protocol over TCP or TLS, e.g, "ws" or "h2" [default: <empty>]
+    #[clap(long)]
+    pub transport: Option<Transport>,
+
+    /// Request path of --transport, requests to other paths are rejected
+    #[clap(long, default_value = DEFAULT_TRANSPORT_PATH)]
+    #[serde(default = "get_default_transport_path")]
+    pub transport_path: String,
+
     /// Certificate file for QUIC or TLS [default: <empty>]
     #[clap(long)]
     pub tls_cert: Option<String>,
@@ -80,11 +139,22 @@ impl Default for ServerOptions {
             config: None,
             bind: get_default_bind(),
             key: None,
+            users: vec![],
+            user_quota: None,
+            user_max_connections: None,
+            user_bandwidth: None,
+            user_usage_file: None,
             encryption: get_default_encryption(),
+            key_derivation: Default::default(),
+            handshake_failure: Default::default(),
             acl: None,
             dns_server: get_default_dns_server(),
+            accept_proxy_protocol: false,
+            send_proxy_protocol: None,
             tls: false,
             quic: false,
+            transport: None,
+            transport_path: get_default_transport_path(),
             tls_cert: None,
             tls_key: None,
             monitor: None,
@@ -94,8 +164,58 @@ impl Default for ServerOptions {
 
 impl ServerOptions {
     pub fn check(&self) -> Result<()> {
-        if self.key.is_none() {
-            return Err(Error::msg("--key must be set."));
+        if self.key.is_none() && self.users.is_empty() {
+            return Err(Error::msg("--key or --user must be set."));
+        }
+
+        if !self.users.is_empty() {
+            if !matches!(self.encryption, EncryptionMethod::EncryptRandomPadding(_)) {
+                return Err(Error::msg("--user only works with erp encryption methods."));
+            }
+
+            let mut names = HashSet::new();
+            let mut keys = HashSet::new();
+
+            if let Some(key) = &self.key {
+                keys.insert(key);
+            }
+
+            for user in &self.users {
+                if !names.insert(&user.name) {
+                    return Err(Error::msg(format!("user {} is set more than once.", user.name)));
+                }
+                // a user is identified by its key, so keys must be unique
+                if !keys.insert(&user.key) {
+                    return Err(Error::msg(format!("key of user {} is used by others.", user.name)));
+                }
+            }
+        }
+
+        if !self.key_derivation.is_none() && !matches!(self.encryption, EncryptionMethod::EncryptRandomPadding(_)) {
+            return Err(Error::msg("--key-derivation only works with erp encryption methods."));
+        }
+
+        let has_user_limits = self.user_quota.is_some()
+            || self.user_max_connections.is_some()
+            || self.user_bandwidth.is_some()
+            || self.user_usage_file.is_some();
+
+        if has_user_limits && self.users.is_empty() {
+            return Err(Error::msg(
+                "--user-quota, --user-max-connections, --user-bandwidth and --user-usage-file only work with --user.",
+            ));
+        }
+
+        if self.transport.is_some() && self.quic {
+            return Err(Error::msg("--transport cannot work with --quic."));
+        }
+
+        if matches!(self.transport, Some(Transport::H2)) && !self.tls {
+            return Err(Error::msg("--transport h2 requires --tls to be set."));
+        }
+
+        if !self.transport_path.starts_with('/') {
+            return Err(Error::msg("--transport-path should start with \"/\"."));
         }
 
         if self.tls && self.quic {
@@ -113,4 +233,20 @@ impl ServerOptions {
 
         Ok(())
     }
+
+    /// Limits of each user, limits not set by the user fall back to --user-quota, --user-max-connections and
+    /// --user-bandwidth
+    pub fn user_limits(&self) -> HashMap<String, UserLimits> {
+        self.users
+            .iter()
+            .map(|user| {
+                let limits = UserLimits {
+                    monthly_quota: user.quota.or(self.user_quota),
+                    max_connections: user.max_connections.or(self.user_max_connections),
+                    bandwidth: user.bandwidth.or(self.user_bandwidth),
+                };
+                (user.name.clone(), limits)
+            })
+            .collect()
+    }
 }
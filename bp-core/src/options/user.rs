@@ -0,0 +1,122 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// A user of bp server with its own key, e.g, "alice:secret"
+///
+/// In configuration files, a user can also be an object with its own limits, which override --user-quota,
+/// --user-max-connections and --user-bandwidth.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub key: String,
+    /// Monthly traffic quota in bytes, counting both directions
+    pub quota: Option<u64>,
+    /// Max concurrent connections
+    pub max_connections: Option<usize>,
+    /// Bandwidth limit in bytes per second, counting both directions
+    pub bandwidth: Option<u64>,
+}
+
+impl User {
+    fn has_limits(&self) -> bool {
+        self.quota.is_some() || self.max_connections.is_some() || self.bandwidth.is_some()
+    }
+}
+
+/// How a user is written in configuration files
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum UserRepr {
+    Short(String),
+    Full {
+        name: String,
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quota: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_connections: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bandwidth: Option<u64>,
+    },
+}
+
+impl FromStr for User {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the key may contain ":"
+        let (name, key) = s
+            .split_once(':')
+            .ok_or("invalid format of user, should be <name>:<key>")?;
+
+        if name.is_empty() || key.is_empty() {
+            return Err("name or key of user cannot be empty".to_string());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            key: key.to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+// key is not shown for logging
+impl Display for User {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Serialize for User {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // keep the short form unless the user has its own limits
+        let repr = if self.has_limits() {
+            UserRepr::Full {
+                name: self.name.clone(),
+                key: self.key.clone(),
+                quota: self.quota,
+                max_connections: self.max_connections,
+                bandwidth: self.bandwidth,
+            }
+        } else {
+            UserRepr::Short(format!("{}:{}", self.name, self.key))
+        };
+
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for User {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match UserRepr::deserialize(deserializer)? {
+            UserRepr::Short(s) => Self::from_str(&s).map_err(serde::de::Error::custom),
+            UserRepr::Full {
+                name,
+                key,
+                quota,
+                max_connections,
+                bandwidth,
+            } => {
+                if name.is_empty() || key.is_empty() {
+                    return Err(serde::de::Error::custom("name or key of user cannot be empty"));
+                }
+
+                Ok(Self {
+                    name,
+                    key,
+                    quota,
+                    max_connections,
+                    bandwidth,
+                })
+            }
+        }
+    }
+}
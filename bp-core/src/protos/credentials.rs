@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use ring::constant_time;
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
+
+/// User and password in the form of "<user>:<password>", shared by HTTP Basic Authorization and
+/// Socks5 Username/Password Authentication
+///
+/// Only the first ':' separates user and password, so the password may contain ':' itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    user: String,
+    password: String,
+}
+
+impl Credentials {
+    pub fn new(user: &str, password: &str) -> Result<Self, String> {
+        if user.is_empty() || password.is_empty() {
+            return Err("user or password cannot be empty".to_string());
+        }
+
+        Ok(Self {
+            user: user.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    /// Check if user and password match the credentials, in constant time against guessing by timing
+    pub fn verify(&self, user: &[u8], password: &[u8]) -> bool {
+        let user_ok = constant_time::verify_slices_are_equal(self.user.as_bytes(), user).is_ok();
+        let password_ok = constant_time::verify_slices_are_equal(self.password.as_bytes(), password).is_ok();
+
+        user_ok & password_ok
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, password) = s.split_once(':').ok_or("invalid format, expect <user>:<password>")?;
+        Self::new(user, password)
+    }
+}
+
+impl Serialize for Credentials {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&format!("{}:{}", self.user, self.password))
+    }
+}
+
+struct CredentialsVisitor;
+
+impl<'de> Visitor<'de> for CredentialsVisitor {
+    type Value = Credentials;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("<user>:<password>")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Self::Value::from_str(v).map_err(serde::de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Credentials {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_string(CredentialsVisitor)
+    }
+}
@@ -38,6 +38,14 @@ impl Protocol for Direct {
         socket.read_some().await
     }
 
+    fn client_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
+        Ok(buf)
+    }
+
+    fn server_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
+        Ok(buf)
+    }
+
     async fn client_decode(&mut self, socket: &Socket) -> Result<Bytes> {
         socket.read_some().await
     }
@@ -51,6 +51,7 @@ impl Protocol for Dns {
             // NOTE: in order to make it work on relay mode(not set --server-bind), we must pass this value (currently).
             address: self.dns_server.clone(),
             pending_buf: Some(buf),
+            client_hello: None,
         });
 
         Ok(self.get_resolved_result())
@@ -1,24 +1,27 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::{Error, Result};
 use async_trait::async_trait;
-use bytes::{BufMut, Bytes, BytesMut};
-use chacha20poly1305::{
-    aead::{Aead, NewAead, Payload},
-    ChaCha20Poly1305, Key, Nonce,
-};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use crate::{
-    net::{address::Address, socket::Socket},
-    protos::{Protocol, ProtocolType, ResolvedResult},
+    constants, global,
+    net::{
+        address::Address,
+        outbound::{ConnectStatus, ConnectStatusError},
+        socket::Socket,
+    },
+    options::user::User,
+    protos::{Command, Protocol, ProtocolType, ResolvedResult},
     utils,
-    utils::crypto::Crypto,
+    utils::{
+        aead::{AeadAlgorithm, AeadCipher},
+        crypto::Crypto,
+    },
     ServiceType,
 };
 
 const MAX_CHUNK_SIZE: usize = 0x3FFF;
-const SALT_SIZE: usize = 32;
-const KEY_SIZE: usize = 32;
-const NONCE_SIZE: usize = 12;
-const TAG_SIZE: usize = 16;
 const HKDF_INFO: &str = "bp-subkey";
 
 /// # Protocol
@@ -30,6 +33,8 @@ const HKDF_INFO: &str = "bp-subkey";
 /// |   32   |  Variable  | ... |
 /// +--------+------------+-----+
 ///
+/// (Salt is as long as the key of AEAD cipher, Tags below are as long as the tag of AEAD cipher)
+///
 /// DataFrame
 /// +------------+----------------+------------+-----------+---------------+------------+-----------+
 /// | PaddingLen | PaddingLen Tag |  Padding   |  ChunkLen |  ChunkLen Tag |   Chunk    | Chunk Tag |
@@ -38,36 +43,69 @@ const HKDF_INFO: &str = "bp-subkey";
 /// +------------+----------------+------------+-----------+---------------+------------+-----------+
 ///
 /// First Chunk
-/// +------+----------+----------+-------------+
-/// | ATYP | DST.ADDR | DST.PORT |    Data     |
-/// +------+----------+----------+-------------+
-/// |  1   | Variable |    2     |  Variable   |
-/// +------+----------+----------+-------------+
+/// +-----------+-----+------+----------+----------+-------------+
+/// | Timestamp | CMD | ATYP | DST.ADDR | DST.PORT |    Data     |
+/// +-----------+-----+------+----------+----------+-------------+
+/// |     8     |  1  |  1   | Variable |    2     |  Variable   |
+/// +-----------+-----+------+----------+----------+-------------+
+///
+/// First Chunk replied by server
+/// +--------+
+/// | STATUS |
+/// +--------+
+/// |   1    |
+/// +--------+
 ///
 /// # Explain
 ///
 /// * Salt is randomly generated, and is to derive the per-session subkey in HKDF.
-/// * AEAD cipher ChaCha20Poly1305 (RFC 8439) is used to encrypt all DataFrames.
-/// * Nonce is little-endian and counting from 0, each chunk increases the nonce three times.
+/// * AEAD cipher ChaCha20Poly1305 (RFC 8439) is used to encrypt all DataFrames by default, XChaCha20Poly1305 and
+///   AES-256-GCM can be chosen by "erp-xchacha20-poly1305" and "erp-aes-256-gcm".
+/// * Nonce is little-endian and counting from 0, each chunk increases the nonce three times. The nonce is 12 bytes,
+///   or 24 bytes for XChaCha20Poly1305.
 /// * The HMAC-based Extract-and-Expand Key Derivation Function(HKDF) is used for key derivation.
 /// * The HKDF use SHA256 hash function.
 /// * The random salt and info = "bp-subkey" is used to HKDF.
 /// * The length of Chunk Data must <= 0x3FFF.
 /// * Only PaddingLen, ChunkLen, Chunk are encrypted.
+/// * Timestamp is a big-endian Unix timestamp in seconds, the server rejects the session if it differs from local
+///   time by more than 120 seconds.
+/// * The server remembers salts of authenticated sessions for a while, and rejects sessions with a used salt, so
+///   that the first flight captured by others cannot be replayed. New sessions are rejected if too many salts are
+///   remembered within the while, rather than forgetting salts earlier.
+/// * A server with multiple users identifies the user of a session by trying to decrypt the first PaddingLen with
+///   the key of each user, which costs one HKDF and one AEAD decryption of 17 bytes per user.
+/// * CMD = 0x01 requests the server to connect to DST.ADDR. CMD = 0x02 requests the server to accept an incoming
+///   connection from DST.ADDR (Socks5 BIND), the server replies the listening address and then the connected peer
+///   address, each in a separate Chunk.
+/// * STATUS is the result of connecting to DST.ADDR (or listening for CMD = 0x02) on server side, the same as REP
+///   in Socks5 Protocol. The server closes the session after replying a failure. It's not replied over UDP.
 ///
 /// # Reference
 ///
-/// * chacha20poly1305: https://docs.rs/chacha20poly1305/0.8.1/chacha20poly1305/
+/// * chacha20poly1305: https://docs.rs/chacha20poly1305/0.9.1/chacha20poly1305/
+/// * aes-gcm: https://docs.rs/aes-gcm/0.9.4/aes_gcm/
 /// * HKDF: https://docs.rs/hkdf/0.11.0/hkdf/
 #[derive(Clone)]
 pub struct Erp {
     header_sent: bool,
 
+    /// Whether the status is received from server, only for client side
+    status_received: bool,
+
     raw_key: String,
 
+    /// Users with their own keys, only for server side
+    users: Vec<User>,
+
+    /// The user identified by key, only for server side
+    user: Option<String>,
+
+    algorithm: AeadAlgorithm,
+
     salt: Option<Bytes>,
 
-    derived_key: Option<Bytes>,
+    cipher: Option<AeadCipher>,
 
     encrypt_nonce: u128,
 
@@ -77,79 +115,127 @@ pub struct Erp {
 }
 
 impl Erp {
-    pub fn new(key: String, service_type: ServiceType) -> Self {
-        let (salt, derived_key) = match service_type {
+    pub fn new(key: String, algorithm: AeadAlgorithm, service_type: ServiceType) -> Self {
+        let (salt, cipher) = match service_type {
             ServiceType::Server => (None, None),
-            // only client side can generate salt and derived_key
+            // only client side can generate salt and cipher
             // generate on server side will take no effect.
             ServiceType::Client => {
-                let salt = Crypto::random_bytes(SALT_SIZE);
-                let derived_key = Self::derive_key(key.clone(), salt.clone());
-                (Some(salt), Some(derived_key))
+                let salt = Crypto::random_bytes(algorithm.key_size());
+                let cipher = Self::new_cipher(&key, algorithm, salt.clone());
+                (Some(salt), Some(cipher))
             }
         };
 
         Self {
             raw_key: key,
+            users: vec![],
+            user: None,
+            algorithm,
             salt,
-            derived_key,
+            cipher,
             encrypt_nonce: 0,
             decrypt_nonce: 0,
             header_sent: false,
+            status_received: false,
             resolved_result: None,
         }
     }
 
-    fn derive_key(raw_key: String, salt: Bytes) -> Bytes {
-        let derived_key = Crypto::hkdf_sha256(raw_key.into(), salt, HKDF_INFO.as_bytes().into(), KEY_SIZE);
+    /// Accept sessions encrypted with keys of users as well, only for server side
+    pub fn set_users(&mut self, users: Vec<User>) {
+        self.users = users;
+    }
+
+    /// Derive the per-session key from salt and create the cipher, only once for each session
+    fn new_cipher(raw_key: &str, algorithm: AeadAlgorithm, salt: Bytes) -> AeadCipher {
+        let derived_key = Crypto::hkdf_sha256(
+            raw_key.to_string().into(),
+            salt,
+            HKDF_INFO.as_bytes().into(),
+            algorithm.key_size(),
+        );
         log::debug!("encrypt/decrypt key = {}", utils::fmt::ToHex(derived_key.to_vec()));
-        derived_key
+
+        AeadCipher::new(algorithm, &derived_key).expect("derived key should match the key size of algorithm")
     }
 
     fn encrypt(&mut self, plain_text: Bytes) -> Result<Bytes> {
-        let key = Key::from_slice(self.derived_key.as_ref().unwrap());
-        let cipher = ChaCha20Poly1305::new(key);
-
-        let nonce = utils::buffer::num_to_buf_le(self.encrypt_nonce, NONCE_SIZE);
-        let nonce = Nonce::from_slice(&nonce);
+        let nonce = utils::buffer::num_to_buf_le(self.encrypt_nonce, self.algorithm.nonce_size());
 
         log::debug!("encrypt nonce = {}", utils::fmt::ToHex(nonce.to_vec()));
         log::debug!("encrypt plain_text = {}", utils::fmt::ToHex(plain_text.to_vec()));
 
-        let plain_text = Payload::from(&plain_text[..]);
+        let cipher_text = self.cipher.as_ref().unwrap().encrypt(&nonce, &plain_text)?;
+        self.encrypt_nonce += 1;
 
-        if let Ok(cipher_text) = cipher.encrypt(nonce, plain_text) {
-            self.encrypt_nonce += 1;
+        log::debug!("encrypted cipher_text = {}", utils::fmt::ToHex(cipher_text.to_vec()));
 
-            log::debug!("encrypted cipher_text = {}", utils::fmt::ToHex(cipher_text.clone()));
-
-            Ok(cipher_text.into())
-        } else {
-            Err(Error::msg("encrypt failed"))
-        }
+        Ok(cipher_text)
     }
 
     fn decrypt(&mut self, cipher_text: Bytes) -> Result<Bytes> {
-        let key = Key::from_slice(self.derived_key.as_ref().unwrap());
-        let cipher = ChaCha20Poly1305::new(key);
-
-        let nonce = utils::buffer::num_to_buf_le(self.decrypt_nonce, NONCE_SIZE);
-        let nonce = Nonce::from_slice(&nonce);
+        let nonce = utils::buffer::num_to_buf_le(self.decrypt_nonce, self.algorithm.nonce_size());
 
         log::debug!("decrypt nonce = {}", utils::fmt::ToHex(nonce.to_vec()));
         log::debug!("decrypt cipher_text = {}", utils::fmt::ToHex(cipher_text.to_vec()));
 
-        let cipher_text = Payload::from(&cipher_text[..]);
+        let plain_text = self.cipher.as_ref().unwrap().decrypt(&nonce, &cipher_text)?;
+        self.decrypt_nonce += 1;
 
-        if let Ok(plain_text) = cipher.decrypt(nonce, cipher_text) {
-            self.decrypt_nonce += 1;
+        log::debug!("decrypted plain_text = {}", utils::fmt::ToHex(plain_text.to_vec()));
 
-            log::debug!("decrypted plain_text = {}", utils::fmt::ToHex(plain_text.to_vec()));
+        Ok(plain_text)
+    }
 
-            Ok(plain_text.into())
-        } else {
-            Err(Error::msg("decrypt failed"))
+    /// Create the cipher with the key this session is encrypted with, return the decrypted PaddingLen.
+    /// The key is found out by trying to decrypt PaddingLen when there are multiple users.
+    fn identify(&mut self, salt: Bytes, enc_pad_len: Bytes) -> Result<u8> {
+        if self.users.is_empty() {
+            self.cipher = Some(Self::new_cipher(&self.raw_key, self.algorithm, salt));
+            return Ok(self.decrypt(enc_pad_len)?[0]);
+        }
+
+        // the shared key is tried first if set
+        let shared = Some(&self.raw_key)
+            .filter(|key| !key.is_empty())
+            .map(|key| (None, key.clone()));
+        let users = self
+            .users
+            .iter()
+            .map(|user| (Some(user.name.clone()), user.key.clone()));
+        let candidates: Vec<_> = shared.into_iter().chain(users).collect();
+
+        for (user, key) in candidates {
+            self.cipher = Some(Self::new_cipher(&key, self.algorithm, salt.clone()));
+
+            if let Ok(pad_len) = self.decrypt(enc_pad_len.clone()) {
+                self.user = user;
+                return Ok(pad_len[0]);
+            }
         }
+
+        Err(Error::msg("the session is not encrypted with the key of any user"))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+
+    fn check_timestamp(timestamp: u64) -> Result<()> {
+        let diff = Self::now().abs_diff(timestamp);
+
+        if diff > constants::ERP_MAX_TIMESTAMP_DIFF_SECONDS {
+            return Err(Error::msg(format!(
+                "timestamp in header differs from local time by {} seconds, the session may be replayed",
+                diff
+            )));
+        }
+
+        Ok(())
     }
 
     fn get_random_bytes_len(&self, chunk_len: usize) -> usize {
@@ -172,8 +258,10 @@ impl Erp {
         let chunks = utils::buffer::get_chunks(buf, MAX_CHUNK_SIZE);
         let mut enc_chunks = vec![];
 
+        let tag_size = self.algorithm.tag_size();
+
         for chunk_buf in chunks {
-            let mut buf = BytesMut::with_capacity(1 + TAG_SIZE + 255 + 2 + TAG_SIZE + chunk_buf.len() + TAG_SIZE);
+            let mut buf = BytesMut::with_capacity(1 + tag_size + 255 + 2 + tag_size + chunk_buf.len() + tag_size);
 
             // generate random padding
             let pad_len = self.get_random_bytes_len(chunk_buf.len());
@@ -203,20 +291,38 @@ impl Erp {
 
     async fn decode(&mut self, socket: &Socket) -> Result<Bytes> {
         // PaddingLen
-        let enc_pad_len = socket.read_exact(1 + TAG_SIZE).await?;
+        let enc_pad_len = socket.read_exact(1 + self.algorithm.tag_size()).await?;
         let pad_len = self.decrypt(enc_pad_len)?;
-        let pad_len = u8::from_be_bytes([pad_len[0]]);
+
+        self.decode_chunk(socket, pad_len[0]).await
+    }
+
+    /// Decode the first Chunk replied by server, which tells if the server connected to dest address
+    async fn decode_status(&mut self, socket: &Socket) -> Result<()> {
+        let chunk = self.decode(socket).await?;
+        self.status_received = true;
+
+        match chunk.first().map(|status| ConnectStatus::from(*status)) {
+            Some(ConnectStatus::Succeeded) => Ok(()),
+            Some(status) => Err(ConnectStatusError(status).into()),
+            None => Err(Error::msg("STATUS is missing in the first chunk replied by server")),
+        }
+    }
+
+    /// Decode the rest of DataFrame after PaddingLen
+    async fn decode_chunk(&mut self, socket: &Socket, pad_len: u8) -> Result<Bytes> {
+        let tag_size = self.algorithm.tag_size();
 
         // Padding
         let _ = socket.read_exact(pad_len as usize).await?;
 
         // ChunkLen
-        let enc_chunk_len = socket.read_exact(2 + TAG_SIZE).await?;
+        let enc_chunk_len = socket.read_exact(2 + tag_size).await?;
         let chunk_len = self.decrypt(enc_chunk_len)?;
         let chunk_len = u16::from_be_bytes([chunk_len[0], chunk_len[1]]);
 
         // Chunk
-        let enc_chunk = socket.read_exact(chunk_len as usize + TAG_SIZE).await?;
+        let enc_chunk = socket.read_exact(chunk_len as usize + tag_size).await?;
         self.decrypt(enc_chunk)
     }
 }
@@ -235,30 +341,82 @@ impl Protocol for Erp {
         self.resolved_result.as_ref().unwrap()
     }
 
+    fn get_user(&self) -> Option<String> {
+        self.user.clone()
+    }
+
     async fn resolve_dest_addr(&mut self, socket: &Socket) -> Result<&ResolvedResult> {
-        let salt = socket.read_exact(SALT_SIZE).await?;
-        self.derived_key = Some(Self::derive_key(self.raw_key.clone(), salt));
+        let salt = socket.read_exact(self.algorithm.key_size()).await?;
+        let enc_pad_len = socket.read_exact(1 + self.algorithm.tag_size()).await?;
 
-        let chunk = self.decode(socket).await?;
+        let pad_len = self.identify(salt.clone(), enc_pad_len)?;
+        let mut chunk = self.decode_chunk(socket, pad_len).await?;
 
+        if chunk.len() < 8 {
+            return Err(Error::msg("timestamp in header is missing"));
+        }
+
+        Self::check_timestamp(chunk.get_u64())?;
+
+        // only remember salts of authenticated sessions, random data cannot pollute the filter
+        global::erp_remember_salt(&salt)?;
+
+        if chunk.is_empty() {
+            return Err(Error::msg("CMD in header is missing"));
+        }
+
+        let command = Command::try_from(chunk.get_u8())?;
         let (address, pending_buf) = Address::from_bytes(chunk)?;
 
         self.set_resolved_result(ResolvedResult {
-            protocol: ProtocolType::Erp,
+            protocol: command.to_protocol(ProtocolType::Erp),
             address,
             pending_buf,
+            client_hello: None,
         });
 
         Ok(self.get_resolved_result())
     }
 
+    async fn reply_connect_status(&mut self, socket: &Socket, status: ConnectStatus) -> Result<()> {
+        if socket.is_udp() {
+            return Ok(());
+        }
+
+        let buf = self.server_encode_buf(Bytes::from(vec![status.into()]))?;
+        socket.send(&buf).await?;
+
+        Ok(())
+    }
+
+    async fn recv_connect_status(&mut self, socket: &Socket) -> Result<()> {
+        // send header alone, the server replies once dest address is connected
+        if !self.header_sent {
+            let buf = self.client_encode_buf(Bytes::new())?;
+            socket.send(&buf).await?;
+        }
+
+        self.decode_status(socket).await
+    }
+
     async fn client_encode(&mut self, socket: &Socket) -> Result<Bytes> {
         let buf = socket.read_some().await?;
+        self.client_encode_buf(buf)
+    }
+
+    async fn server_encode(&mut self, socket: &Socket) -> Result<Bytes> {
+        let buf = socket.read_some().await?;
+        self.server_encode_buf(buf)
+    }
+
+    fn client_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
         let mut data = BytesMut::with_capacity(buf.len() + 200);
 
         // attach header
         if !self.header_sent {
             let resolved = self.get_resolved_result();
+            data.put_u64(Self::now());
+            data.put_u8(Command::from_protocol(&resolved.protocol).into());
             data.put(resolved.address.as_bytes());
         }
 
@@ -269,7 +427,7 @@ impl Protocol for Erp {
         if self.header_sent {
             Ok(data)
         } else {
-            let mut buf = BytesMut::with_capacity(SALT_SIZE + data.len());
+            let mut buf = BytesMut::with_capacity(self.algorithm.key_size() + data.len());
 
             buf.put(self.salt.as_ref().unwrap().clone());
             buf.put(data);
@@ -280,12 +438,16 @@ impl Protocol for Erp {
         }
     }
 
-    async fn server_encode(&mut self, socket: &Socket) -> Result<Bytes> {
-        let buf = socket.read_some().await?;
+    fn server_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
         self.encode(buf)
     }
 
     async fn client_decode(&mut self, socket: &Socket) -> Result<Bytes> {
+        // data was sent along with the header without waiting for the status
+        if !self.status_received && !socket.is_udp() {
+            self.decode_status(socket).await?;
+        }
+
         self.decode(socket).await
     }
 
@@ -2,23 +2,48 @@ use std::str::FromStr;
 
 use anyhow::{Error, Result};
 use async_trait::async_trait;
-use bytes::{Bytes, BytesMut};
-use httparse::Request;
-use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
+use bytes::{BufMut, Bytes, BytesMut};
+use httparse::{Request, Status};
 use url::Url;
 
 use crate::{
     net::{
         address::{Address, Host},
+        outbound::ConnectStatus,
         socket::Socket,
     },
-    protos::{Protocol, ProtocolType, ResolvedResult},
+    protos::{Credentials, Protocol, ProtocolType, ResolvedResult},
 };
 
+const PROXY_AUTHENTICATION_REQUIRED_RESPONSE: &[u8] = b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+    Proxy-Authenticate: Basic realm=\"bp\"\r\n\
+    Content-Length: 0\r\n\
+    Connection: close\r\n\r\n";
+
+const MAX_HEADERS: usize = 64;
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Body state of the plain HTTP request being relayed
+#[derive(Debug, Clone, Copy)]
+enum HttpBody {
+    /// No body, or the body is relayed completely
+    None,
+    /// Remaining bytes of body delimited by Content-Length
+    Length(usize),
+    /// Remaining bytes of current chunk(trailing CRLF included), 0 means chunk-size line is expected
+    Chunked(usize),
+    /// Trailer section after the last chunk
+    Trailers,
+    /// Body cannot be delimited, e.g, Upgrade, relay everything as is
+    Tunnel,
+}
+
 #[derive(Clone)]
 pub struct Http {
     resolved_result: Option<ResolvedResult>,
     basic_auth: Option<HttpBasicAuth>,
+    body: HttpBody,
+    is_request_read: bool,
 }
 
 impl Http {
@@ -26,17 +51,231 @@ impl Http {
         Self {
             resolved_result: None,
             basic_auth,
+            body: HttpBody::None,
+            is_request_read: false,
+        }
+    }
+
+    /// Read the next piece of plain HTTP requests. Request header is rewritten to origin-form and its
+    /// dest address is resolved again, the following pieces of body are relayed to the same address.
+    pub async fn read_request(&mut self, socket: &Socket) -> Result<ResolvedResult> {
+        let buf = match self.body {
+            HttpBody::None => return self.read_request_header(socket).await,
+            HttpBody::Length(len) => {
+                let buf = Self::read_at_most(socket, len).await?;
+
+                self.body = match len - buf.len() {
+                    0 => HttpBody::None,
+                    len => HttpBody::Length(len),
+                };
+
+                buf
+            }
+            HttpBody::Chunked(0) => {
+                let line = Self::read_line(socket).await?;
+
+                let size = match httparse::parse_chunk_size(&line) {
+                    Ok(Status::Complete((_, size))) => size as usize,
+                    _ => return Err(Error::msg("invalid chunk size line of chunked body")),
+                };
+
+                self.body = match size {
+                    0 => HttpBody::Trailers,
+                    size => HttpBody::Chunked(size + 2),
+                };
+
+                line
+            }
+            HttpBody::Chunked(len) => {
+                let buf = Self::read_at_most(socket, len).await?;
+                self.body = HttpBody::Chunked(len - buf.len());
+                buf
+            }
+            HttpBody::Trailers => {
+                let line = Self::read_line(socket).await?;
+
+                // an empty line terminates the trailer section
+                if &line[..] == b"\r\n" {
+                    self.body = HttpBody::None;
+                }
+
+                line
+            }
+            HttpBody::Tunnel => socket.read_some().await?,
+        };
+
+        Ok(ResolvedResult {
+            pending_buf: Some(buf),
+            client_hello: None,
+            ..self.get_resolved_result().clone()
+        })
+    }
+
+    async fn read_request_header(&mut self, socket: &Socket) -> Result<ResolvedResult> {
+        let mut buf = BytesMut::with_capacity(1024);
+
+        loop {
+            socket.read_into(&mut buf).await?;
+
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut req = Request::new(&mut headers);
+
+            let header_len = match req.parse(&buf[..])? {
+                Status::Complete(len) => len,
+                Status::Partial => {
+                    if buf.len() > MAX_HEADER_SIZE {
+                        return Err(Error::msg(format!(
+                            "request header is larger than {} bytes",
+                            MAX_HEADER_SIZE
+                        )));
+                    }
+                    // waiting request frame complete
+                    continue;
+                }
+            };
+
+            // Proxy Authorization check
+            if let Some(auth) = &self.basic_auth {
+                if let Err(err) = Self::proxy_authorization_verify(&req, auth) {
+                    socket.send(PROXY_AUTHENTICATION_REQUIRED_RESPONSE).await?;
+                    return Err(err);
+                }
+            }
+
+            if req.method.unwrap().to_uppercase() == "CONNECT" {
+                return Err(Error::msg("CONNECT is not allowed after plain HTTP requests"));
+            }
+
+            // the first request is resolved already, its address may be adjusted, e.g, port of iptables REDIRECT
+            let address = if self.is_request_read {
+                Self::parse_dest_addr(&req)?
+            } else {
+                self.get_resolved_result().address.clone()
+            };
+
+            let header = Self::rewrite_header(&req);
+
+            self.body = Self::parse_body(&req)?;
+            self.is_request_read = true;
+
+            // data followed by header should be read again
+            socket.cache(buf.split_off(header_len).freeze());
+
+            self.set_resolved_result(ResolvedResult {
+                protocol: ProtocolType::Http,
+                address,
+                pending_buf: None,
+                client_hello: None,
+            });
+
+            return Ok(ResolvedResult {
+                pending_buf: Some(header),
+                client_hello: None,
+                ..self.get_resolved_result().clone()
+            });
+        }
+    }
+
+    async fn read_at_most(socket: &Socket, len: usize) -> Result<Bytes> {
+        let mut buf = socket.read_some().await?;
+
+        if buf.len() > len {
+            socket.cache(buf.split_off(len));
+        }
+
+        Ok(buf)
+    }
+
+    async fn read_line(socket: &Socket) -> Result<Bytes> {
+        let mut buf = BytesMut::new();
+
+        loop {
+            if let Some(pos) = buf.windows(2).position(|item| item == b"\r\n") {
+                socket.cache(buf.split_off(pos + 2).freeze());
+                return Ok(buf.freeze());
+            }
+
+            if buf.len() > MAX_HEADER_SIZE {
+                return Err(Error::msg(format!("line is longer than {} bytes", MAX_HEADER_SIZE)));
+            }
+
+            socket.read_into(&mut buf).await?;
         }
     }
 
-    fn basic_authorization_verify(req: &Request, auth: &HttpBasicAuth) -> Result<()> {
-        let auth_header = req
-            .headers
+    fn find_header<'a>(req: &'a Request, name: &str) -> Option<&'a [u8]> {
+        req.headers
             .iter()
-            .find(|item| item.name.to_uppercase() == "AUTHORIZATION")
-            .ok_or_else(|| Error::msg("authorization required but Authorization Header is not found"))?;
+            .find(|item| item.name.eq_ignore_ascii_case(name))
+            .map(|item| item.value)
+    }
+
+    fn parse_dest_addr(req: &Request) -> Result<Address> {
+        let addr = match Url::parse(req.path.unwrap()) {
+            Ok(v) => {
+                let host = v
+                    .host()
+                    .ok_or_else(|| Error::msg(format!("no host in request uri {}", v)))?
+                    .to_string();
+                let port = v.port_or_known_default().unwrap_or(80);
+
+                Address::new(Host::Name(host), port)
+            }
+            Err(err) => {
+                // fallback to read Host header
+                match Self::find_header(req, "Host") {
+                    Some(v) => {
+                        let host = String::from_utf8(v.to_vec())?;
+
+                        // Host header maybe <host:port>
+                        if host.contains(':') {
+                            Address::from_str(&host).map_err(|err| Error::msg(err.to_string()))?
+                        } else {
+                            Address::new(Host::Name(host), 80)
+                        }
+                    }
+                    None => return Err(err.into()),
+                }
+            }
+        };
+
+        Ok(addr)
+    }
+
+    fn parse_body(req: &Request) -> Result<HttpBody> {
+        if let Some(value) = Self::find_header(req, "Transfer-Encoding") {
+            let value = String::from_utf8_lossy(value).to_lowercase();
+
+            return match value.trim_end().ends_with("chunked") {
+                true => Ok(HttpBody::Chunked(0)),
+                false => Ok(HttpBody::Tunnel),
+            };
+        }
+
+        // protocol is switched after this request, e.g, WebSocket
+        if Self::find_header(req, "Upgrade").is_some() {
+            return Ok(HttpBody::Tunnel);
+        }
+
+        if let Some(value) = Self::find_header(req, "Content-Length") {
+            let len = String::from_utf8_lossy(value)
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| Error::msg("invalid Content-Length"))?;
+
+            if len > 0 {
+                return Ok(HttpBody::Length(len));
+            }
+        }
+
+        Ok(HttpBody::None)
+    }
 
-        let value = String::from_utf8(auth_header.value.to_vec())?;
+    fn proxy_authorization_verify(req: &Request, auth: &HttpBasicAuth) -> Result<()> {
+        let auth_header = Self::find_header(req, "Proxy-Authorization")
+            .ok_or_else(|| Error::msg("authorization required but Proxy-Authorization Header is not found"))?;
+
+        let value = String::from_utf8(auth_header.to_vec())?;
         let mut split = value.split(' ');
 
         let auth_type = split
@@ -57,23 +296,113 @@ impl Http {
         let auth_credentials = String::from_utf8(auth_credentials)
             .map_err(|_| Error::msg("invalid authorization, credentials is not base64 encoded utf-8 string"))?;
 
-        let mut split = auth_credentials.split(':');
-
-        let user = split
-            .next()
-            .ok_or_else(|| Error::msg("invalid authorization, user is not found"))?;
-
-        let password = split
-            .next()
+        let (user, password) = auth_credentials
+            .split_once(':')
             .ok_or_else(|| Error::msg("invalid authorization, password is not found"))?;
 
         // compare
-        if auth.user != user || auth.password != password {
+        if !auth.verify(user.as_bytes(), password.as_bytes()) {
             return Err(Error::msg("invalid authorization, mismatch user or password"));
         }
 
         Ok(())
     }
+
+    /// Build a response with a short plain text body tells why the request cannot be relayed
+    fn build_error_response(status: ConnectStatus, addr: &Address) -> Bytes {
+        let (code, reason) = match status {
+            ConnectStatus::NotAllowed => ("403 Forbidden", "destination is not allowed"),
+            ConnectStatus::TimedOut => ("504 Gateway Timeout", "connection timed out"),
+            ConnectStatus::HostUnreachable => ("502 Bad Gateway", "host is unreachable or cannot be resolved"),
+            ConnectStatus::NetworkUnreachable => ("502 Bad Gateway", "network is unreachable"),
+            ConnectStatus::ConnectionRefused => ("502 Bad Gateway", "connection refused"),
+            _ => ("502 Bad Gateway", "connection closed unexpectedly"),
+        };
+
+        let body = format!("bp cannot relay request to {} due to: {}\n", addr, reason);
+
+        let resp = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            code,
+            body.len(),
+            body
+        );
+
+        Bytes::from(resp)
+    }
+
+    /// Split absolute-URI into authority and origin-form, e.g, http://example.com/a?b => (example.com, /a?b)
+    fn split_absolute_uri(path: &str) -> Option<(&str, String)> {
+        let (_, rest) = path.split_once("://")?;
+
+        let (authority, origin) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+
+        // drop userinfo
+        let authority = authority.rsplit('@').next().unwrap();
+
+        let origin = match origin.starts_with('/') {
+            true => origin.to_string(),
+            false => format!("/{}", origin),
+        };
+
+        Some((authority, origin))
+    }
+
+    /// Rebuild request header for the destination:
+    /// 1. absolute-URI of request line is rewritten to origin-form, Host is replaced by its authority.
+    /// 2. Proxy-Authorization and Proxy-Connection are removed, they are for bp only.
+    fn rewrite_header(req: &Request) -> Bytes {
+        let path = req.path.unwrap();
+        let absolute_uri = Self::split_absolute_uri(path);
+
+        let mut buf = BytesMut::with_capacity(1024);
+
+        buf.put_slice(
+            format!(
+                "{} {} HTTP/1.{}\r\n",
+                req.method.unwrap(),
+                absolute_uri.as_ref().map(|(_, origin)| origin.as_str()).unwrap_or(path),
+                req.version.unwrap()
+            )
+            .as_bytes(),
+        );
+
+        let mut put_header = |name: &str, value: &[u8]| {
+            buf.put_slice(name.as_bytes());
+            buf.put_slice(b": ");
+            buf.put_slice(value);
+            buf.put_slice(b"\r\n");
+        };
+
+        let mut is_host_put = false;
+
+        for header in req.headers.iter() {
+            let name = header.name.to_uppercase();
+
+            if name == "PROXY-AUTHORIZATION" || name == "PROXY-CONNECTION" {
+                continue;
+            }
+
+            if name == "HOST" {
+                if let Some((authority, _)) = &absolute_uri {
+                    put_header(header.name, authority.as_bytes());
+                    is_host_put = true;
+                    continue;
+                }
+            }
+
+            put_header(header.name, header.value);
+        }
+
+        if let Some((authority, _)) = &absolute_uri {
+            if !is_host_put {
+                put_header("Host", authority.as_bytes());
+            }
+        }
+
+        buf.put_slice(b"\r\n");
+        buf.freeze()
+    }
 }
 
 #[async_trait]
@@ -96,77 +425,53 @@ impl Protocol for Http {
         loop {
             socket.read_into(&mut buf).await?;
 
-            let mut headers = [httparse::EMPTY_HEADER; 16];
-            let mut req = httparse::Request::new(&mut headers);
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut req = Request::new(&mut headers);
 
             let status = req.parse(&buf[..])?;
 
             // waiting request frame complete
             if !status.is_complete() {
+                if buf.len() > MAX_HEADER_SIZE {
+                    return Err(Error::msg(format!(
+                        "request header is larger than {} bytes",
+                        MAX_HEADER_SIZE
+                    )));
+                }
                 continue;
             }
 
             let path = req.path.unwrap();
             let method = req.method.unwrap();
 
-            if method.to_uppercase() == "CONNECT" {
-                // TODO: Proxy Authorization
+            // Proxy Authorization check
+            if let Some(auth) = &self.basic_auth {
+                if let Err(err) = Self::proxy_authorization_verify(&req, auth) {
+                    socket.send(PROXY_AUTHENTICATION_REQUIRED_RESPONSE).await?;
+                    return Err(err);
+                }
+            }
 
+            if method.to_uppercase() == "CONNECT" {
                 // for HTTP proxy tunnel requests
                 let addr = Address::from_str(path).map_err(|err| Error::msg(err.to_string()))?;
-                let resp = Bytes::from_static(b"HTTP/1.1 200 Connection Established\r\n\r\n");
-
-                socket.send(&resp).await?;
 
+                // reply is deferred until the outbound connection is made, see reply_connect_status()
                 self.set_resolved_result(ResolvedResult {
                     protocol: ProtocolType::HttpProxy,
                     address: addr,
                     pending_buf: None,
+                    client_hello: None,
                 });
 
                 return Ok(self.get_resolved_result());
             } else {
-                // for direct HTTP requests
-
-                // Basic Authorization check
-                if let Some(auth) = &self.basic_auth {
-                    if let Err(err) = Self::basic_authorization_verify(&req, auth) {
-                        socket.send(b"HTTP/1.1 401 Unauthorized\r\n\r\n").await?;
-                        return Err(err);
-                    }
-                }
-
-                let addr = match Url::parse(path) {
-                    Ok(v) => {
-                        let host = v.host().unwrap().to_string();
-                        let port = v.port().unwrap_or(80);
-
-                        Address::new(Host::Name(host), port)
-                    }
-                    Err(err) => {
-                        // fallback to read Host header
-                        let host_header = headers.iter().find(|&item| item.name.to_uppercase() == "HOST");
-
-                        match host_header {
-                            Some(v) => {
-                                let host = String::from_utf8(v.value.to_vec()).unwrap();
-
-                                // Host header maybe <host:port>
-                                if host.contains(':') {
-                                    Address::from_str(&host).map_err(|err| Error::msg(err.to_string()))?
-                                } else {
-                                    Address::new(Host::Name(host), 80)
-                                }
-                            }
-                            None => return Err(err.into()),
-                        }
-                    }
-                };
-
+                // for direct HTTP requests, following requests are read by read_request()
                 self.set_resolved_result(ResolvedResult {
                     protocol: ProtocolType::Http,
-                    address: addr,
+                    address: Self::parse_dest_addr(&req)?,
                     pending_buf: None,
+                    client_hello: None,
                 });
 
                 return Ok(self.get_resolved_result());
@@ -174,6 +479,26 @@ impl Protocol for Http {
         }
     }
 
+    async fn reply_connect_status(&mut self, socket: &Socket, status: ConnectStatus) -> Result<()> {
+        let resolved = self.get_resolved_result();
+
+        match status {
+            ConnectStatus::Succeeded => {
+                // plain HTTP request is replied by the destination
+                if matches!(resolved.protocol, ProtocolType::HttpProxy) {
+                    socket.send(b"HTTP/1.1 200 Connection Established\r\n\r\n").await?;
+                }
+            }
+            _ => {
+                socket
+                    .send(&Self::build_error_response(status, &resolved.address))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn client_encode(&mut self, _socket: &Socket) -> Result<Bytes> {
         unimplemented!()
     }
@@ -191,62 +516,5 @@ impl Protocol for Http {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct HttpBasicAuth {
-    user: String,
-    password: String,
-}
-
-impl FromStr for HttpBasicAuth {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut split = s.split(':');
-        let user = split.next().ok_or("invalid format of user")?;
-        let password = split.next().ok_or("invalid format of password")?;
-
-        if user.is_empty() || password.is_empty() {
-            return Err("user or password cannot be empty".to_string());
-        }
-
-        Ok(Self {
-            user: user.to_string(),
-            password: password.to_string(),
-        })
-    }
-}
-
-impl Serialize for HttpBasicAuth {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(&format!("{}:{}", self.user, self.password))
-    }
-}
-
-struct HttpBasicAuthVisitor;
-
-impl<'de> Visitor<'de> for HttpBasicAuthVisitor {
-    type Value = HttpBasicAuth;
-
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("<user>:<password>")
-    }
-
-    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-    where
-        E: serde::de::Error,
-    {
-        Self::Value::from_str(v).map_err(serde::de::Error::custom)
-    }
-}
-
-impl<'de> Deserialize<'de> for HttpBasicAuth {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        deserializer.deserialize_string(HttpBasicAuthVisitor)
-    }
-}
+/// Credentials of HTTP Basic Authorization
+pub type HttpBasicAuth = Credentials;
@@ -1,6 +1,6 @@
 use anyhow::{Error, Result};
 use async_trait::async_trait;
-use bytes::{Buf, Bytes};
+use bytes::{Bytes, BytesMut};
 
 use crate::{
     net::{
@@ -8,9 +8,14 @@ use crate::{
         socket::Socket,
     },
     protos::{Protocol, ProtocolType, ResolvedResult},
-    utils,
+    utils::{self, tls},
 };
 
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+
+/// The max length of TLSPlaintext.fragment
+const MAX_RECORD_LEN: usize = 1 << 14;
+
 #[derive(Clone, Default)]
 pub struct Https {
     resolved_result: Option<ResolvedResult>,
@@ -31,113 +36,94 @@ impl Protocol for Https {
     }
 
     async fn resolve_dest_addr(&mut self, socket: &Socket) -> Result<&ResolvedResult> {
-        let content_type = socket.read_exact(1).await?;
-
-        if content_type[0] != 0x16 {
-            return Err(Error::msg(format!(
-                "Content Type must be Handshake (0x16), but got {:#04x}",
-                content_type[0]
-            )));
-        }
-
-        let version = socket.read_exact(2).await?;
-
-        if version[0] != 0x03 && version[1] != 0x01 {
-            return Err(Error::msg(format!(
-                "Version must be TLS 1.0 (0x0301), but got {}",
-                utils::fmt::ToHex(version.to_vec())
-            )));
-        }
-
-        let mut len_buf = socket.read_exact(2).await?;
-        let mut handshake_buf = socket.read_exact(len_buf.get_u16() as usize).await?;
-
-        let handshake_type = handshake_buf.get_u8();
-
-        if handshake_type != 0x01 {
-            return Err(Error::msg(format!(
-                "Handshake Type must be Client Hello (0x01), but got {:#04x}",
-                handshake_type
-            )));
-        }
-
-        // skip Length(3 bytes)
-        handshake_buf.advance(3);
-
-        let version = handshake_buf.slice(0..2);
+        // ClientHello may be fragmented into multiple records, collect fragments until the message is complete
+        let mut handshake = BytesMut::new();
+        let mut message_len = None;
 
-        if version[0] != 0x03 && version[1] != 0x03 {
-            return Err(Error::msg(format!(
-                "Version must be TLS 1.2 (0x0303), but got {}",
-                utils::fmt::ToHex(version.to_vec())
-            )));
-        }
+        loop {
+            // TLS Record
+            // +--------------+---------+--------+----------+
+            // | Content Type | Version | Length | Fragment |
+            // +--------------+---------+--------+----------+
+            // |      1       |    2    |   2    |  Length  |
+            // +--------------+---------+--------+----------+
+
+            let header = socket.read_exact(5).await?;
+
+            if header[0] != CONTENT_TYPE_HANDSHAKE {
+                return Err(Error::msg(format!(
+                    "Content Type must be Handshake (0x16), but got {:#04x}",
+                    header[0]
+                )));
+            }
 
-        // skip Version(2 bytes) and Random(32 bytes)
-        handshake_buf.advance(34);
+            // record version can be any of 0x0300 ~ 0x0304
+            if header[1] != 0x03 || header[2] > 0x04 {
+                return Err(Error::msg(format!(
+                    "Version must be 0x0300 ~ 0x0304, but got {}",
+                    utils::fmt::ToHex(header[1..3].to_vec())
+                )));
+            }
 
-        let session_id_len = handshake_buf.get_u8();
+            let fragment_len = u16::from_be_bytes([header[3], header[4]]) as usize;
 
-        // skip Session ID(n bytes)
-        handshake_buf.advance(session_id_len as usize);
+            if fragment_len == 0 || fragment_len > MAX_RECORD_LEN {
+                return Err(Error::msg(format!(
+                    "Record Length must be 1 ~ {}, but got {}",
+                    MAX_RECORD_LEN, fragment_len
+                )));
+            }
 
-        let cipher_suites_len = handshake_buf.get_u16();
+            handshake.extend_from_slice(&socket.read_exact(fragment_len).await?);
 
-        // skip Cipher Suites(n bytes)
-        handshake_buf.advance(cipher_suites_len as usize);
+            if message_len.is_none() && handshake.len() >= 4 {
+                if handshake[0] != tls::HANDSHAKE_TYPE_CLIENT_HELLO {
+                    return Err(Error::msg(format!(
+                        "Handshake Type must be Client Hello (0x01), but got {:#04x}",
+                        handshake[0]
+                    )));
+                }
 
-        let compression_methods_len = handshake_buf.get_u8();
+                let len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
 
-        // skip Compression Methods(n bytes)
-        handshake_buf.advance(compression_methods_len as usize);
+                if len > tls::MAX_CLIENT_HELLO_LEN {
+                    return Err(Error::msg(format!(
+                        "Client Hello Length({}) exceeds {} bytes",
+                        len,
+                        tls::MAX_CLIENT_HELLO_LEN
+                    )));
+                }
 
-        let extensions_len = handshake_buf.get_u16() as usize;
-        let ext_buf = handshake_buf;
+                message_len = Some(len + 4);
+            }
 
-        if extensions_len != ext_buf.len() {
-            return Err(Error::msg(format!(
-                "Extension Length({}) mismatch the remaining buffer size({})",
-                extensions_len,
-                ext_buf.len()
-            )));
+            if let Some(len) = message_len {
+                if handshake.len() >= len {
+                    handshake.truncate(len);
+                    break;
+                }
+            }
         }
 
-        // find Extension: server_name
-        let mut cur = 0;
-
-        loop {
-            let ext_type = ext_buf.slice(cur..cur + 2);
-            let ext_len = ext_buf[cur + 3] as usize;
-
-            // server_name extension
-            if ext_type[0] == 0x00 && ext_type[1] == 0x00 {
-                let cur = cur + 4;
-
-                let mut sni = ext_buf.slice(cur..cur + ext_len);
-                let _server_name_list_len = sni.get_u16();
-                let _server_name_type = sni.get_u8();
-                let server_name_len = sni.get_u16() as usize;
-                let server_name = String::from_utf8(sni.slice(0..server_name_len).to_vec())?;
-
-                self.set_resolved_result(ResolvedResult {
-                    protocol: ProtocolType::Https,
-                    address: Address::new(Host::Name(server_name), 443),
-                    pending_buf: None,
-                });
-
-                return Ok(self.get_resolved_result());
-            }
+        let client_hello = tls::parse_client_hello(&handshake)?;
 
-            // skip Type and Length
-            cur += 4;
-            cur += ext_len;
+        let server_name = match client_hello.server_name.clone() {
+            Some(server_name) => server_name,
+            None => return Err(Error::msg("server_name Extension not found")),
+        };
 
-            if cur >= extensions_len {
-                break;
-            }
+        if !client_hello.alpn.is_empty() {
+            log::debug!("[{}] sniffed alpn: {:?}", socket.peer_addr(), client_hello.alpn);
         }
 
-        Err(Error::msg("server_name Extension not found"))
+        self.set_resolved_result(ResolvedResult {
+            protocol: ProtocolType::Https,
+            address: Address::new(Host::Name(server_name), 443),
+            pending_buf: None,
+            client_hello: Some(client_hello),
+        });
+
+        Ok(self.get_resolved_result())
     }
 
     async fn client_encode(&mut self, _socket: &Socket) -> Result<Bytes> {
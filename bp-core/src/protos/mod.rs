@@ -1,36 +1,47 @@
 use std::str::{self, FromStr};
 
-use anyhow::Result;
+use anyhow::{Error, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
 
 use crate::{
-    net::{address::Address, socket::Socket},
-    ServiceType,
+    net::{address::Address, outbound::ConnectStatus, socket::Socket},
+    utils::{aead::AeadAlgorithm, tls::ClientHello},
+    ServerOptions, ServiceType,
 };
 
+mod credentials;
 mod direct;
 mod dns;
 mod erp;
 mod http;
 mod https;
 mod plain;
+mod quic;
+mod shadowsocks;
 mod socks;
+mod socks4;
 
+pub use credentials::Credentials;
 pub use direct::Direct;
 pub use dns::Dns;
 pub use erp::Erp;
 pub use http::{Http, HttpBasicAuth};
 pub use https::Https;
 pub use plain::Plain;
-pub use socks::Socks;
+pub use quic::Quic;
+pub use shadowsocks::{Shadowsocks, ShadowsocksMethod};
+pub use socks::{Socks, SocksAuth};
+pub use socks4::Socks4;
 
 #[derive(Debug, Clone)]
 pub struct ResolvedResult {
     pub protocol: ProtocolType,
     pub address: Address,
     pub pending_buf: Option<Bytes>,
+    /// SNI and ALPN sniffed from TLS ClientHello
+    pub client_hello: Option<ClientHello>,
 }
 
 impl ResolvedResult {
@@ -47,12 +58,71 @@ pub enum ProtocolType {
     Http,
     HttpProxy,
     Https,
+    Mux,
     Plain,
+    Quic,
+    Shadowsocks,
     Socks,
+    SocksBind,
+    SocksUdpAssociate,
+}
+
+/// Command in the header of bp protocols(erp and plain), tells bp server what to do with DST.ADDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Connect to DST.ADDR and relay data
+    Connect,
+    /// Accept an incoming connection from DST.ADDR, for Socks5 BIND
+    Bind,
+    /// Start a mux session on the connection, DST.ADDR is ignored
+    Mux,
+}
+
+impl Command {
+    /// The command bp client requests for a resolved result
+    pub fn from_protocol(protocol: &ProtocolType) -> Self {
+        match protocol {
+            ProtocolType::SocksBind => Self::Bind,
+            ProtocolType::Mux => Self::Mux,
+            _ => Self::Connect,
+        }
+    }
+
+    /// The protocol type bp server resolves, connect is handled by the protocol parsing the header
+    pub fn to_protocol(self, protocol: ProtocolType) -> ProtocolType {
+        match self {
+            Self::Connect => protocol,
+            Self::Bind => ProtocolType::SocksBind,
+            Self::Mux => ProtocolType::Mux,
+        }
+    }
+}
+
+impl From<Command> for u8 {
+    fn from(value: Command) -> u8 {
+        match value {
+            Command::Connect => 1,
+            Command::Bind => 2,
+            Command::Mux => 3,
+        }
+    }
+}
+
+impl TryFrom<u8> for Command {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(Command::Connect),
+            2 => Ok(Command::Bind),
+            3 => Ok(Command::Mux),
+            _ => Err(Error::msg(format!("CMD {:#04x} in header is not supported", value))),
+        }
+    }
 }
 
 #[async_trait]
-pub trait Protocol: dyn_clone::DynClone {
+pub trait Protocol: dyn_clone::DynClone + Send + Sync {
     fn get_name(&self) -> String;
 
     fn set_resolved_result(&mut self, _res: ResolvedResult) {
@@ -65,10 +135,43 @@ pub trait Protocol: dyn_clone::DynClone {
 
     async fn resolve_dest_addr(&mut self, socket: &Socket) -> Result<&ResolvedResult>;
 
+    /// The user identified on server side during resolving dest address, if the protocol supports multiple users
+    fn get_user(&self) -> Option<String> {
+        None
+    }
+
+    /// Reply the status of connecting to dest address, only for protocols which should reply
+    /// after outbound connection is made, e.g, Socks5 CONNECT
+    async fn reply_connect_status(&mut self, _socket: &Socket, _status: ConnectStatus) -> Result<()> {
+        Ok(())
+    }
+
+    /// Receive the status of connecting to dest address on client side, only for protocols which reply it
+    /// from server side, e.g, erp
+    async fn recv_connect_status(&mut self, _socket: &Socket) -> Result<()> {
+        Ok(())
+    }
+
     async fn client_encode(&mut self, socket: &Socket) -> Result<Bytes>;
 
     async fn server_encode(&mut self, socket: &Socket) -> Result<Bytes>;
 
+    /// Encode a buffer which is not read from socket on client side
+    fn client_encode_buf(&mut self, _buf: Bytes) -> Result<Bytes> {
+        Err(Error::msg(format!(
+            "{} cannot encode a buffer on client side",
+            self.get_name()
+        )))
+    }
+
+    /// Encode a buffer which is not read from socket on server side
+    fn server_encode_buf(&mut self, _buf: Bytes) -> Result<Bytes> {
+        Err(Error::msg(format!(
+            "{} cannot encode a buffer on server side",
+            self.get_name()
+        )))
+    }
+
     async fn client_decode(&mut self, socket: &Socket) -> Result<Bytes>;
 
     async fn server_decode(&mut self, socket: &Socket) -> Result<Bytes>;
@@ -81,24 +184,38 @@ pub type DynProtocol = Box<dyn Protocol + Send + Sync + 'static>;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EncryptionMethod {
     Plain,
-    EncryptRandomPadding,
+    EncryptRandomPadding(AeadAlgorithm),
+    Shadowsocks(ShadowsocksMethod),
 }
 
 impl str::FromStr for EncryptionMethod {
     type Err = String;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
+        let s = s.to_lowercase();
+
+        // erp with a specified AEAD algorithm, e.g, "erp-aes-256-gcm"
+        if let Some(algorithm) = s.strip_prefix("erp-") {
+            return Ok(Self::EncryptRandomPadding(algorithm.parse()?));
+        }
+
+        match s.as_str() {
             "plain" => Ok(Self::Plain),
-            "~" | "" | "erp" => Ok(Self::EncryptRandomPadding),
-            _ => Err(format!("{} is not supported, available methods are: plain, erp", s)),
+            "~" | "" | "erp" => Ok(Self::EncryptRandomPadding(AeadAlgorithm::default())),
+            "chacha20-ietf-poly1305" => Ok(Self::Shadowsocks(ShadowsocksMethod::Chacha20IetfPoly1305)),
+            "aes-256-gcm" => Ok(Self::Shadowsocks(ShadowsocksMethod::Aes256Gcm)),
+            _ => Err(format!(
+                "{} is not supported, available methods are: plain, erp, erp-xchacha20-poly1305, erp-aes-256-gcm, \
+                 chacha20-ietf-poly1305, aes-256-gcm",
+                s
+            )),
         }
     }
 }
 
 impl Default for EncryptionMethod {
     fn default() -> Self {
-        Self::EncryptRandomPadding
+        Self::EncryptRandomPadding(AeadAlgorithm::default())
     }
 }
 
@@ -106,7 +223,10 @@ impl ToString for EncryptionMethod {
     fn to_string(&self) -> String {
         match self {
             Self::Plain => "plain".to_string(),
-            Self::EncryptRandomPadding => "erp".to_string(),
+            Self::EncryptRandomPadding(algorithm) if *algorithm == AeadAlgorithm::default() => "erp".to_string(),
+            Self::EncryptRandomPadding(algorithm) => format!("erp-{}", algorithm.to_string()),
+            Self::Shadowsocks(ShadowsocksMethod::Chacha20IetfPoly1305) => "chacha20-ietf-poly1305".to_string(),
+            Self::Shadowsocks(ShadowsocksMethod::Aes256Gcm) => "aes-256-gcm".to_string(),
         }
     }
 }
@@ -126,7 +246,7 @@ impl<'de> Visitor<'de> for EncryptionMethodVisitor {
     type Value = EncryptionMethod;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("plain/erp")
+        formatter.write_str("plain/erp/erp-<algorithm>/chacha20-ietf-poly1305/aes-256-gcm")
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -146,9 +266,24 @@ impl<'de> Deserialize<'de> for EncryptionMethod {
     }
 }
 
+/// Initialize the protocol of bp server, which accepts the shared key and keys of users
+pub fn init_server_protocol(opts: &ServerOptions) -> DynProtocol {
+    let key = opts.key.clone().unwrap_or_default();
+
+    match opts.encryption {
+        EncryptionMethod::EncryptRandomPadding(algorithm) => {
+            let mut erp = Erp::new(key, algorithm, ServiceType::Server);
+            erp.set_users(opts.users.clone());
+            Box::new(erp)
+        }
+        encryption => init_protocol(encryption, key, ServiceType::Server),
+    }
+}
+
 pub fn init_protocol(encryption: EncryptionMethod, key: String, service_type: ServiceType) -> DynProtocol {
     match encryption {
         EncryptionMethod::Plain => Box::<Plain>::default(),
-        EncryptionMethod::EncryptRandomPadding => Box::new(Erp::new(key, service_type)),
+        EncryptionMethod::EncryptRandomPadding(algorithm) => Box::new(Erp::new(key, algorithm, service_type)),
+        EncryptionMethod::Shadowsocks(method) => Box::new(Shadowsocks::new(method, key)),
     }
 }
@@ -3,20 +3,27 @@ use async_trait::async_trait;
 use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::{
-    net::{address::Address, socket},
-    protos::{Protocol, ProtocolType, ResolvedResult},
+    net::{
+        address::Address,
+        outbound::{ConnectStatus, ConnectStatusError},
+        socket,
+    },
+    protos::{Command, Protocol, ProtocolType, ResolvedResult},
     Socket,
 };
 
 /// # Protocol
-/// +------+----------+----------+-------------+
-/// | ATYP | DST.ADDR | DST.PORT |    Data     |
-/// +------+----------+----------+-------------+
-/// |  1   | Variable |    2     |  Variable   |
-/// +------+----------+----------+-------------+
+/// +-----+------+----------+----------+-------------+
+/// | CMD | ATYP | DST.ADDR | DST.PORT |    Data     |
+/// +-----+------+----------+----------+-------------+
+/// |  1  |  1   | Variable |    2     |  Variable   |
+/// +-----+------+----------+----------+-------------+
+///
+/// CMD is the same as erp, and the server replies STATUS in one byte first as well, see Erp.
 #[derive(Clone, Default)]
 pub struct Plain {
     header_sent: bool,
+    status_received: bool,
     resolved_result: Option<ResolvedResult>,
 }
 
@@ -35,37 +42,79 @@ impl Protocol for Plain {
     }
 
     async fn resolve_dest_addr(&mut self, socket: &Socket) -> Result<&ResolvedResult> {
+        let command = Command::try_from(socket.read_exact(1).await?[0])?;
         let address = Address::from_socket(socket).await?;
 
         self.set_resolved_result(ResolvedResult {
-            protocol: ProtocolType::Plain,
+            protocol: command.to_protocol(ProtocolType::Plain),
             address,
             pending_buf: None,
+            client_hello: None,
         });
 
         Ok(self.get_resolved_result())
     }
 
+    async fn reply_connect_status(&mut self, socket: &Socket, status: ConnectStatus) -> Result<()> {
+        if socket.is_udp() {
+            return Ok(());
+        }
+
+        socket.send(&[status.into()]).await?;
+
+        Ok(())
+    }
+
+    async fn recv_connect_status(&mut self, socket: &Socket) -> Result<()> {
+        // send header alone, the server replies once dest address is connected
+        if !self.header_sent {
+            let buf = self.client_encode_buf(Bytes::new())?;
+            socket.send(&buf).await?;
+        }
+
+        let status = ConnectStatus::from(socket.read_exact(1).await?[0]);
+        self.status_received = true;
+
+        match status {
+            ConnectStatus::Succeeded => Ok(()),
+            _ => Err(ConnectStatusError(status).into()),
+        }
+    }
+
     async fn client_encode(&mut self, socket: &socket::Socket) -> Result<Bytes> {
+        let buf = socket.read_some().await?;
+        self.client_encode_buf(buf)
+    }
+
+    async fn server_encode(&mut self, socket: &socket::Socket) -> Result<Bytes> {
+        socket.read_some().await
+    }
+
+    fn client_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
         let mut frame = BytesMut::new();
 
         if !self.header_sent {
             let resolved = self.get_resolved_result();
+            frame.put_u8(Command::from_protocol(&resolved.protocol).into());
             frame.put(resolved.address.as_bytes());
             self.header_sent = true;
         }
 
-        let buf = socket.read_some().await?;
         frame.put(buf);
 
         Ok(frame.freeze())
     }
 
-    async fn server_encode(&mut self, socket: &socket::Socket) -> Result<Bytes> {
-        socket.read_some().await
+    fn server_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
+        Ok(buf)
     }
 
     async fn client_decode(&mut self, socket: &socket::Socket) -> Result<Bytes> {
+        // data was sent along with the header without waiting for the status
+        if !self.status_received && !socket.is_udp() {
+            self.recv_connect_status(socket).await?;
+        }
+
         socket.read_some().await
     }
 
@@ -0,0 +1,71 @@
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{
+    net::{
+        address::{Address, Host},
+        socket::Socket,
+    },
+    protos::{Protocol, ProtocolType, ResolvedResult},
+    utils::quic,
+};
+
+#[derive(Clone, Default)]
+pub struct Quic {
+    resolved_result: Option<ResolvedResult>,
+}
+
+#[async_trait]
+impl Protocol for Quic {
+    fn get_name(&self) -> String {
+        "quic".into()
+    }
+
+    fn set_resolved_result(&mut self, res: ResolvedResult) {
+        self.resolved_result = Some(res);
+    }
+
+    fn get_resolved_result(&self) -> &ResolvedResult {
+        self.resolved_result.as_ref().unwrap()
+    }
+
+    async fn resolve_dest_addr(&mut self, socket: &Socket) -> Result<&ResolvedResult> {
+        if !socket.is_udp() {
+            return Err(Error::msg("quic only works on udp"));
+        }
+
+        let buf = socket.read_some().await?;
+        let client_hello = quic::parse_initial_packet(&buf)?;
+
+        let server_name = match client_hello.server_name.clone() {
+            Some(server_name) => server_name,
+            None => return Err(Error::msg("server_name Extension not found")),
+        };
+
+        self.set_resolved_result(ResolvedResult {
+            protocol: ProtocolType::Quic,
+            address: Address::new(Host::Name(server_name), 443),
+            pending_buf: None,
+            client_hello: Some(client_hello),
+        });
+
+        Ok(self.get_resolved_result())
+    }
+
+    async fn client_encode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+
+    async fn server_encode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+
+    async fn client_decode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+
+    async fn server_decode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+}
@@ -0,0 +1,310 @@
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    net::{address::Address, socket::Socket},
+    protos::{Command, Protocol, ProtocolType, ResolvedResult},
+    utils::{
+        self,
+        aead::{AeadAlgorithm, AeadCipher},
+        crypto::Crypto,
+    },
+};
+
+const MAX_CHUNK_SIZE: usize = 0x3FFF;
+const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const HKDF_INFO: &str = "ss-subkey";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadowsocksMethod {
+    Chacha20IetfPoly1305,
+    Aes256Gcm,
+}
+
+impl ShadowsocksMethod {
+    fn algorithm(&self) -> AeadAlgorithm {
+        match self {
+            Self::Chacha20IetfPoly1305 => AeadAlgorithm::ChaCha20Poly1305,
+            Self::Aes256Gcm => AeadAlgorithm::Aes256Gcm,
+        }
+    }
+}
+
+/// # Protocol
+///
+/// TCP Stream
+/// +--------+------------+-----------------+------------+-----------+-----+
+/// |  Salt  |  ChunkLen  |  ChunkLen Tag   |   Chunk    | Chunk Tag | ... |
+/// +--------+------------+-----------------+------------+-----------+-----+
+/// |   32   |     2      |       16        |  Variable  |    16     | ... |
+/// +--------+------------+-----------------+------------+-----------+-----+
+///
+/// UDP Packet
+/// +--------+------+----------+----------+-------------+-----+
+/// |  Salt  | ATYP | DST.ADDR | DST.PORT |    Data     | Tag |
+/// +--------+------+----------+----------+-------------+-----+
+/// |   32   |  1   | Variable |    2     |  Variable   | 16  |
+/// +--------+------+----------+----------+-------------+-----+
+///
+/// First Chunk
+/// +------+----------+----------+-------------+
+/// | ATYP | DST.ADDR | DST.PORT |    Data     |
+/// +------+----------+----------+-------------+
+/// |  1   | Variable |    2     |  Variable   |
+/// +------+----------+----------+-------------+
+///
+/// # Explain
+///
+/// * The master key is derived from the password by EVP_BytesToKey with MD5.
+/// * Salt is randomly generated by each side of TCP stream, and by each UDP packet.
+/// * The per-session subkey is derived by HKDF-SHA1 with the master key, the salt and info = "ss-subkey".
+/// * Nonce is little-endian and counting from 0, each chunk increases the nonce twice, UDP packets always use 0.
+/// * The length of Chunk Data must <= 0x3FFF.
+/// * Data sent back to client over UDP is prefixed with the source address, in the same format as DST.ADDR.
+///
+/// # Reference
+///
+/// * Shadowsocks AEAD: https://shadowsocks.org/doc/aead.html
+#[derive(Clone)]
+pub struct Shadowsocks {
+    method: ShadowsocksMethod,
+
+    master_key: Bytes,
+
+    header_sent: bool,
+
+    /// Whether data is framed as UDP packets rather than TCP chunks
+    is_udp: bool,
+
+    encrypt_cipher: Option<AeadCipher>,
+
+    decrypt_cipher: Option<AeadCipher>,
+
+    encrypt_nonce: u128,
+
+    decrypt_nonce: u128,
+
+    resolved_result: Option<ResolvedResult>,
+}
+
+impl Shadowsocks {
+    pub fn new(method: ShadowsocksMethod, key: String) -> Self {
+        Self {
+            method,
+            master_key: Crypto::evp_bytes_to_key(key.as_bytes(), KEY_SIZE),
+            header_sent: false,
+            is_udp: false,
+            encrypt_cipher: None,
+            decrypt_cipher: None,
+            encrypt_nonce: 0,
+            decrypt_nonce: 0,
+            resolved_result: None,
+        }
+    }
+
+    fn new_cipher(&self, salt: Bytes) -> Result<AeadCipher> {
+        let subkey = Crypto::hkdf_sha1(self.master_key.clone(), salt, HKDF_INFO.as_bytes().into(), KEY_SIZE);
+        AeadCipher::new(self.method.algorithm(), &subkey)
+    }
+
+    fn encrypt(&mut self, plain_text: &[u8]) -> Result<Bytes> {
+        let nonce = utils::buffer::num_to_buf_le(self.encrypt_nonce, NONCE_SIZE);
+        let cipher_text = self.encrypt_cipher.as_ref().unwrap().encrypt(&nonce, plain_text)?;
+        self.encrypt_nonce += 1;
+        Ok(cipher_text)
+    }
+
+    fn decrypt(&mut self, cipher_text: &[u8]) -> Result<Bytes> {
+        let nonce = utils::buffer::num_to_buf_le(self.decrypt_nonce, NONCE_SIZE);
+        let plain_text = self.decrypt_cipher.as_ref().unwrap().decrypt(&nonce, cipher_text)?;
+        self.decrypt_nonce += 1;
+        Ok(plain_text)
+    }
+
+    /// Encode buffer into TCP chunks, the salt is attached at the beginning of the stream
+    fn encode(&mut self, buf: Bytes) -> Result<Bytes> {
+        let mut data = BytesMut::with_capacity(SALT_SIZE + buf.len() + 200);
+
+        if self.encrypt_cipher.is_none() {
+            let salt = Crypto::random_bytes(SALT_SIZE);
+            self.encrypt_cipher = Some(self.new_cipher(salt.clone())?);
+            data.put(salt);
+        }
+
+        for chunk in utils::buffer::get_chunks(buf, MAX_CHUNK_SIZE) {
+            // ChunkLen + ChunkLen Tag
+            let enc_chunk_len = self.encrypt(&(chunk.len() as u16).to_be_bytes())?;
+            data.put(enc_chunk_len);
+
+            // Chunk + Chunk Tag
+            let enc_chunk = self.encrypt(&chunk)?;
+            data.put(enc_chunk);
+        }
+
+        Ok(data.freeze())
+    }
+
+    /// Decode a TCP chunk, the salt is read at the beginning of the stream
+    async fn decode(&mut self, socket: &Socket) -> Result<Bytes> {
+        if self.decrypt_cipher.is_none() {
+            let salt = socket.read_exact(SALT_SIZE).await?;
+            self.decrypt_cipher = Some(self.new_cipher(salt)?);
+        }
+
+        // ChunkLen
+        let enc_chunk_len = socket.read_exact(2 + TAG_SIZE).await?;
+        let chunk_len = self.decrypt(&enc_chunk_len)?;
+        let chunk_len = u16::from_be_bytes([chunk_len[0], chunk_len[1]]) as usize;
+
+        if chunk_len > MAX_CHUNK_SIZE {
+            return Err(Error::msg(format!(
+                "chunk length {} exceeds {}",
+                chunk_len, MAX_CHUNK_SIZE
+            )));
+        }
+
+        // Chunk
+        let enc_chunk = socket.read_exact(chunk_len + TAG_SIZE).await?;
+        self.decrypt(&enc_chunk)
+    }
+
+    /// Encode an UDP packet with a fresh salt, the address is attached before data
+    fn encode_packet(&self, address: &Address, buf: Bytes) -> Result<Bytes> {
+        let mut plain_text = BytesMut::with_capacity(buf.len() + 20);
+        plain_text.put(address.as_bytes());
+        plain_text.put(buf);
+
+        let salt = Crypto::random_bytes(SALT_SIZE);
+        let nonce = utils::buffer::num_to_buf_le(0, NONCE_SIZE);
+        let cipher_text = self.new_cipher(salt.clone())?.encrypt(&nonce, &plain_text)?;
+
+        let mut data = BytesMut::with_capacity(SALT_SIZE + cipher_text.len());
+        data.put(salt);
+        data.put(cipher_text);
+
+        Ok(data.freeze())
+    }
+
+    /// Decode an UDP packet, return the address and data after it
+    async fn decode_packet(&self, socket: &Socket) -> Result<(Address, Option<Bytes>)> {
+        let salt = socket.read_exact(SALT_SIZE).await?;
+        let cipher_text = socket.read_some().await?;
+
+        let nonce = utils::buffer::num_to_buf_le(0, NONCE_SIZE);
+        let plain_text = self.new_cipher(salt)?.decrypt(&nonce, &cipher_text)?;
+
+        Address::from_bytes(plain_text)
+    }
+}
+
+#[async_trait]
+impl Protocol for Shadowsocks {
+    fn get_name(&self) -> String {
+        match self.method {
+            ShadowsocksMethod::Chacha20IetfPoly1305 => "ss-chacha20-ietf-poly1305".into(),
+            ShadowsocksMethod::Aes256Gcm => "ss-aes-256-gcm".into(),
+        }
+    }
+
+    fn set_resolved_result(&mut self, res: ResolvedResult) {
+        self.resolved_result = Some(res);
+    }
+
+    fn get_resolved_result(&self) -> &ResolvedResult {
+        self.resolved_result.as_ref().unwrap()
+    }
+
+    async fn resolve_dest_addr(&mut self, socket: &Socket) -> Result<&ResolvedResult> {
+        self.is_udp = socket.is_udp();
+
+        let (address, pending_buf) = if self.is_udp {
+            self.decode_packet(socket).await?
+        } else {
+            let chunk = self.decode(socket).await?;
+            Address::from_bytes(chunk)?
+        };
+
+        self.set_resolved_result(ResolvedResult {
+            protocol: ProtocolType::Shadowsocks,
+            address,
+            pending_buf,
+            client_hello: None,
+        });
+
+        Ok(self.get_resolved_result())
+    }
+
+    async fn client_encode(&mut self, socket: &Socket) -> Result<Bytes> {
+        let buf = socket.read_some().await?;
+
+        // udp inbound is relayed over udp as well, --udp-over-tcp is rejected for Shadowsocks
+        self.is_udp = socket.is_udp();
+
+        if self.is_udp {
+            return self.encode_packet(&self.get_resolved_result().address, buf);
+        }
+
+        self.client_encode_buf(buf)
+    }
+
+    async fn server_encode(&mut self, socket: &Socket) -> Result<Bytes> {
+        let buf = socket.read_some().await?;
+
+        // udp packets are sent back with the dest address as source address
+        if self.is_udp {
+            return self.encode_packet(&self.get_resolved_result().address, buf);
+        }
+
+        self.server_encode_buf(buf)
+    }
+
+    fn client_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
+        // attach header
+        if !self.header_sent {
+            let resolved = self.get_resolved_result();
+
+            // the header of shadowsocks has no room for a command
+            if Command::from_protocol(&resolved.protocol) != Command::Connect {
+                return Err(Error::msg(
+                    "only CONNECT can be requested over shadowsocks, use erp or plain instead",
+                ));
+            }
+
+            let mut data = BytesMut::with_capacity(buf.len() + 20);
+            data.put(resolved.address.as_bytes());
+            data.put(buf);
+
+            self.header_sent = true;
+
+            return self.encode(data.freeze());
+        }
+
+        self.encode(buf)
+    }
+
+    fn server_encode_buf(&mut self, buf: Bytes) -> Result<Bytes> {
+        self.encode(buf)
+    }
+
+    async fn client_decode(&mut self, socket: &Socket) -> Result<Bytes> {
+        if socket.is_udp() {
+            let (_, buf) = self.decode_packet(socket).await?;
+            return Ok(buf.unwrap_or_default());
+        }
+
+        self.decode(socket).await
+    }
+
+    async fn server_decode(&mut self, socket: &Socket) -> Result<Bytes> {
+        if socket.is_udp() {
+            let (_, buf) = self.decode_packet(socket).await?;
+            return Ok(buf.unwrap_or_default());
+        }
+
+        self.decode(socket).await
+    }
+}
@@ -1,36 +1,39 @@
 use anyhow::{Error, Result};
 use async_trait::async_trait;
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::{
-    net::{address::Address, socket::Socket},
-    protos::{Protocol, ProtocolType, ResolvedResult},
+    global,
+    net::{address::Address, outbound::ConnectStatus, socket::Socket},
+    protos::{Credentials, Protocol, ProtocolType, ResolvedResult},
     utils,
 };
 
 const NOOP: u8 = 0x00;
-// const SOCKS_VERSION_V4: u8 = 0x04;
 pub const SOCKS_VERSION_V5: u8 = 0x05;
 const METHOD_NO_AUTH: u8 = 0x00;
-// const METHOD_USERNAME_PASSWORD: u8 = 0x02;
-// const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+
+const AUTH_VERSION_V1: u8 = 0x01;
+const AUTH_STATUS_SUCCESS: u8 = 0x00;
+const AUTH_STATUS_FAILURE: u8 = 0x01;
 
 // const REQUEST_COMMAND_CONNECT: u8 = 0x01;
 const REQUEST_COMMAND_BIND: u8 = 0x02;
-// const REQUEST_COMMAND_UDP: u8 = 0x03;
+const REQUEST_COMMAND_UDP: u8 = 0x03;
 
 const ATYP_V4: u8 = 0x01;
 // const ATYP_DOMAIN: u8 = 0x03;
 // const ATYP_V6: u8 = 0x04;
 
-// const REPLY_GRANTED: u8 = 0x5a;
 const REPLY_SUCCEEDED: u8 = 0x00;
-// const REPLY_FAILURE: u8 = 0x01;
-// const REPLY_NOT_ALLOWED: u8 = 0x02;
-// const REPLY_NETWORK_UNREACHABLE: u8 = 0x03;
-// const REPLY_HOST_UNREACHABLE: u8 = 0x04;
-// const REPLY_CONNECTION_REFUSED: u8 = 0x05;
-// const REPLY_TTL_EXPIRED: u8 = 0x06;
+const REPLY_FAILURE: u8 = 0x01;
+const REPLY_NOT_ALLOWED: u8 = 0x02;
+const REPLY_NETWORK_UNREACHABLE: u8 = 0x03;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+const REPLY_TTL_EXPIRED: u8 = 0x06;
 // const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
 // const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
 // const REPLY_UNASSIGNED: u8 = 0xff;
@@ -38,16 +41,102 @@ const REPLY_SUCCEEDED: u8 = 0x00;
 #[derive(Clone)]
 pub struct Socks {
     bind_addr: Option<Address>,
+    auth: Option<SocksAuth>,
     resolved_result: Option<ResolvedResult>,
 }
 
 impl Socks {
-    pub fn new(bind_addr: Option<Address>) -> Self {
+    pub fn new(bind_addr: Option<Address>, auth: Option<SocksAuth>) -> Self {
         Self {
             bind_addr,
+            auth,
             resolved_result: None,
         }
     }
+
+    /// Build Socks5 Reply Message, REP is mapped from status
+    pub fn build_reply(status: ConnectStatus, bind_addr: Option<&Address>) -> Bytes {
+        // Socks5 Reply Message
+        // +----+-----+-------+------+----------+----------+
+        // |VER | REP |  RSV  | ATYP | BND.ADDR | BND.PORT |
+        // +----+-----+-------+------+----------+----------+
+        // | 1  |  1  | X'00' |  1   | Variable |    2     |
+        // +----+-----+-------+------+----------+----------+
+
+        let rep = match status {
+            ConnectStatus::Succeeded => REPLY_SUCCEEDED,
+            ConnectStatus::GeneralFailure => REPLY_FAILURE,
+            ConnectStatus::NotAllowed => REPLY_NOT_ALLOWED,
+            ConnectStatus::NetworkUnreachable => REPLY_NETWORK_UNREACHABLE,
+            ConnectStatus::HostUnreachable => REPLY_HOST_UNREACHABLE,
+            ConnectStatus::ConnectionRefused => REPLY_CONNECTION_REFUSED,
+            ConnectStatus::TimedOut => REPLY_TTL_EXPIRED,
+        };
+
+        let mut buf = BytesMut::new();
+
+        buf.put_slice(&[SOCKS_VERSION_V5, rep, NOOP]);
+
+        match bind_addr {
+            Some(addr) => buf.put(addr.as_bytes()),
+            None => buf.put_slice(&[ATYP_V4, NOOP, NOOP, NOOP, NOOP, NOOP, NOOP]),
+        }
+
+        buf.freeze()
+    }
+
+    /// Build Socks5 UDP header which wraps datagrams relayed back to the client
+    pub fn udp_header(addr: &Address) -> Bytes {
+        let mut buf = BytesMut::new();
+
+        // RSV
+        buf.put_u16(0);
+        // FRAG
+        buf.put_u8(NOOP);
+        // ATYP | DST.ADDR | DST.PORT
+        buf.put(addr.as_bytes());
+
+        buf.freeze()
+    }
+
+    async fn username_password_verify(socket: &Socket, auth: &SocksAuth) -> Result<()> {
+        // Username/Password Request (RFC 1929)
+        // +----+------+----------+------+----------+
+        // |VER | ULEN |  UNAME   | PLEN |  PASSWD  |
+        // +----+------+----------+------+----------+
+        // | 1  |  1   | 1 to 255 |  1   | 1 to 255 |
+        // +----+------+----------+------+----------+
+
+        let buf = socket.read_exact(2).await?;
+
+        if buf[0] != AUTH_VERSION_V1 {
+            return Err(Error::msg(format!(
+                "VER should be {:#04x} in username/password request but got {:#04x}",
+                AUTH_VERSION_V1, buf[0]
+            )));
+        }
+
+        let user = socket.read_exact(buf[1] as usize).await?;
+
+        let buf = socket.read_exact(1).await?;
+        let password = socket.read_exact(buf[0] as usize).await?;
+
+        // Username/Password Response
+        // +----+--------+
+        // |VER | STATUS |
+        // +----+--------+
+        // | 1  |   1    |
+        // +----+--------+
+
+        if !auth.verify(&user, &password) {
+            socket.send(&[AUTH_VERSION_V1, AUTH_STATUS_FAILURE]).await?;
+            return Err(Error::msg("invalid authorization, mismatch user or password"));
+        }
+
+        socket.send(&[AUTH_VERSION_V1, AUTH_STATUS_SUCCESS]).await?;
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -72,15 +161,41 @@ impl Protocol for Socks {
             // +----+------+------+----------+----------+----------+
             // | 2  |  1   |  1   | Variable |    2     | Variable |
             // +----+------+------+----------+----------+----------+
+            let peer_addr = socket.peer_addr();
+
+            // datagrams are only accepted from the client address announced by an active UDP ASSOCIATE
+            if !global::is_socks_udp_associated(peer_addr) {
+                return Err(Error::msg(format!("no udp association found for {}", peer_addr)));
+            }
+
             let packet = socket.read_some().await?;
-            let buf = packet.slice(3..);
 
-            let (address, pending_buf) = Address::from_bytes(buf)?;
+            if packet.len() < 4 {
+                return Err(Error::msg(format!(
+                    "udp packet is too short: {}",
+                    utils::fmt::ToHex(packet.to_vec())
+                )));
+            }
+
+            if packet[0] != NOOP || packet[1] != NOOP {
+                return Err(Error::msg(format!(
+                    "RSV must be 0x0000 but got {}",
+                    utils::fmt::ToHex(packet[0..2].to_vec())
+                )));
+            }
+
+            // fragmentation is not supported, drop any fragments
+            if packet[2] != NOOP {
+                return Err(Error::msg(format!("FRAG {:#04x} is not supported", packet[2])));
+            }
+
+            let (address, pending_buf) = Address::from_bytes(packet.slice(3..))?;
 
             self.set_resolved_result(ResolvedResult {
                 protocol: ProtocolType::Socks,
                 address,
                 pending_buf,
+                client_hello: None,
             });
 
             return Ok(self.get_resolved_result());
@@ -106,25 +221,20 @@ impl Protocol for Socks {
             )));
         }
 
-        // select one method
+        // select one method, username/password is required if --socks-auth is set
         let buf = socket.read_exact(n_methods).await?;
 
-        let mut method = None;
-        let mut n = 0usize;
+        let method = match self.auth {
+            Some(_) => METHOD_USERNAME_PASSWORD,
+            None => METHOD_NO_AUTH,
+        };
 
-        // TODO: now only support METHOD_NO_AUTH
-        while n < n_methods {
-            if buf[n] == METHOD_NO_AUTH {
-                method = Some(METHOD_NO_AUTH);
-                break;
-            }
-            n += 1;
-        }
+        if !buf.contains(&method) {
+            socket.send(&[SOCKS_VERSION_V5, METHOD_NOT_ACCEPTABLE]).await?;
 
-        if method.is_none() {
             return Err(Error::msg(format!(
-                "METHOD only support {:#04x} but it's not found in socks5 identifier message",
-                METHOD_NO_AUTH
+                "METHOD {:#04x} is required but it's not found in socks5 identifier message",
+                method
             )));
         }
 
@@ -137,7 +247,13 @@ impl Protocol for Socks {
         // | 1  |   1    |
         // +----+--------+
 
-        socket.send(&[SOCKS_VERSION_V5, METHOD_NO_AUTH]).await?;
+        socket.send(&[SOCKS_VERSION_V5, method]).await?;
+
+        // 2.1 Username/Password Authentication
+
+        if let Some(auth) = &self.auth {
+            Self::username_password_verify(socket, auth).await?;
+        }
 
         // 3. Parse Socks5 Request Message
 
@@ -157,53 +273,77 @@ impl Protocol for Socks {
             )));
         }
 
-        // TODO: add support for REQUEST_COMMAND_BIND
-        if buf[1] == REQUEST_COMMAND_BIND {
-            return Err(Error::msg(format!(
-                "CMD does not support {:#04x}",
-                REQUEST_COMMAND_BIND
-            )));
-        }
-
         if buf[2] != NOOP {
             return Err(Error::msg(format!("RSV must be 0x00 but got {:#04x}", buf[2])));
         }
 
+        let cmd = buf[1];
         let addr = Address::from_socket(socket).await?;
 
+        // BIND replies twice once the listening address and the incoming connection are known,
+        // both are sent later by the connection.
+        if cmd == REQUEST_COMMAND_BIND {
+            self.set_resolved_result(ResolvedResult {
+                protocol: ProtocolType::SocksBind,
+                address: addr,
+                pending_buf: None,
+                client_hello: None,
+            });
+
+            return Ok(self.get_resolved_result());
+        }
+
         // 4. Reply Socks5 Reply Message
 
-        // Socks5 Reply Message
-        // +----+-----+-------+------+----------+----------+
-        // |VER | REP |  RSV  | ATYP | BND.ADDR | BND.PORT |
-        // +----+-----+-------+------+----------+----------+
-        // | 1  |  1  | X'00' |  1   | Variable |    2     |
-        // +----+-----+-------+------+----------+----------+
+        // for UDP ASSOCIATE, BND.ADDR and BND.PORT is where the client should send datagrams to,
+        // which is the udp service listening on the same address of the incoming connection.
+        if cmd == REQUEST_COMMAND_UDP {
+            let bind_addr = socket
+                .local_addr()
+                .map(Address::from)
+                .or_else(|| self.bind_addr.clone());
 
-        let mut reply_buf = vec![SOCKS_VERSION_V5, REPLY_SUCCEEDED, NOOP];
+            socket
+                .send(&Self::build_reply(ConnectStatus::Succeeded, bind_addr.as_ref()))
+                .await?;
 
-        match &self.bind_addr {
-            Some(addr) => {
-                let mut addr_buf = addr.as_bytes().to_vec();
-                reply_buf.append(&mut addr_buf);
-            }
-            None => {
-                let mut addr_buf = [ATYP_V4, NOOP, NOOP, NOOP, NOOP, NOOP, NOOP].to_vec();
-                reply_buf.append(&mut addr_buf);
-            }
-        }
+            self.set_resolved_result(ResolvedResult {
+                protocol: ProtocolType::SocksUdpAssociate,
+                address: addr,
+                pending_buf: None,
+                client_hello: None,
+            });
 
-        socket.send(reply_buf.as_slice()).await?;
+            return Ok(self.get_resolved_result());
+        }
 
+        // for CONNECT, reply is deferred until the outbound connection is made, see reply_connect_status()
         self.set_resolved_result(ResolvedResult {
             protocol: ProtocolType::Socks,
             address: addr,
             pending_buf: None,
+            client_hello: None,
         });
 
         Ok(self.get_resolved_result())
     }
 
+    async fn reply_connect_status(&mut self, socket: &Socket, status: ConnectStatus) -> Result<()> {
+        // datagrams of UDP ASSOCIATE have no reply message, the UDP ASSOCIATE request is replied already
+        if socket.is_udp() {
+            return Ok(());
+        }
+
+        let bind_addr = match status {
+            ConnectStatus::Succeeded => self.bind_addr.as_ref(),
+            _ => None,
+        };
+
+        socket.send(&Self::build_reply(status, bind_addr)).await?;
+
+        Ok(())
+    }
+
     async fn client_encode(&mut self, _socket: &Socket) -> Result<Bytes> {
         unimplemented!()
     }
@@ -220,3 +360,6 @@ impl Protocol for Socks {
         unimplemented!()
     }
 }
+
+/// Credentials of Socks5 Username/Password Authentication
+pub type SocksAuth = Credentials;
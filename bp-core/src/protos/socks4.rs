@@ -0,0 +1,176 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{
+    net::{
+        address::{Address, Host},
+        outbound::ConnectStatus,
+        socket::Socket,
+    },
+    protos::{Protocol, ProtocolType, ResolvedResult},
+    utils,
+};
+
+const NOOP: u8 = 0x00;
+const SOCKS_VERSION_V4: u8 = 0x04;
+const REPLY_VERSION: u8 = 0x00;
+
+const REQUEST_COMMAND_CONNECT: u8 = 0x01;
+
+const REPLY_GRANTED: u8 = 0x5a;
+const REPLY_REJECTED: u8 = 0x5b;
+
+const MAX_FIELD_LEN: usize = 255;
+
+#[derive(Clone, Default)]
+pub struct Socks4 {
+    resolved_result: Option<ResolvedResult>,
+}
+
+impl Socks4 {
+    /// Read a NULL terminated field, e.g, USERID or hostname of Socks4a
+    async fn read_null_terminated(socket: &Socket) -> Result<Bytes> {
+        let mut buf = vec![];
+
+        loop {
+            let byte = socket.read_exact(1).await?;
+
+            if byte[0] == NOOP {
+                break;
+            }
+
+            if buf.len() >= MAX_FIELD_LEN {
+                return Err(Error::msg(format!(
+                    "field is longer than {} bytes without NULL terminated",
+                    MAX_FIELD_LEN
+                )));
+            }
+
+            buf.push(byte[0]);
+        }
+
+        Ok(buf.into())
+    }
+
+    fn build_reply(cd: u8) -> [u8; 8] {
+        // Socks4 Reply Message
+        // +----+----+---------+-------+
+        // | VN | CD | DSTPORT | DSTIP |
+        // +----+----+---------+-------+
+        // | 1  | 1  |    2    |   4   |
+        // +----+----+---------+-------+
+
+        [REPLY_VERSION, cd, NOOP, NOOP, NOOP, NOOP, NOOP, NOOP]
+    }
+}
+
+#[async_trait]
+impl Protocol for Socks4 {
+    fn get_name(&self) -> String {
+        "socks4".into()
+    }
+
+    fn set_resolved_result(&mut self, res: ResolvedResult) {
+        self.resolved_result = Some(res);
+    }
+
+    fn get_resolved_result(&self) -> &ResolvedResult {
+        self.resolved_result.as_ref().unwrap()
+    }
+
+    async fn resolve_dest_addr(&mut self, socket: &Socket) -> Result<&ResolvedResult> {
+        if socket.is_udp() {
+            return Err(Error::msg("socks4 does not support udp"));
+        }
+
+        // 1. Parse Socks4 Request Message
+
+        // Socks4 Request Message
+        // +----+----+---------+-------+----------+------+
+        // | VN | CD | DSTPORT | DSTIP |  USERID  | NULL |
+        // +----+----+---------+-------+----------+------+
+        // | 1  | 1  |    2    |   4   | Variable |  1   |
+        // +----+----+---------+-------+----------+------+
+
+        let buf = socket.read_exact(8).await?;
+
+        if buf[0] != SOCKS_VERSION_V4 {
+            return Err(Error::msg(format!(
+                "message is invalid when parsing socks4 request message: {}",
+                utils::fmt::ToHex(buf.to_vec())
+            )));
+        }
+
+        let cd = buf[1];
+        let port = u16::from_be_bytes([buf[2], buf[3]]);
+        let ip = Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]);
+
+        // USERID is not used
+        let _ = Self::read_null_terminated(socket).await?;
+
+        // 2. Parse Socks4a Hostname
+
+        // DSTIP is set to 0.0.0.x (x is nonzero) when the hostname follows USERID
+        // +----------+------+
+        // | HOSTNAME | NULL |
+        // +----------+------+
+        // | Variable |  1   |
+        // +----------+------+
+
+        let octets = ip.octets();
+
+        let host = if octets[0..3] == [NOOP, NOOP, NOOP] && octets[3] != NOOP {
+            let buf = Self::read_null_terminated(socket).await?;
+            Host::Name(String::from_utf8(buf.to_vec())?)
+        } else {
+            Host::Ip(IpAddr::V4(ip))
+        };
+
+        if cd != REQUEST_COMMAND_CONNECT {
+            socket.send(&Self::build_reply(REPLY_REJECTED)).await?;
+            return Err(Error::msg(format!("CD does not support {:#04x}", cd)));
+        }
+
+        // reply is deferred until the outbound connection is made, see reply_connect_status()
+        self.set_resolved_result(ResolvedResult {
+            protocol: ProtocolType::Socks,
+            address: Address::new(host, port),
+            pending_buf: None,
+            client_hello: None,
+        });
+
+        Ok(self.get_resolved_result())
+    }
+
+    async fn reply_connect_status(&mut self, socket: &Socket, status: ConnectStatus) -> Result<()> {
+        // 3. Reply Socks4 Reply Message
+
+        let cd = match status {
+            ConnectStatus::Succeeded => REPLY_GRANTED,
+            _ => REPLY_REJECTED,
+        };
+
+        socket.send(&Self::build_reply(cd)).await?;
+
+        Ok(())
+    }
+
+    async fn client_encode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+
+    async fn server_encode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+
+    async fn client_decode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+
+    async fn server_decode(&mut self, _socket: &Socket) -> Result<Bytes> {
+        unimplemented!()
+    }
+}
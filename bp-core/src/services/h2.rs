@@ -0,0 +1,138 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{Error, Result};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{error::TrySendError, Sender},
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::http::{Method, Response, StatusCode};
+
+use super::tcp::read_proxy_protocol_header;
+use crate::{constants, global::get_tls_server_config, net::socket::Socket, Shutdown};
+
+pub async fn start_h2_service(
+    bind_addr: SocketAddr,
+    path: String,
+    accept_proxy_protocol: bool,
+    sender: Sender<Option<Socket>>,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let listener = TcpListener::bind(bind_addr)
+        .await
+        .map_err(|err| Error::msg(format!("h2 service start failed from {} due to: {}", bind_addr, err)))?;
+
+    log::info!(
+        "service running at https://{}{} over h2, waiting for connection...",
+        bind_addr,
+        path
+    );
+
+    tokio::spawn(async move {
+        let mut config = get_tls_server_config();
+        config.alpn_protocols = vec![constants::H2_ALPN_PROTOCOL.to_vec()];
+
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let path = Arc::new(path);
+
+        loop {
+            let accept = tokio::select! {
+                v = listener.accept() => v,
+                _ = shutdown.recv() => break,
+            };
+
+            if sender.is_closed() {
+                break;
+            }
+
+            match accept {
+                Ok((mut tcp_stream, addr)) => {
+                    let sender = sender.clone();
+                    let acceptor = acceptor.clone();
+                    let path = path.clone();
+                    let shutdown = shutdown.clone();
+
+                    // each connection is served in a new task until it's closed
+                    tokio::spawn(async move {
+                        // PROXY protocol header is sent before TLS handshake
+                        let peer_addr = if accept_proxy_protocol {
+                            match read_proxy_protocol_header(&mut tcp_stream).await {
+                                Ok(peer_addr) => peer_addr,
+                                Err(err) => {
+                                    log::error!("[{}] read PROXY protocol header failed due to: {}", addr, err);
+                                    return;
+                                }
+                            }
+                        } else {
+                            addr
+                        };
+
+                        let res = tokio::select! {
+                            v = serve(tcp_stream, acceptor, &path, peer_addr, sender) => v,
+                            _ = shutdown.recv() => Ok(()),
+                        };
+
+                        if let Err(err) = res {
+                            log::warn!("[{}] h2 connection error due to: {}", peer_addr, err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    log::error!("encountered an error: {}", err);
+                    sender.send(None).await.unwrap();
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Accept streams of a HTTP/2 connection, POST requests to the path are accepted as new connections,
+/// other requests are answered with 404 like a web server
+async fn serve(
+    tcp_stream: TcpStream,
+    acceptor: TlsAcceptor,
+    path: &str,
+    peer_addr: SocketAddr,
+    sender: Sender<Option<Socket>>,
+) -> Result<()> {
+    let tls_stream = acceptor.accept(tcp_stream).await?;
+    let mut conn = h2::server::handshake(tls_stream).await?;
+
+    log::info!("[{}] established new h2 connection", peer_addr);
+
+    while let Some(stream) = conn.accept().await {
+        let (request, mut respond) = stream?;
+
+        if request.method() != Method::POST || request.uri().path() != path {
+            let response = Response::builder().status(StatusCode::NOT_FOUND).body(())?;
+            respond.send_response(response, true)?;
+            continue;
+        }
+
+        // waiting for the receiver stalls all streams of the connection, refuse the stream instead so that the
+        // client can retry it
+        let permit = match sender.try_reserve() {
+            Ok(permit) => permit,
+            Err(TrySendError::Full(_)) => {
+                log::warn!("[{}] too many pending connections, refuse h2 stream", peer_addr);
+                respond.send_reset(h2::Reason::REFUSED_STREAM);
+                continue;
+            }
+            Err(TrySendError::Closed(_)) => break,
+        };
+
+        let send_stream = respond.send_response(Response::new(()), false)?;
+        let socket = Socket::from_h2(peer_addr, (send_stream, request.into_body()));
+
+        log::info!("[{}] create new h2 stream", peer_addr);
+
+        permit.send(Some(socket));
+    }
+
+    log::info!("[{}] h2 connection closed", peer_addr);
+
+    Ok(())
+}
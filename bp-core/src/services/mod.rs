@@ -2,20 +2,26 @@ use std::net::SocketAddr;
 
 use serde::Serialize;
 
+pub mod h2;
 pub mod monitor;
 pub mod pac;
 pub mod quic;
 pub mod tcp;
 pub mod tls;
 pub mod udp;
+pub mod websocket;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ServiceProtocol {
     Tcp,
     Udp,
     Tls,
+    Https,
     Pac,
     Quic,
+    Ws,
+    Wss,
+    H2,
     Monitor,
 }
 
@@ -28,8 +34,12 @@ impl Serialize for ServiceProtocol {
             ServiceProtocol::Tcp => "tcp",
             ServiceProtocol::Udp => "udp",
             ServiceProtocol::Tls => "tls",
+            ServiceProtocol::Https => "https",
             ServiceProtocol::Pac => "pac",
             ServiceProtocol::Quic => "quic",
+            ServiceProtocol::Ws => "ws",
+            ServiceProtocol::Wss => "wss",
+            ServiceProtocol::H2 => "h2",
             ServiceProtocol::Monitor => "monitor",
         };
         serializer.serialize_str(s)
@@ -2,10 +2,10 @@ use std::net::SocketAddr;
 
 use anyhow::{Error, Result};
 use futures_util::stream::StreamExt;
-use quinn::Endpoint;
+use quinn::{Endpoint, NewConnection};
 use tokio::sync::mpsc::Sender;
 
-use crate::{global, Shutdown, Socket};
+use crate::{global, net::quic::DatagramSessions, Shutdown, Socket};
 
 pub async fn start_quic_service(
     bind_addr: SocketAddr,
@@ -42,15 +42,33 @@ pub async fn start_quic_service(
                 continue;
             }
 
-            let mut conn = conn.unwrap();
-            let conn_id = conn.connection.stable_id();
-            let peer_addr = conn.connection.remote_address();
+            let NewConnection {
+                connection,
+                mut bi_streams,
+                uni_streams,
+                datagrams,
+                ..
+            } = conn.unwrap();
+            let conn_id = connection.stable_id();
+            let peer_addr = connection.remote_address();
 
             log::info!("[{}] [{}] established new quic connection", peer_addr, conn_id);
 
+            // udp packets relayed by datagrams, each session is handled as a new connection
+            let datagram_sessions = DatagramSessions::new(connection);
+            let future = datagram_sessions.dispatch(datagrams, uni_streams, Some(sender.clone()));
+            let shutdown_copy = shutdown.clone();
+
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = future => {},
+                    _ = shutdown_copy.recv() => {},
+                }
+            });
+
             tokio::spawn(async move {
                 while let Some(stream) = tokio::select! {
-                    v = conn.bi_streams.next() => v,
+                    v = bi_streams.next() => v,
                     _ = shutdown.recv() => None,
                 } {
                     match stream {
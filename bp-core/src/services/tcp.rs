@@ -1,12 +1,17 @@
 use std::net::SocketAddr;
 
 use anyhow::{Error, Result};
-use tokio::{net::TcpListener, sync::mpsc::Sender};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Sender,
+    time::{timeout, Duration},
+};
 
-use crate::{net::socket::Socket, Shutdown};
+use crate::{constants, net::socket::Socket, utils::proxy_protocol, Shutdown};
 
 pub async fn start_tcp_service(
     bind_addr: SocketAddr,
+    accept_proxy_protocol: bool,
     sender: Sender<Option<Socket>>,
     shutdown: Shutdown,
 ) -> Result<()> {
@@ -28,9 +33,26 @@ pub async fn start_tcp_service(
             }
 
             match accept {
-                Ok((stream, _)) => {
+                Ok((stream, _)) if !accept_proxy_protocol => {
                     sender.send(Some(Socket::from_tcp_stream(stream))).await.unwrap();
                 }
+                Ok((mut stream, addr)) => {
+                    let sender = sender.clone();
+
+                    // read header in a new task, a slow or bad peer should not block the listener
+                    tokio::spawn(async move {
+                        match read_proxy_protocol_header(&mut stream).await {
+                            Ok(peer_addr) => {
+                                let mut socket = Socket::from_tcp_stream(stream);
+                                socket.set_peer_addr(peer_addr);
+                                let _ = sender.send(Some(socket)).await;
+                            }
+                            Err(err) => {
+                                log::error!("[{}] read PROXY protocol header failed due to: {}", addr, err);
+                            }
+                        }
+                    });
+                }
                 Err(err) => {
                     log::error!("encountered an error: {}", err);
                     sender.send(None).await.unwrap();
@@ -42,3 +64,26 @@ pub async fn start_tcp_service(
 
     Ok(())
 }
+
+/// Read PROXY protocol header sent by load balancer, return the real client address
+pub(crate) async fn read_proxy_protocol_header(stream: &mut TcpStream) -> Result<SocketAddr> {
+    let future = proxy_protocol::read_header(stream);
+    let header = timeout(
+        Duration::from_secs(constants::PROXY_PROTOCOL_READ_TIMEOUT_SECONDS),
+        future,
+    )
+    .await??;
+
+    match header {
+        Some(header) => {
+            log::debug!(
+                "[{}] PROXY protocol header received, real client address is {}",
+                stream.peer_addr()?,
+                header.source
+            );
+            Ok(header.source)
+        }
+        // health checks of load balancer carry no address
+        None => Ok(stream.peer_addr()?),
+    }
+}
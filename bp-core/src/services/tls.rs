@@ -4,10 +4,12 @@ use anyhow::{Error, Result};
 use tokio::{net::TcpListener, sync::mpsc::Sender};
 use tokio_rustls::{TlsAcceptor, TlsStream};
 
+use super::tcp::read_proxy_protocol_header;
 use crate::{global::get_tls_server_config, net::socket::Socket, Shutdown};
 
 pub async fn start_tls_service(
     bind_addr: SocketAddr,
+    accept_proxy_protocol: bool,
     sender: Sender<Option<Socket>>,
     shutdown: Shutdown,
 ) -> Result<()> {
@@ -33,17 +35,35 @@ pub async fn start_tls_service(
             }
 
             match accept {
-                Ok((tcp_stream, _)) => {
-                    let tls_stream = tokio::select! {
-                        v = acceptor.accept(tcp_stream) => v,
-                        _ = shutdown.recv() => break,
-                    }
-                    .unwrap();
-
-                    sender
-                        .send(Some(Socket::from_tls_stream(TlsStream::Server(tls_stream))))
-                        .await
-                        .unwrap();
+                Ok((mut tcp_stream, addr)) => {
+                    let sender = sender.clone();
+
+                    // handshake in a new task, a slow or bad peer should not block the listener
+                    tokio::spawn(async move {
+                        // PROXY protocol header is sent before TLS handshake
+                        let peer_addr = if accept_proxy_protocol {
+                            match read_proxy_protocol_header(&mut tcp_stream).await {
+                                Ok(peer_addr) => peer_addr,
+                                Err(err) => {
+                                    log::error!("[{}] read PROXY protocol header failed due to: {}", addr, err);
+                                    return;
+                                }
+                            }
+                        } else {
+                            addr
+                        };
+
+                        match acceptor.accept(tcp_stream).await {
+                            Ok(tls_stream) => {
+                                let mut socket = Socket::from_tls_stream(TlsStream::Server(tls_stream));
+                                socket.set_peer_addr(peer_addr);
+                                let _ = sender.send(Some(socket)).await;
+                            }
+                            Err(err) => {
+                                log::error!("[{}] tls handshake failed due to: {}", peer_addr, err);
+                            }
+                        }
+                    });
                 }
                 Err(err) => {
                     log::error!("encountered an error: {}", err);
@@ -0,0 +1,126 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{Error, Result};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Sender,
+};
+use tokio_rustls::{TlsAcceptor, TlsStream};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::StatusCode,
+    },
+};
+
+use super::tcp::read_proxy_protocol_header;
+use crate::{global::get_tls_server_config, net::socket::Socket, Shutdown};
+
+pub async fn start_websocket_service(
+    bind_addr: SocketAddr,
+    path: String,
+    tls: bool,
+    accept_proxy_protocol: bool,
+    sender: Sender<Option<Socket>>,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let scheme = if tls { "wss" } else { "ws" };
+
+    let listener = TcpListener::bind(bind_addr).await.map_err(|err| {
+        Error::msg(format!(
+            "{} service start failed from {} due to: {}",
+            scheme, bind_addr, err
+        ))
+    })?;
+
+    log::info!(
+        "service running at {}://{}{}, waiting for connection...",
+        scheme,
+        bind_addr,
+        path
+    );
+
+    tokio::spawn(async move {
+        let acceptor = tls.then(|| TlsAcceptor::from(Arc::new(get_tls_server_config())));
+        let path = Arc::new(path);
+
+        loop {
+            let accept = tokio::select! {
+                v = listener.accept() => v,
+                _ = shutdown.recv() => break,
+            };
+
+            if sender.is_closed() {
+                break;
+            }
+
+            match accept {
+                Ok((mut tcp_stream, addr)) => {
+                    let sender = sender.clone();
+                    let acceptor = acceptor.clone();
+                    let path = path.clone();
+
+                    // handshake in a new task, a slow or bad peer should not block the listener
+                    tokio::spawn(async move {
+                        // PROXY protocol header is sent before TLS and WebSocket handshakes
+                        let peer_addr = if accept_proxy_protocol {
+                            match read_proxy_protocol_header(&mut tcp_stream).await {
+                                Ok(peer_addr) => peer_addr,
+                                Err(err) => {
+                                    log::error!("[{}] read PROXY protocol header failed due to: {}", addr, err);
+                                    return;
+                                }
+                            }
+                        } else {
+                            addr
+                        };
+
+                        match handshake(tcp_stream, acceptor, &path).await {
+                            Ok(mut socket) => {
+                                socket.set_peer_addr(peer_addr);
+                                let _ = sender.send(Some(socket)).await;
+                            }
+                            Err(err) => {
+                                log::error!("[{}] {} handshake failed due to: {}", peer_addr, scheme, err);
+                            }
+                        }
+                    });
+                }
+                Err(err) => {
+                    log::error!("encountered an error: {}", err);
+                    sender.send(None).await.unwrap();
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Accept a WebSocket at the path, requests to other paths are answered with 404 like a web server
+async fn handshake(tcp_stream: TcpStream, acceptor: Option<TlsAcceptor>, path: &str) -> Result<Socket> {
+    let callback = |req: &Request, res: Response| -> Result<Response, ErrorResponse> {
+        if req.uri().path() != path {
+            let mut res = ErrorResponse::new(None);
+            *res.status_mut() = StatusCode::NOT_FOUND;
+            return Err(res);
+        }
+        Ok(res)
+    };
+
+    let socket = match acceptor {
+        Some(acceptor) => {
+            let tls_stream = acceptor.accept(tcp_stream).await?;
+            let stream = accept_hdr_async(TlsStream::Server(tls_stream), callback).await?;
+            Socket::from_wss_stream(stream)
+        }
+        None => {
+            let stream = accept_hdr_async(tcp_stream, callback).await?;
+            Socket::from_ws_stream(stream)
+        }
+    };
+
+    Ok(socket)
+}
@@ -11,7 +11,8 @@ impl Shutdown {
     }
 
     pub fn broadcast(&self) -> usize {
-        self.sender.send(()).unwrap()
+        // no receivers is not an error, nobody is waiting for the signal
+        self.sender.send(()).unwrap_or(0)
     }
 
     pub async fn recv(&self) {
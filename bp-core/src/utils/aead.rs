@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+use aes_gcm::Aes256Gcm;
+use anyhow::{Error, Result};
+use bytes::Bytes;
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, XChaCha20Poly1305,
+};
+
+/// AEAD algorithms with 256-bit key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadAlgorithm {
+    ChaCha20Poly1305,
+    XChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl AeadAlgorithm {
+    pub fn key_size(&self) -> usize {
+        32
+    }
+
+    pub fn nonce_size(&self) -> usize {
+        match self {
+            Self::ChaCha20Poly1305 | Self::Aes256Gcm => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    pub fn tag_size(&self) -> usize {
+        16
+    }
+}
+
+impl Default for AeadAlgorithm {
+    fn default() -> Self {
+        Self::ChaCha20Poly1305
+    }
+}
+
+impl FromStr for AeadAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "chacha20-poly1305" => Ok(Self::ChaCha20Poly1305),
+            "xchacha20-poly1305" => Ok(Self::XChaCha20Poly1305),
+            "aes-256-gcm" => Ok(Self::Aes256Gcm),
+            _ => Err(format!(
+                "{} is not supported, available algorithms are: chacha20-poly1305, xchacha20-poly1305, aes-256-gcm",
+                s
+            )),
+        }
+    }
+}
+
+impl ToString for AeadAlgorithm {
+    fn to_string(&self) -> String {
+        match self {
+            Self::ChaCha20Poly1305 => "chacha20-poly1305".to_string(),
+            Self::XChaCha20Poly1305 => "xchacha20-poly1305".to_string(),
+            Self::Aes256Gcm => "aes-256-gcm".to_string(),
+        }
+    }
+}
+
+/// An AEAD cipher initialized with a key, which should be created once and reused in a session
+#[derive(Clone)]
+pub enum AeadCipher {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+impl AeadCipher {
+    pub fn new(algorithm: AeadAlgorithm, key: &[u8]) -> Result<Self> {
+        let invalid_key = |_| {
+            Error::msg(format!(
+                "invalid key length {} for {}",
+                key.len(),
+                algorithm.to_string()
+            ))
+        };
+
+        Ok(match algorithm {
+            AeadAlgorithm::ChaCha20Poly1305 => {
+                Self::ChaCha20Poly1305(ChaCha20Poly1305::new_from_slice(key).map_err(invalid_key)?)
+            }
+            AeadAlgorithm::XChaCha20Poly1305 => {
+                Self::XChaCha20Poly1305(XChaCha20Poly1305::new_from_slice(key).map_err(invalid_key)?)
+            }
+            AeadAlgorithm::Aes256Gcm => Self::Aes256Gcm(Box::new(Aes256Gcm::new_from_slice(key).map_err(invalid_key)?)),
+        })
+    }
+
+    pub fn algorithm(&self) -> AeadAlgorithm {
+        match self {
+            Self::ChaCha20Poly1305(_) => AeadAlgorithm::ChaCha20Poly1305,
+            Self::XChaCha20Poly1305(_) => AeadAlgorithm::XChaCha20Poly1305,
+            Self::Aes256Gcm(_) => AeadAlgorithm::Aes256Gcm,
+        }
+    }
+
+    pub fn encrypt(&self, nonce: &[u8], plain_text: &[u8]) -> Result<Bytes> {
+        self.check_nonce(nonce)?;
+
+        let res = match self {
+            Self::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce.into(), plain_text),
+            Self::XChaCha20Poly1305(cipher) => cipher.encrypt(nonce.into(), plain_text),
+            Self::Aes256Gcm(cipher) => cipher.encrypt(nonce.into(), plain_text),
+        };
+
+        res.map(Bytes::from).map_err(|_| Error::msg("encrypt failed"))
+    }
+
+    pub fn decrypt(&self, nonce: &[u8], cipher_text: &[u8]) -> Result<Bytes> {
+        self.check_nonce(nonce)?;
+
+        let res = match self {
+            Self::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), cipher_text),
+            Self::XChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), cipher_text),
+            Self::Aes256Gcm(cipher) => cipher.decrypt(nonce.into(), cipher_text),
+        };
+
+        res.map(Bytes::from).map_err(|_| Error::msg("decrypt failed"))
+    }
+
+    // GenericArray panics if the length mismatches
+    fn check_nonce(&self, nonce: &[u8]) -> Result<()> {
+        let nonce_size = self.algorithm().nonce_size();
+
+        if nonce.len() != nonce_size {
+            return Err(Error::msg(format!(
+                "nonce should be {} bytes for {}, but got {} bytes",
+                nonce_size,
+                self.algorithm().to_string(),
+                nonce.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
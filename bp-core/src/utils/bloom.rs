@@ -0,0 +1,146 @@
+use std::{
+    collections::hash_map::RandomState,
+    f64::consts::LN_2,
+    hash::{BuildHasher, Hash, Hasher},
+    mem,
+    time::{Duration, Instant},
+};
+
+/// A Bloom filter with randomly keyed hash functions, so that false positives cannot be predicted by others
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    capacity: usize,
+    len: usize,
+    hash_builder: RandomState,
+}
+
+impl BloomFilter {
+    /// Create a filter which holds capacity items with the false positive rate
+    pub fn new(capacity: usize, fp_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+
+        // m = -n * ln(p) / ln(2)^2, k = m / n * ln(2)
+        let num_bits = (-(capacity as f64) * fp_rate.ln() / (LN_2 * LN_2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / capacity as f64) * LN_2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; ((num_bits + 63) / 64) as usize],
+            num_bits,
+            num_hashes,
+            capacity,
+            len: 0,
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        for index in self.indexes(item) {
+            self.bits[(index / 64) as usize] |= 1 << (index % 64);
+        }
+        self.len += 1;
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.indexes(item)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    /// The number of inserted items
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The false positive rate is no longer guaranteed once it's full
+    pub fn is_full(&self) -> bool {
+        self.len >= self.capacity
+    }
+
+    /// Double hashing, the i-th index is h1 + i * h2
+    fn indexes(&self, item: &[u8]) -> impl Iterator<Item = u64> {
+        let hash = |seed: u8| {
+            let mut hasher = self.hash_builder.build_hasher();
+            seed.hash(&mut hasher);
+            item.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        let (h1, h2) = (hash(0), hash(1));
+        let num_bits = self.num_bits;
+
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+/// Two generations of Bloom filters, items are remembered for at least one period
+///
+/// The filters are rotated early once the current one is full before the period ends, so items of the oldest
+/// generation may be forgotten earlier under heavy load, rather than refusing new items.
+pub struct RotatingBloomFilter {
+    current: BloomFilter,
+    previous: BloomFilter,
+    capacity: usize,
+    fp_rate: f64,
+    period: Duration,
+    rotated_at: Instant,
+    clock: Box<dyn Fn() -> Instant + Send>,
+}
+
+impl RotatingBloomFilter {
+    pub fn new(capacity: usize, fp_rate: f64, period: Duration) -> Self {
+        Self::with_clock(capacity, fp_rate, period, Instant::now)
+    }
+
+    /// Create a filter which reads the current time from clock instead of Instant::now()
+    pub fn with_clock<F>(capacity: usize, fp_rate: f64, period: Duration, clock: F) -> Self
+    where
+        F: Fn() -> Instant + Send + 'static,
+    {
+        Self {
+            current: BloomFilter::new(capacity, fp_rate),
+            previous: BloomFilter::new(capacity, fp_rate),
+            capacity,
+            fp_rate,
+            period,
+            rotated_at: clock(),
+            clock: Box::new(clock),
+        }
+    }
+
+    /// Return true if the item is seen before, otherwise remember it and return false
+    pub fn contains_or_insert(&mut self, item: &[u8]) -> bool {
+        let now = (self.clock)();
+
+        if now.saturating_duration_since(self.rotated_at) >= self.period {
+            self.rotate(now);
+        }
+
+        if self.current.contains(item) || self.previous.contains(item) {
+            return true;
+        }
+
+        if self.current.is_full() {
+            log::warn!(
+                "bloom filter is full with {} items {:?} after last rotation, rotated early",
+                self.capacity,
+                now.saturating_duration_since(self.rotated_at)
+            );
+            self.rotate(now);
+        }
+
+        self.current.insert(item);
+
+        false
+    }
+
+    /// The oldest generation is forgotten
+    fn rotate(&mut self, now: Instant) {
+        self.previous = mem::replace(&mut self.current, BloomFilter::new(self.capacity, self.fp_rate));
+        self.rotated_at = now;
+    }
+}
@@ -32,7 +32,8 @@ pub fn num_to_buf_be(num: u64, nbytes: usize) -> Bytes {
 }
 
 pub fn num_to_buf_le(num: u128, nbytes: usize) -> Bytes {
-    let mut buf = BytesMut::with_capacity(nbytes);
+    let mut buf = BytesMut::with_capacity(nbytes.max(16));
     buf.put_u128_le(num);
-    buf.freeze().slice(0..12)
+    buf.resize(nbytes.max(16), 0);
+    buf.freeze().slice(0..nbytes)
 }
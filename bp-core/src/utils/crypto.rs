@@ -1,6 +1,8 @@
 use bytes::Bytes;
 use hkdf::Hkdf;
+use md5::{Digest, Md5};
 use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, RngCore, SeedableRng};
+use sha1::Sha1;
 use sha2::Sha256;
 
 pub struct Crypto;
@@ -22,6 +24,10 @@ impl Crypto {
         Self::random_bytes(1)[0]
     }
 
+    pub fn random_u32() -> u32 {
+        Self::std_rng().next_u32()
+    }
+
     pub fn random_choose<T>(arr: &[T]) -> Option<&T> {
         let mut rng = Self::std_rng();
         SliceRandom::choose(arr, &mut rng)
@@ -36,4 +42,32 @@ impl Crypto {
 
         Vec::from(&okm[0..len]).into()
     }
+
+    pub fn hkdf_sha1(ikm: Bytes, salt: Bytes, info: Bytes, len: usize) -> Bytes {
+        let hk = Hkdf::<Sha1>::new(Some(&salt[..]), &ikm);
+        let mut okm = vec![0u8; len];
+
+        hk.expand(&info, &mut okm)
+            .expect("len should be a valid length for Sha1 to output");
+
+        okm.into()
+    }
+
+    /// OpenSSL's EVP_BytesToKey() with MD5 and no salt, which derives the master key from password in Shadowsocks
+    pub fn evp_bytes_to_key(password: &[u8], len: usize) -> Bytes {
+        let mut key = Vec::with_capacity(len + 16);
+        let mut prev: Vec<u8> = vec![];
+
+        while key.len() < len {
+            let mut hasher = Md5::new();
+            hasher.update(&prev);
+            hasher.update(password);
+
+            prev = hasher.finalize().to_vec();
+            key.extend_from_slice(&prev);
+        }
+
+        key.truncate(len);
+        key.into()
+    }
 }
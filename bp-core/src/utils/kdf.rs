@@ -0,0 +1,170 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{Error, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The length of salt in bytes, generated per deployment by `bp generate --key-derivation`
+pub const SALT_SIZE: usize = 16;
+
+/// The length of derived key in bytes
+const KEY_SIZE: usize = 32;
+
+/// A memory-hard function to strengthen the key before it's used, e.g, "argon2id:m=19456,t=2,p=1,salt=<base64>"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDerivation {
+    None,
+    /// Memory cost in KiB, number of iterations and degree of parallelism
+    Argon2id {
+        m: u32,
+        t: u32,
+        p: u32,
+        salt: [u8; SALT_SIZE],
+    },
+    /// log2 of CPU/memory cost, block size and parallelization
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+        salt: [u8; SALT_SIZE],
+    },
+}
+
+impl KeyDerivation {
+    /// Derive a strengthened key from the key, which is hex encoded
+    pub fn derive(&self, key: &str) -> Result<String> {
+        let mut output = [0u8; KEY_SIZE];
+
+        match *self {
+            Self::None => return Ok(key.to_string()),
+            Self::Argon2id { m, t, p, salt } => {
+                let params = argon2::Params::new(m, t, p, Some(KEY_SIZE)).map_err(|err| Error::msg(err.to_string()))?;
+
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(key.as_bytes(), &salt, &mut output)
+                    .map_err(|err| Error::msg(err.to_string()))?;
+            }
+            Self::Scrypt { log_n, r, p, salt } => {
+                let params = scrypt::Params::new(log_n, r, p).map_err(|err| Error::msg(err.to_string()))?;
+
+                scrypt::scrypt(key.as_bytes(), &salt, &params, &mut output)
+                    .map_err(|err| Error::msg(err.to_string()))?;
+            }
+        }
+
+        Ok(output.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    pub fn is_none(&self) -> bool {
+        matches!(self, Self::None)
+    }
+}
+
+impl Default for KeyDerivation {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl FromStr for KeyDerivation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, params) = s.split_once(':').unwrap_or((s, ""));
+
+        // e.g, "m=19456,t=2,p=1,salt=<base64>"
+        let mut values = HashMap::new();
+
+        for param in params.split(',').filter(|param| !param.is_empty()) {
+            let (k, v) = param
+                .split_once('=')
+                .ok_or_else(|| format!("invalid parameter {}, should be <name>=<value>", param))?;
+            values.insert(k, v);
+        }
+
+        let mut take = |k: &str, default: u32| -> std::result::Result<u32, String> {
+            match values.remove(k) {
+                Some(v) => v.parse().map_err(|_| format!("invalid value of parameter {}", k)),
+                None => Ok(default),
+            }
+        };
+
+        // defaults are recommended by OWASP
+        let kdf = match name {
+            "none" => Self::None,
+            "argon2id" => Self::Argon2id {
+                m: take("m", 19456)?,
+                t: take("t", 2)?,
+                p: take("p", 1)?,
+                salt: parse_salt(values.remove("salt"))?,
+            },
+            "scrypt" => Self::Scrypt {
+                log_n: take("n", 17)?.try_into().map_err(|_| "n of scrypt is too large")?,
+                r: take("r", 8)?,
+                p: take("p", 1)?,
+                salt: parse_salt(values.remove("salt"))?,
+            },
+            _ => {
+                return Err(format!(
+                    "{} is not supported, available key derivations are: none, argon2id, scrypt",
+                    name
+                ))
+            }
+        };
+
+        if let Some(k) = values.keys().next() {
+            return Err(format!("unknown parameter {} of {}", k, name));
+        }
+
+        Ok(kdf)
+    }
+}
+
+impl ToString for KeyDerivation {
+    fn to_string(&self) -> String {
+        match self {
+            Self::None => "none".to_string(),
+            Self::Argon2id { m, t, p, salt } => format!("argon2id:m={},t={},p={},salt={}", m, t, p, encode_salt(salt)),
+            Self::Scrypt { log_n, r, p, salt } => {
+                format!("scrypt:n={},r={},p={},salt={}", log_n, r, p, encode_salt(salt))
+            }
+        }
+    }
+}
+
+/// Salt is required, a fixed one would let a single precomputed table attack every deployment
+fn parse_salt(value: Option<&str>) -> std::result::Result<[u8; SALT_SIZE], String> {
+    let value = value.ok_or("salt is required, generate one by `bp generate --key-derivation <name>`")?;
+
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|salt| salt.try_into().ok())
+        .ok_or_else(|| {
+            format!(
+                "salt should be {} bytes encoded in url-safe base64 without padding",
+                SALT_SIZE
+            )
+        })
+}
+
+fn encode_salt(salt: &[u8]) -> String {
+    base64::encode_config(salt, base64::URL_SAFE_NO_PAD)
+}
+
+impl Serialize for KeyDerivation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyDerivation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
@@ -1,7 +1,13 @@
+pub mod aead;
+pub mod bloom;
 pub mod buffer;
 pub mod crypto;
 pub mod event;
 pub mod fmt;
+pub mod kdf;
 pub mod net;
+pub mod proxy_protocol;
+pub mod quic;
 pub mod store;
 pub mod tls;
+pub mod token_bucket;
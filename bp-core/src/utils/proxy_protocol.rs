@@ -0,0 +1,284 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::{self, FromStr},
+};
+
+use anyhow::{Error, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The signature of PROXY protocol v2 header
+pub const V2_SIGNATURE: [u8; 12] = [0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a];
+
+/// The max length of PROXY protocol v1 header, including CRLF
+const V1_MAX_LEN: usize = 107;
+
+const V2_COMMAND_LOCAL: u8 = 0x20;
+const V2_COMMAND_PROXY: u8 = 0x21;
+
+const V2_FAMILY_TCP4: u8 = 0x11;
+const V2_FAMILY_TCP6: u8 = 0x21;
+
+/// The addresses carried by PROXY protocol header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+impl FromStr for ProxyProtocolVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "v1" | "1" => Ok(Self::V1),
+            "v2" | "2" => Ok(Self::V2),
+            _ => Err(format!("{} is not supported, available versions are: v1, v2", s)),
+        }
+    }
+}
+
+impl ToString for ProxyProtocolVersion {
+    fn to_string(&self) -> String {
+        match self {
+            Self::V1 => "v1".to_string(),
+            Self::V2 => "v2".to_string(),
+        }
+    }
+}
+
+impl Serialize for ProxyProtocolVersion {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ProxyProtocolVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Read PROXY protocol v1 or v2 header from the very beginning of a connection, without consuming any data after it.
+/// Return None if the header doesn't carry addresses, e.g, "UNKNOWN" of v1 or "LOCAL" of v2.
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<ProxyHeader>> {
+    // both "PROXY UNKNOWN\r\n" of v1 and the signature of v2 are no shorter than 12 bytes
+    let mut buf = vec![0u8; V2_SIGNATURE.len()];
+    reader.read_exact(&mut buf).await?;
+
+    if buf == V2_SIGNATURE {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header).await?;
+        buf.extend_from_slice(&header);
+
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut addresses = vec![0u8; len];
+        reader.read_exact(&mut addresses).await?;
+        buf.extend_from_slice(&addresses);
+
+        return parse_v2(&buf);
+    }
+
+    if !buf.starts_with(b"PROXY ") {
+        return Err(Error::msg("PROXY protocol header not found"));
+    }
+
+    // read byte by byte until CRLF, in order not to consume data after the header
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(Error::msg(format!(
+                "PROXY protocol v1 header is longer than {} bytes",
+                V1_MAX_LEN
+            )));
+        }
+        buf.push(reader.read_u8().await?);
+    }
+
+    parse_v1(&buf)
+}
+
+/// Parse a PROXY protocol v1 header line, e.g, "PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n"
+pub fn parse_v1(buf: &[u8]) -> Result<Option<ProxyHeader>> {
+    let line = str::from_utf8(buf)?
+        .strip_suffix("\r\n")
+        .ok_or_else(|| Error::msg("PROXY protocol v1 header must end with CRLF"))?;
+
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), src_ip, dst_ip, src_port, dst_port] => {
+            let src_ip: IpAddr = src_ip.parse()?;
+            let dst_ip: IpAddr = dst_ip.parse()?;
+
+            if src_ip.is_ipv4() != (family == "TCP4") || dst_ip.is_ipv4() != (family == "TCP4") {
+                return Err(Error::msg(format!("PROXY protocol v1 addresses mismatch {}", family)));
+            }
+
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(src_ip, src_port.parse()?),
+                destination: SocketAddr::new(dst_ip, dst_port.parse()?),
+            }))
+        }
+        _ => Err(Error::msg(format!("PROXY protocol v1 header is invalid: {}", line))),
+    }
+}
+
+/// Parse a complete PROXY protocol v2 header, including the 16 bytes fixed part
+pub fn parse_v2(buf: &[u8]) -> Result<Option<ProxyHeader>> {
+    // PROXY protocol v2 header
+    // +-----------+---------+--------+--------+-----------+
+    // | Signature | Ver/Cmd | Family | Length | Addresses |
+    // +-----------+---------+--------+--------+-----------+
+    // |    12     |    1    |   1    |   2    |  Length   |
+    // +-----------+---------+--------+--------+-----------+
+
+    if buf.len() < 16 || buf[0..12] != V2_SIGNATURE {
+        return Err(Error::msg("PROXY protocol v2 signature not found"));
+    }
+
+    let ver_cmd = buf[12];
+    let family = buf[13];
+    let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    let addresses = &buf[16..];
+
+    if addresses.len() != len {
+        return Err(Error::msg(format!(
+            "PROXY protocol v2 Length({}) mismatch the remaining buffer size({})",
+            len,
+            addresses.len()
+        )));
+    }
+
+    match ver_cmd {
+        V2_COMMAND_LOCAL => return Ok(None),
+        V2_COMMAND_PROXY => {}
+        _ => {
+            return Err(Error::msg(format!(
+                "PROXY protocol v2 Ver/Cmd {:#04x} is not supported",
+                ver_cmd
+            )))
+        }
+    }
+
+    // the address family is mixed with transport protocol, TLVs after addresses are ignored
+    let header = match family >> 4 {
+        // AF_INET
+        0x1 if len >= 12 => {
+            let ip = |i: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    addresses[i],
+                    addresses[i + 1],
+                    addresses[i + 2],
+                    addresses[i + 3],
+                ))
+            };
+            let port = |i: usize| u16::from_be_bytes([addresses[i], addresses[i + 1]]);
+
+            ProxyHeader {
+                source: SocketAddr::new(ip(0), port(8)),
+                destination: SocketAddr::new(ip(4), port(10)),
+            }
+        }
+        // AF_INET6
+        0x2 if len >= 36 => {
+            let ip = |i: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addresses[i..i + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = |i: usize| u16::from_be_bytes([addresses[i], addresses[i + 1]]);
+
+            ProxyHeader {
+                source: SocketAddr::new(ip(0), port(32)),
+                destination: SocketAddr::new(ip(16), port(34)),
+            }
+        }
+        // AF_UNSPEC and AF_UNIX carry no ip address
+        0x0 | 0x3 => return Ok(None),
+        _ => {
+            return Err(Error::msg(format!(
+                "PROXY protocol v2 Family {:#04x} is invalid or too short",
+                family
+            )))
+        }
+    };
+
+    Ok(Some(header))
+}
+
+/// Encode a PROXY protocol header for a TCP connection from source to destination
+pub fn encode(version: ProxyProtocolVersion, source: SocketAddr, destination: SocketAddr) -> Bytes {
+    // both addresses must be in the same family, map ipv4 to ipv6 if they are mixed
+    let (source, destination) = if source.is_ipv4() == destination.is_ipv4() {
+        (source, destination)
+    } else {
+        (to_ipv6(source), to_ipv6(destination))
+    };
+
+    let mut buf = BytesMut::new();
+
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+
+            buf.put_slice(
+                format!(
+                    "PROXY {} {} {} {} {}\r\n",
+                    family,
+                    source.ip(),
+                    destination.ip(),
+                    source.port(),
+                    destination.port()
+                )
+                .as_bytes(),
+            );
+        }
+        ProxyProtocolVersion::V2 => {
+            buf.put_slice(&V2_SIGNATURE);
+            buf.put_u8(V2_COMMAND_PROXY);
+
+            match (source.ip(), destination.ip()) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    buf.put_u8(V2_FAMILY_TCP4);
+                    buf.put_u16(12);
+                    buf.put_slice(&src.octets());
+                    buf.put_slice(&dst.octets());
+                }
+                (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                    buf.put_u8(V2_FAMILY_TCP6);
+                    buf.put_u16(36);
+                    buf.put_slice(&src.octets());
+                    buf.put_slice(&dst.octets());
+                }
+                _ => unreachable!(),
+            }
+
+            buf.put_u16(source.port());
+            buf.put_u16(destination.port());
+        }
+    }
+
+    buf.freeze()
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
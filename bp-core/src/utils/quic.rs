@@ -0,0 +1,245 @@
+use anyhow::{Error, Result};
+use rustls::quic::{Keys, Version};
+
+use super::tls::{self, ClientHello};
+
+const QUIC_VERSION_1: u32 = 0x0000_0001;
+
+const LONG_HEADER_FORM: u8 = 0x80;
+const FIXED_BIT: u8 = 0x40;
+const LONG_PACKET_TYPE_INITIAL: u8 = 0x00;
+
+const MAX_CID_LEN: usize = 20;
+const MAX_PACKET_NUMBER_LEN: usize = 4;
+
+const FRAME_PADDING: u64 = 0x00;
+const FRAME_PING: u64 = 0x01;
+const FRAME_ACK: u64 = 0x02;
+const FRAME_ACK_ECN: u64 = 0x03;
+const FRAME_CRYPTO: u64 = 0x06;
+const FRAME_CONNECTION_CLOSE: u64 = 0x1c;
+
+/// Check if the datagram starts with a QUIC v1 Initial packet
+pub fn check_initial_packet(buf: &[u8]) -> bool {
+    if buf.len() < 5 {
+        return false;
+    }
+
+    let packet_type = (buf[0] & 0x30) >> 4;
+    let version = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]);
+
+    buf[0] & (LONG_HEADER_FORM | FIXED_BIT) == LONG_HEADER_FORM | FIXED_BIT
+        && packet_type == LONG_PACKET_TYPE_INITIAL
+        && version == QUIC_VERSION_1
+}
+
+/// Decrypt the first QUIC v1 Initial packet of a datagram sent by client, and parse the ClientHello
+/// carried by its CRYPTO frames
+pub fn parse_initial_packet(buf: &[u8]) -> Result<ClientHello> {
+    // Initial Packet
+    // +-------+---------+-----------+-----+-----------+-----+--------------+-------+--------+---------+
+    // | Flags | Version | DCID Len  | DCID| SCID Len  | SCID| Token Length | Token | Length | PN(...) |
+    // +-------+---------+-----------+-----+-----------+-----+--------------+-------+--------+---------+
+    // |   1   |    4    |     1     | 0-20|     1     | 0-20|    varint    |   n   | varint |  1-4    |
+    // +-------+---------+-----------+-----+-----------+-----+--------------+-------+--------+---------+
+
+    if !check_initial_packet(buf) {
+        return Err(Error::msg("not a QUIC v1 Initial packet"));
+    }
+
+    let mut reader = Reader::new(&buf[5..]);
+
+    let dcid = reader.read_cid()?;
+    let _scid = reader.read_cid()?;
+
+    let token_len = reader.read_varint()? as usize;
+    reader.read(token_len)?;
+
+    let len = reader.read_varint()? as usize;
+    let pn_offset = buf.len() - reader.remaining();
+
+    // ignore coalesced packets after the Initial packet
+    if len < MAX_PACKET_NUMBER_LEN || len > reader.remaining() {
+        return Err(Error::msg(format!(
+            "Initial packet Length({}) is invalid, {} bytes left",
+            len,
+            reader.remaining()
+        )));
+    }
+
+    let mut packet = buf[..pn_offset + len].to_vec();
+
+    // client Initial packets are protected by keys derived from the DCID it chose
+    let keys = Keys::initial(Version::V1, dcid, false);
+
+    // 1. remove header protection
+    let sample_offset = pn_offset + MAX_PACKET_NUMBER_LEN;
+    let sample_len = keys.remote.header.sample_len();
+
+    if packet.len() < sample_offset + sample_len {
+        return Err(Error::msg("Initial packet is too short to sample"));
+    }
+
+    let sample = packet[sample_offset..sample_offset + sample_len].to_vec();
+    let (first, rest) = packet.split_at_mut(1);
+    let pn_buf = &mut rest[pn_offset - 1..pn_offset - 1 + MAX_PACKET_NUMBER_LEN];
+
+    keys.remote
+        .header
+        .decrypt_in_place(&sample, &mut first[0], pn_buf)
+        .map_err(|err| Error::msg(format!("cannot remove header protection due to: {}", err)))?;
+
+    let pn_len = (packet[0] & 0x03) as usize + 1;
+    let packet_number = packet[pn_offset..pn_offset + pn_len]
+        .iter()
+        .fold(0u64, |pn, byte| (pn << 8) | *byte as u64);
+
+    // 2. decrypt payload
+    let (header, payload) = packet.split_at_mut(pn_offset + pn_len);
+
+    let payload = keys
+        .remote
+        .packet
+        .decrypt_in_place(packet_number, header, payload)
+        .map_err(|err| Error::msg(format!("cannot decrypt Initial packet due to: {}", err)))?;
+
+    // 3. reassemble CRYPTO frames
+    let crypto = read_crypto_frames(payload)?;
+
+    if crypto.len() < 4 {
+        return Err(Error::msg("ClientHello is not found in Initial packet"));
+    }
+
+    let message_len = u32::from_be_bytes([0, crypto[1], crypto[2], crypto[3]]) as usize + 4;
+
+    if message_len > crypto.len() {
+        return Err(Error::msg(format!(
+            "ClientHello({} bytes) is not complete in Initial packet, only {} bytes found",
+            message_len,
+            crypto.len()
+        )));
+    }
+
+    tls::parse_client_hello(&crypto[..message_len])
+}
+
+/// Collect CRYPTO frames and return data which is contiguous from offset 0
+fn read_crypto_frames(payload: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = Reader::new(payload);
+    let mut frames = vec![];
+
+    while reader.remaining() > 0 {
+        let frame_type = reader.read_varint()?;
+
+        match frame_type {
+            FRAME_PADDING | FRAME_PING => {}
+            FRAME_ACK | FRAME_ACK_ECN => {
+                // Largest Acknowledged, ACK Delay, ACK Range Count, First ACK Range
+                reader.read_varint()?;
+                reader.read_varint()?;
+                let range_count = reader.read_varint()?;
+                reader.read_varint()?;
+
+                // Gap, ACK Range Length
+                for _ in 0..range_count {
+                    reader.read_varint()?;
+                    reader.read_varint()?;
+                }
+
+                // ECT0 Count, ECT1 Count, ECN-CE Count
+                if frame_type == FRAME_ACK_ECN {
+                    reader.read_varint()?;
+                    reader.read_varint()?;
+                    reader.read_varint()?;
+                }
+            }
+            FRAME_CRYPTO => {
+                let offset = reader.read_varint()? as usize;
+                let len = reader.read_varint()? as usize;
+                frames.push((offset, reader.read(len)?));
+            }
+            FRAME_CONNECTION_CLOSE => {
+                return Err(Error::msg("Initial packet contains CONNECTION_CLOSE frame"));
+            }
+            _ => {
+                return Err(Error::msg(format!(
+                    "unexpected frame type {:#04x} in Initial packet",
+                    frame_type
+                )));
+            }
+        }
+    }
+
+    // CRYPTO frames may be out of order
+    frames.sort_by_key(|(offset, _)| *offset);
+
+    let mut crypto = vec![];
+
+    for (offset, data) in frames {
+        if offset > crypto.len() {
+            break;
+        }
+
+        let end = offset + data.len();
+
+        if end > crypto.len() {
+            crypto.extend_from_slice(&data[crypto.len() - offset..]);
+        }
+    }
+
+    Ok(crypto)
+}
+
+/// A bounds checked reader for QUIC packets
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(Error::msg(format!(
+                "QUIC packet is truncated, expect {} bytes but only {} bytes left",
+                len,
+                self.buf.len()
+            )));
+        }
+
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+
+        Ok(head)
+    }
+
+    fn read_cid(&mut self) -> Result<&'a [u8]> {
+        let len = self.read(1)?[0] as usize;
+
+        if len > MAX_CID_LEN {
+            return Err(Error::msg(format!(
+                "Connection ID Length({}) exceeds {} bytes",
+                len, MAX_CID_LEN
+            )));
+        }
+
+        self.read(len)
+    }
+
+    /// Variable-Length Integer, the two most significant bits of the first byte encode the length
+    fn read_varint(&mut self) -> Result<u64> {
+        let first = self.read(1)?[0];
+        let len = 1 << (first >> 6);
+        let rest = self.read(len - 1)?;
+
+        Ok(rest
+            .iter()
+            .fold((first & 0x3f) as u64, |num, byte| (num << 8) | *byte as u64))
+    }
+}
@@ -1,6 +1,6 @@
 use std::fs;
 
-use anyhow::Result;
+use anyhow::{Error, Result};
 use rcgen::generate_simple_self_signed;
 use rustls::{Certificate, PrivateKey};
 
@@ -22,3 +22,157 @@ pub fn read_key_from_file(key_path: &str) -> Result<PrivateKey> {
     let key = PrivateKey(key_buf);
     Ok(key)
 }
+
+pub const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 0x01;
+
+/// The max length of ClientHello message we can accept
+pub const MAX_CLIENT_HELLO_LEN: usize = 0xffff;
+
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_ALPN: u16 = 0x0010;
+
+const SERVER_NAME_TYPE_HOST_NAME: u8 = 0x00;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientHello {
+    /// Value of server_name extension (SNI)
+    pub server_name: Option<String>,
+    /// Protocol names of application_layer_protocol_negotiation extension (ALPN)
+    pub alpn: Vec<String>,
+}
+
+/// Parse a complete ClientHello handshake message, including the 4 bytes handshake header
+pub fn parse_client_hello(buf: &[u8]) -> Result<ClientHello> {
+    // Handshake Message
+    // +------+--------+---------+
+    // | Type | Length |  Body   |
+    // +------+--------+---------+
+    // |  1   |   3    | Length  |
+    // +------+--------+---------+
+
+    let mut reader = ByteReader::new(buf);
+
+    let handshake_type = reader.read_u8()?;
+
+    if handshake_type != HANDSHAKE_TYPE_CLIENT_HELLO {
+        return Err(Error::msg(format!(
+            "Handshake Type must be Client Hello (0x01), but got {:#04x}",
+            handshake_type
+        )));
+    }
+
+    let len = reader.read_u24()?;
+    let mut body = ByteReader::new(reader.read(len)?);
+
+    // legacy_version is frozen at TLS 1.2 (0x0303), but older clients may send 0x0300 ~ 0x0302
+    let version = body.read(2)?;
+
+    if version[0] != 0x03 {
+        return Err(Error::msg(format!(
+            "Version must be 0x03xx, but got {}",
+            super::fmt::ToHex(version.to_vec())
+        )));
+    }
+
+    // skip Random(32 bytes)
+    body.read(32)?;
+
+    // skip Session ID, Cipher Suites and Compression Methods
+    body.read_vec8()?;
+    body.read_vec16()?;
+    body.read_vec8()?;
+
+    let mut client_hello = ClientHello::default();
+
+    // Extensions are optional before TLS 1.3
+    if body.is_empty() {
+        return Ok(client_hello);
+    }
+
+    let mut extensions = ByteReader::new(body.read_vec16()?);
+
+    while !extensions.is_empty() {
+        let ext_type = extensions.read_u16()?;
+        let mut ext = ByteReader::new(extensions.read_vec16()?);
+
+        match ext_type {
+            EXTENSION_SERVER_NAME => {
+                let mut list = ByteReader::new(ext.read_vec16()?);
+
+                while !list.is_empty() {
+                    let name_type = list.read_u8()?;
+                    let name = list.read_vec16()?;
+
+                    if name_type == SERVER_NAME_TYPE_HOST_NAME && client_hello.server_name.is_none() {
+                        client_hello.server_name = Some(String::from_utf8(name.to_vec())?);
+                    }
+                }
+            }
+            EXTENSION_ALPN => {
+                let mut list = ByteReader::new(ext.read_vec16()?);
+
+                while !list.is_empty() {
+                    let name = list.read_vec8()?;
+                    client_hello.alpn.push(String::from_utf8(name.to_vec())?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(client_hello)
+}
+
+/// A bounds checked reader for TLS structures
+struct ByteReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(Error::msg(format!(
+                "ClientHello is truncated, expect {} bytes but only {} bytes left",
+                len,
+                self.buf.len()
+            )));
+        }
+
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+
+        Ok(head)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        let buf = self.read(2)?;
+        Ok(u16::from_be_bytes([buf[0], buf[1]]))
+    }
+
+    fn read_u24(&mut self) -> Result<usize> {
+        let buf = self.read(3)?;
+        Ok(u32::from_be_bytes([0, buf[0], buf[1], buf[2]]) as usize)
+    }
+
+    fn read_vec8(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u8()? as usize;
+        self.read(len)
+    }
+
+    fn read_vec16(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u16()? as usize;
+        self.read(len)
+    }
+}
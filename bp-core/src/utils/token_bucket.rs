@@ -0,0 +1,42 @@
+use std::time::{Duration, Instant};
+
+/// A token bucket refilled at a fixed rate, which allows bursts up to one second of tokens.
+/// Taking more tokens than available is allowed, the caller should wait for the debt to be paid off.
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket refilled with rate tokens per second
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+
+        Self {
+            rate,
+            tokens: rate,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take n tokens, return how long the caller should wait before using them
+    pub fn take(&mut self, n: usize) -> Duration {
+        self.refill();
+        self.tokens -= n as f64;
+
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(-self.tokens / self.rate)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+    }
+}
@@ -0,0 +1,41 @@
+use bp_core::utils::aead::{AeadAlgorithm, AeadCipher};
+
+#[test]
+fn test_parse_algorithm() {
+    assert_eq!(
+        "aes-256-gcm".parse::<AeadAlgorithm>().unwrap(),
+        AeadAlgorithm::Aes256Gcm
+    );
+    assert_eq!(
+        "XChaCha20-Poly1305".parse::<AeadAlgorithm>().unwrap(),
+        AeadAlgorithm::XChaCha20Poly1305
+    );
+    assert!("aes-128-gcm".parse::<AeadAlgorithm>().is_err());
+}
+
+#[test]
+fn test_encrypt_decrypt() {
+    let key = [7u8; 32];
+
+    for algorithm in [
+        AeadAlgorithm::ChaCha20Poly1305,
+        AeadAlgorithm::XChaCha20Poly1305,
+        AeadAlgorithm::Aes256Gcm,
+    ] {
+        let cipher = AeadCipher::new(algorithm, &key).unwrap();
+        let nonce = vec![1u8; algorithm.nonce_size()];
+
+        let cipher_text = cipher.encrypt(&nonce, b"hello").unwrap();
+        assert_eq!(cipher_text.len(), 5 + algorithm.tag_size());
+        assert_eq!(&cipher.decrypt(&nonce, &cipher_text).unwrap()[..], b"hello");
+
+        // wrong nonce size is an error rather than panic
+        assert!(cipher.encrypt(&[0u8; 8], b"hello").is_err());
+        // cipher text cannot be decrypted with another nonce
+        assert!(cipher
+            .decrypt(&[0u8; 24][..algorithm.nonce_size()], &cipher_text)
+            .is_err());
+    }
+
+    assert!(AeadCipher::new(AeadAlgorithm::Aes256Gcm, &[0u8; 16]).is_err());
+}
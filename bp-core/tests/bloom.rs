@@ -0,0 +1,70 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bp_core::utils::bloom::{BloomFilter, RotatingBloomFilter};
+
+#[test]
+fn test_bloom_filter() {
+    let mut filter = BloomFilter::new(1000, 1e-6);
+
+    assert!(filter.is_empty());
+
+    for i in 0..1000u32 {
+        filter.insert(&i.to_be_bytes());
+    }
+
+    assert_eq!(filter.len(), 1000);
+    assert!(filter.is_full());
+    assert!((0..1000u32).all(|i| filter.contains(&i.to_be_bytes())));
+    assert!((1000..2000u32).all(|i| !filter.contains(&i.to_be_bytes())));
+}
+
+#[test]
+fn test_rotating_bloom_filter() {
+    let now = Arc::new(Mutex::new(Instant::now()));
+    let clock = {
+        let now = now.clone();
+        move || *now.lock().unwrap()
+    };
+    let mut filter = RotatingBloomFilter::with_clock(100, 1e-6, Duration::from_secs(60), clock);
+
+    assert!(!filter.contains_or_insert(b"salt"));
+    assert!(filter.contains_or_insert(b"salt"));
+
+    // remembered by the previous filter after the first rotation
+    *now.lock().unwrap() += Duration::from_secs(60);
+    assert!(filter.contains_or_insert(b"salt"));
+
+    // forgotten after the second rotation
+    *now.lock().unwrap() += Duration::from_secs(60);
+    assert!(!filter.contains_or_insert(b"salt"));
+}
+
+#[test]
+fn test_rotating_bloom_filter_full() {
+    let now = Arc::new(Mutex::new(Instant::now()));
+    let clock = {
+        let now = now.clone();
+        move || *now.lock().unwrap()
+    };
+    let mut filter = RotatingBloomFilter::with_clock(10, 1e-6, Duration::from_secs(60), clock);
+
+    for i in 0..10u32 {
+        assert!(!filter.contains_or_insert(&i.to_be_bytes()));
+    }
+
+    // new items are still accepted by rotating early, seen items are recognized by the previous filter
+    *now.lock().unwrap() += Duration::from_secs(1);
+    assert!(!filter.contains_or_insert(&10u32.to_be_bytes()));
+    assert!(filter.contains_or_insert(&10u32.to_be_bytes()));
+    assert!(filter.contains_or_insert(&0u32.to_be_bytes()));
+
+    // the oldest generation is forgotten once the current one is full again
+    for i in 11..20u32 {
+        assert!(!filter.contains_or_insert(&i.to_be_bytes()));
+    }
+    assert!(!filter.contains_or_insert(&20u32.to_be_bytes()));
+    assert!(!filter.contains_or_insert(&0u32.to_be_bytes()));
+}
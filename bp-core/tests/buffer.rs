@@ -34,3 +34,12 @@ fn test_num_to_buf_le() {
         Bytes::from_static(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xf0])
     );
 }
+
+#[test]
+fn test_num_to_buf_le_extended() {
+    let buf = num_to_buf_le(0x0102, 24);
+
+    assert_eq!(buf.len(), 24);
+    assert_eq!(buf[..3], [0x02, 0x01, 0x00]);
+    assert!(buf[2..].iter().all(|b| *b == 0));
+}
@@ -0,0 +1,31 @@
+use bp_core::utils::crypto::Crypto;
+
+#[test]
+fn test_evp_bytes_to_key() {
+    assert_eq!(
+        Crypto::evp_bytes_to_key(b"foobar", 32).to_vec(),
+        hex("3858f62230ac3c915f300c664312c63f568378529614d22ddb49237d2f60bfdf")
+    );
+    assert_eq!(
+        Crypto::evp_bytes_to_key(b"foobar", 16).to_vec(),
+        hex("3858f62230ac3c915f300c664312c63f")
+    );
+}
+
+#[test]
+fn test_hkdf_sha1() {
+    let key = Crypto::evp_bytes_to_key(b"foobar", 32);
+    let salt: Vec<u8> = (0..32).collect();
+
+    assert_eq!(
+        Crypto::hkdf_sha1(key, salt.into(), "ss-subkey".into(), 32).to_vec(),
+        hex("c4f0e9818348b2f30188d82b37a4cddc9f5ea531070ec67225160209faff573c")
+    );
+}
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
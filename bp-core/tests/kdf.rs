@@ -0,0 +1,129 @@
+use bp_core::utils::kdf::KeyDerivation;
+
+const SALT: [u8; 16] = *b"0123456789abcdef";
+
+#[test]
+fn test_parse_key_derivation() {
+    assert_eq!("none".parse(), Ok(KeyDerivation::None));
+    assert_eq!(
+        "argon2id:salt=MDEyMzQ1Njc4OWFiY2RlZg".parse(),
+        Ok(KeyDerivation::Argon2id {
+            m: 19456,
+            t: 2,
+            p: 1,
+            salt: SALT
+        })
+    );
+    assert_eq!(
+        "argon2id:m=65536,t=3,salt=MDEyMzQ1Njc4OWFiY2RlZg".parse(),
+        Ok(KeyDerivation::Argon2id {
+            m: 65536,
+            t: 3,
+            p: 1,
+            salt: SALT
+        })
+    );
+    assert_eq!(
+        "scrypt:n=15,salt=MDEyMzQ1Njc4OWFiY2RlZg".parse(),
+        Ok(KeyDerivation::Scrypt {
+            log_n: 15,
+            r: 8,
+            p: 1,
+            salt: SALT
+        })
+    );
+
+    assert!("bcrypt".parse::<KeyDerivation>().is_err());
+    assert!("argon2id:x=1,salt=MDEyMzQ1Njc4OWFiY2RlZg"
+        .parse::<KeyDerivation>()
+        .is_err());
+    assert!("argon2id:m,salt=MDEyMzQ1Njc4OWFiY2RlZg"
+        .parse::<KeyDerivation>()
+        .is_err());
+    assert!("scrypt:n=256,salt=MDEyMzQ1Njc4OWFiY2RlZg"
+        .parse::<KeyDerivation>()
+        .is_err());
+
+    // salt is required and must be 16 bytes
+    assert!("argon2id".parse::<KeyDerivation>().is_err());
+    assert!("scrypt:n=15".parse::<KeyDerivation>().is_err());
+    assert!("argon2id:salt=MDEyMzQ1Njc".parse::<KeyDerivation>().is_err());
+    assert!("argon2id:salt=MDEyMzQ1Njc4OWFiY2RlZg=="
+        .parse::<KeyDerivation>()
+        .is_err());
+
+    // parameters are always recorded
+    assert_eq!(
+        "argon2id:salt=MDEyMzQ1Njc4OWFiY2RlZg"
+            .parse::<KeyDerivation>()
+            .unwrap()
+            .to_string(),
+        "argon2id:m=19456,t=2,p=1,salt=MDEyMzQ1Njc4OWFiY2RlZg"
+    );
+    assert_eq!(
+        "scrypt:salt=MDEyMzQ1Njc4OWFiY2RlZg"
+            .parse::<KeyDerivation>()
+            .unwrap()
+            .to_string(),
+        "scrypt:n=17,r=8,p=1,salt=MDEyMzQ1Njc4OWFiY2RlZg"
+    );
+}
+
+#[test]
+fn test_derive_key() {
+    assert_eq!(KeyDerivation::None.derive("key").unwrap(), "key");
+
+    assert_eq!(
+        KeyDerivation::Argon2id {
+            m: 64,
+            t: 1,
+            p: 1,
+            salt: SALT
+        }
+        .derive("key")
+        .unwrap(),
+        "27fff6e96709885585a402abb0069e31b715320d9da8623dc416986ddd1b0b26"
+    );
+
+    assert_eq!(
+        KeyDerivation::Scrypt {
+            log_n: 10,
+            r: 8,
+            p: 1,
+            salt: SALT
+        }
+        .derive("key")
+        .unwrap(),
+        "d9d9d6e0573937c46e1d6a26db8d05128aa82d69a36870000c300cbc28b398c1"
+    );
+
+    // different salts derive different keys
+    assert_ne!(
+        KeyDerivation::Argon2id {
+            m: 64,
+            t: 1,
+            p: 1,
+            salt: [0; 16]
+        }
+        .derive("key")
+        .unwrap(),
+        KeyDerivation::Argon2id {
+            m: 64,
+            t: 1,
+            p: 1,
+            salt: SALT
+        }
+        .derive("key")
+        .unwrap()
+    );
+
+    // invalid parameters
+    assert!(KeyDerivation::Argon2id {
+        m: 1,
+        t: 1,
+        p: 1,
+        salt: SALT
+    }
+    .derive("key")
+    .is_err());
+}
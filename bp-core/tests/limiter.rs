@@ -0,0 +1,102 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use bp_core::{utils::token_bucket::TokenBucket, ServerOptions, User, UserLimiter, UserLimits};
+
+#[test]
+fn test_token_bucket() {
+    let mut bucket = TokenBucket::new(1000);
+
+    // burst of one second is allowed
+    assert_eq!(bucket.take(1000), Duration::ZERO);
+
+    let wait = bucket.take(500);
+    assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+}
+
+#[test]
+fn test_user_limiter() {
+    let limiter = UserLimiter::default();
+
+    // bob has its own limit of connections, others fall back to the global ones
+    let opts = ServerOptions {
+        users: vec![
+            "alice:a".parse().unwrap(),
+            User {
+                max_connections: Some(1),
+                ..User::from_str("bob:b").unwrap()
+            },
+        ],
+        user_quota: Some(100),
+        user_max_connections: Some(2),
+        ..Default::default()
+    };
+
+    let limits = opts.user_limits();
+    assert_eq!(
+        limits["bob"],
+        UserLimits {
+            monthly_quota: Some(100),
+            max_connections: Some(1),
+            bandwidth: None,
+        }
+    );
+
+    limiter.set_limits(limits);
+
+    assert!(limiter.acquire("alice").is_ok());
+    assert!(limiter.acquire("alice").is_ok());
+    assert!(limiter.acquire("alice").is_err());
+    assert!(limiter.acquire("bob").is_ok());
+    assert!(limiter.acquire("bob").is_err());
+
+    // users without limits
+    assert!(limiter.acquire("carol").is_ok());
+    assert!(limiter.acquire("carol").is_ok());
+    assert!(limiter.acquire("carol").is_ok());
+
+    limiter.release("alice");
+    assert!(limiter.acquire("alice").is_ok());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("create tokio runtime");
+
+    runtime.block_on(async {
+        assert!(limiter.consume("alice", 60).await.is_ok());
+        assert!(limiter.consume("alice", 50).await.is_err());
+    });
+
+    assert_eq!(limiter.usage("alice"), 110);
+    assert_eq!(limiter.usage("bob"), 0);
+
+    // quota is used up
+    limiter.release("alice");
+    assert!(limiter.acquire("alice").is_err());
+}
+
+#[test]
+fn test_user_limiter_persist() {
+    let tmp_path = "tests/tmp/usage.json";
+
+    let limiter = UserLimiter::default();
+    limiter.set_limits(HashMap::new());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("create tokio runtime");
+
+    runtime.block_on(async {
+        limiter.consume("alice", 1024).await.unwrap();
+    });
+
+    assert!(limiter.save_to_file(tmp_path).is_ok());
+
+    let limiter = UserLimiter::default();
+    assert!(limiter.load_from_file(tmp_path).is_ok());
+    assert_eq!(limiter.usage("alice"), 1024);
+
+    // a missing file means no usage yet
+    assert!(limiter.load_from_file("tests/tmp/not_exist.json").is_ok());
+}
@@ -29,7 +29,7 @@ mod test_common {
 
 #[cfg(test)]
 mod test_client {
-    use bp_core::ClientOptions;
+    use bp_core::{ClientOptions, Credentials, EncryptionMethod, Transport};
 
     #[test]
     fn test_checker() {
@@ -48,6 +48,24 @@ mod test_client {
         };
         assert!(opts.check().is_err());
 
+        let opts = ClientOptions {
+            key: Some("key".to_string()),
+            server_bind: Some("127.0.0.1:1081".parse().unwrap()),
+            encryption: "aes-256-gcm".parse::<EncryptionMethod>().unwrap(),
+            udp_over_tcp: true,
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        let opts = ClientOptions {
+            key: Some("key".to_string()),
+            server_bind: Some("127.0.0.1:1081".parse().unwrap()),
+            encryption: "aes-256-gcm".parse::<EncryptionMethod>().unwrap(),
+            key_derivation: "argon2id:salt=MDEyMzQ1Njc4OWFiY2RlZg".parse().unwrap(),
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
         let opts = ClientOptions {
             tls: true,
             quic: true,
@@ -66,12 +84,105 @@ mod test_client {
             ..Default::default()
         };
         assert!(opts.check().is_err());
+
+        let opts = ClientOptions {
+            mux: true,
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        let opts = ClientOptions {
+            key: Some("key".to_string()),
+            server_bind: Some("127.0.0.1:1081".parse().unwrap()),
+            encryption: "aes-256-gcm".parse::<EncryptionMethod>().unwrap(),
+            mux: true,
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        let opts = ClientOptions {
+            key: Some("key".to_string()),
+            server_bind: Some("127.0.0.1:1081".parse().unwrap()),
+            mux: true,
+            quic: true,
+            tls_cert: Some("cert.der".to_string()),
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        let mut opts = ClientOptions {
+            key: Some("key".to_string()),
+            server_bind: Some("127.0.0.1:1081".parse().unwrap()),
+            quic_datagram: true,
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        opts.quic = true;
+        opts.tls_cert = Some("cert.der".to_string());
+        assert!(opts.check().is_ok());
+
+        opts.udp_over_tcp = true;
+        assert!(opts.check().is_err());
+
+        let mut opts = ClientOptions {
+            key: Some("key".to_string()),
+            transport: Some(Transport::WebSocket),
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        opts.server_bind = Some("127.0.0.1:1081".parse().unwrap());
+        assert!(opts.check().is_ok());
+
+        opts.transport_path = "ws".to_string();
+        assert!(opts.check().is_err());
+
+        opts.transport_path = "/ws".to_string();
+        opts.quic = true;
+        opts.tls_cert = Some("cert.der".to_string());
+        assert!(opts.check().is_err());
+
+        let mut opts = ClientOptions {
+            key: Some("key".to_string()),
+            server_bind: Some("127.0.0.1:1081".parse().unwrap()),
+            transport: Some(Transport::H2),
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        opts.tls = true;
+        opts.tls_cert = Some("cert.der".to_string());
+        assert!(opts.check().is_ok());
+
+        opts.mux = true;
+        assert!(opts.check().is_err());
+
+        let opts = ClientOptions {
+            socks_auth: Some(format!("user:{}", "p".repeat(256)).parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+    }
+
+    #[test]
+    fn test_credentials() {
+        let auth = "user:pa:ss".parse::<Credentials>().unwrap();
+        assert_eq!(auth.user(), "user");
+        assert_eq!(auth.password(), "pa:ss");
+        assert!(auth.verify(b"user", b"pa:ss"));
+        assert!(!auth.verify(b"user", b"pa"));
+        assert!(!auth.verify(b"use", b"pa:ss"));
+
+        assert!("user".parse::<Credentials>().is_err());
+        assert!(":pass".parse::<Credentials>().is_err());
+        assert!("user:".parse::<Credentials>().is_err());
     }
 }
 
 #[cfg(test)]
 mod test_server {
-    use bp_core::ServerOptions;
+    use bp_core::{EncryptionMethod, HandshakeFailurePolicy, ServerOptions, Transport, User};
 
     #[test]
     fn test_checker() {
@@ -101,5 +212,98 @@ mod test_server {
 
         opts.tls_key = Some("key.der".to_string());
         assert!(opts.check().is_ok());
+
+        opts.transport = Some(Transport::WebSocket);
+        assert!(opts.check().is_err());
+
+        let mut opts = ServerOptions {
+            key: Some("key".to_string()),
+            transport: Some(Transport::WebSocket),
+            ..Default::default()
+        };
+        assert!(opts.check().is_ok());
+
+        opts.transport_path = "ws".to_string();
+        assert!(opts.check().is_err());
+
+        let mut opts = ServerOptions {
+            key: Some("key".to_string()),
+            transport: Some(Transport::H2),
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        opts.tls = true;
+        opts.tls_cert = Some("cert.der".to_string());
+        opts.tls_key = Some("key.der".to_string());
+        assert!(opts.check().is_ok());
+    }
+
+    #[test]
+    fn test_users() {
+        let user: User = "alice:secret:with:colons".parse().unwrap();
+        assert_eq!(user.name, "alice");
+        assert_eq!(user.key, "secret:with:colons");
+
+        assert!("alice".parse::<User>().is_err());
+        assert!(":secret".parse::<User>().is_err());
+        assert!("alice:".parse::<User>().is_err());
+
+        // a user with its own limits in configuration files
+        assert!(serde_json::from_str::<User>(r#"{ "name": "carol", "key": "" }"#).is_err());
+
+        let users: Vec<User> =
+            serde_json::from_str(r#"["alice:a", { "name": "bob", "key": "b", "max_connections": 1 }]"#).unwrap();
+        assert_eq!(users[0].max_connections, None);
+        assert_eq!(users[1].max_connections, Some(1));
+        assert_eq!(serde_json::to_string(&users[0]).unwrap(), r#""alice:a""#);
+        assert_eq!(
+            serde_json::to_string(&users[1]).unwrap(),
+            r#"{"name":"bob","key":"b","max_connections":1}"#
+        );
+
+        // users without a shared key
+        let mut opts = ServerOptions {
+            users: vec!["alice:a".parse().unwrap(), "bob:b".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(opts.check().is_ok());
+
+        opts.users.push("alice:c".parse().unwrap());
+        assert!(opts.check().is_err());
+
+        opts.users.pop();
+        opts.key = Some("a".to_string());
+        assert!(opts.check().is_err());
+
+        opts.key = None;
+        opts.encryption = EncryptionMethod::Plain;
+        assert!(opts.check().is_err());
+
+        // limits of users require users
+        let mut opts = ServerOptions {
+            key: Some("key".to_string()),
+            user_quota: Some(1024),
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+
+        opts.users.push("alice:a".parse().unwrap());
+        assert!(opts.check().is_ok());
+    }
+
+    #[test]
+    fn test_handshake_failure() {
+        assert_eq!("close".parse(), Ok(HandshakeFailurePolicy::Close));
+        assert_eq!("drain".parse(), Ok(HandshakeFailurePolicy::Drain));
+        assert_eq!(
+            "fallback:127.0.0.1:80".parse(),
+            Ok(HandshakeFailurePolicy::Fallback("127.0.0.1:80".parse().unwrap()))
+        );
+        assert!("fallback:".parse::<HandshakeFailurePolicy>().is_err());
+        assert!("reset".parse::<HandshakeFailurePolicy>().is_err());
+
+        let policy = HandshakeFailurePolicy::Fallback("example.com:443".parse().unwrap());
+        assert_eq!(policy.to_string(), "fallback:example.com:443");
     }
 }
@@ -0,0 +1,88 @@
+use std::net::SocketAddr;
+
+use bp_core::utils::proxy_protocol::*;
+
+#[test]
+fn test_parse_v1() {
+    let header = parse_v1(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(header.source, "192.168.0.1:56324".parse().unwrap());
+    assert_eq!(header.destination, "192.168.0.11:443".parse().unwrap());
+
+    let header = parse_v1(b"PROXY TCP6 ::1 2001:db8::1 56324 443\r\n").unwrap().unwrap();
+
+    assert_eq!(header.source, "[::1]:56324".parse().unwrap());
+    assert_eq!(header.destination, "[2001:db8::1]:443".parse().unwrap());
+
+    assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n").unwrap(), None);
+    assert_eq!(parse_v1(b"PROXY UNKNOWN ::1 ::1 1 2\r\n").unwrap(), None);
+}
+
+#[test]
+fn test_parse_v1_invalid() {
+    assert!(parse_v1(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443").is_err());
+    assert!(parse_v1(b"PROXY TCP4 ::1 192.168.0.11 56324 443\r\n").is_err());
+    assert!(parse_v1(b"PROXY UDP4 192.168.0.1 192.168.0.11 56324 443\r\n").is_err());
+    assert!(parse_v1(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324\r\n").is_err());
+}
+
+#[test]
+fn test_parse_v2() {
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.extend_from_slice(&[0x21, 0x11, 0x00, 0x0f]);
+    buf.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 11, 0xdc, 0x04, 0x01, 0xbb]);
+    // TLV is ignored
+    buf.extend_from_slice(&[0x04, 0x00, 0x00]);
+
+    let header = parse_v2(&buf).unwrap().unwrap();
+
+    assert_eq!(header.source, "192.168.0.1:56324".parse().unwrap());
+    assert_eq!(header.destination, "192.168.0.11:443".parse().unwrap());
+
+    // LOCAL command
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+
+    assert_eq!(parse_v2(&buf).unwrap(), None);
+}
+
+#[test]
+fn test_parse_v2_invalid() {
+    // Length mismatch
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.extend_from_slice(&[0x21, 0x11, 0x00, 0x0c, 192, 168, 0, 1]);
+    assert!(parse_v2(&buf).is_err());
+
+    // unknown version
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.extend_from_slice(&[0x11, 0x00, 0x00, 0x00]);
+    assert!(parse_v2(&buf).is_err());
+}
+
+#[test]
+fn test_encode() {
+    let source: SocketAddr = "192.168.0.1:56324".parse().unwrap();
+    let destination: SocketAddr = "192.168.0.11:443".parse().unwrap();
+
+    assert_eq!(
+        encode(ProxyProtocolVersion::V1, source, destination),
+        &b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n"[..]
+    );
+
+    let buf = encode(ProxyProtocolVersion::V2, source, destination);
+    let header = parse_v2(&buf).unwrap().unwrap();
+
+    assert_eq!(header.source, source);
+    assert_eq!(header.destination, destination);
+
+    // mixed address families are mapped to ipv6
+    let destination: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+    let buf = encode(ProxyProtocolVersion::V1, source, destination);
+
+    assert_eq!(
+        &buf[..],
+        &b"PROXY TCP6 ::ffff:192.168.0.1 2001:db8::1 56324 443\r\n"[..]
+    );
+}
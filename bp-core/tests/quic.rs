@@ -0,0 +1,36 @@
+use bp_core::utils::quic::*;
+
+#[test]
+fn test_check_initial_packet() {
+    assert!(check_initial_packet(include_bytes!("fixtures/quic_initial.bin")));
+
+    // short header
+    assert!(!check_initial_packet(&[0x40, 0x00, 0x00, 0x00, 0x01]));
+
+    // handshake packet
+    assert!(!check_initial_packet(&[0xe0, 0x00, 0x00, 0x00, 0x01]));
+
+    // quic v2
+    assert!(!check_initial_packet(&[0xc0, 0x6b, 0x33, 0x43, 0xcf]));
+}
+
+#[test]
+fn test_parse_initial_packet() {
+    let client_hello = parse_initial_packet(include_bytes!("fixtures/quic_initial.bin")).unwrap();
+
+    assert_eq!(client_hello.server_name, Some("www.example.com".into()));
+    assert_eq!(client_hello.alpn, vec!["h3".to_string()]);
+}
+
+#[test]
+fn test_parse_initial_packet_invalid() {
+    let buf = include_bytes!("fixtures/quic_initial.bin");
+
+    // truncated
+    assert!(parse_initial_packet(&buf[..100]).is_err());
+
+    // tampered payload
+    let mut tampered = buf.to_vec();
+    tampered[500] ^= 0xff;
+    assert!(parse_initial_packet(&tampered).is_err());
+}
@@ -0,0 +1,43 @@
+use bp_core::utils::tls::*;
+
+#[test]
+fn test_parse_client_hello() {
+    // skip the 5 bytes record header
+    let buf = &include_bytes!("fixtures/client_hello.bin")[5..];
+    let client_hello = parse_client_hello(buf).unwrap();
+
+    assert_eq!(client_hello.server_name, Some("www.baidu.com".into()));
+    assert_eq!(client_hello.alpn, vec!["h2".to_string(), "http/1.1".to_string()]);
+}
+
+#[test]
+fn test_parse_client_hello_without_extensions() {
+    let mut body = vec![0x03, 0x01];
+    body.extend_from_slice(&[0u8; 32]);
+    body.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x2f, 0x01, 0x00]);
+
+    let mut buf = vec![0x01, 0x00, 0x00, body.len() as u8];
+    buf.extend_from_slice(&body);
+
+    let client_hello = parse_client_hello(&buf).unwrap();
+
+    assert_eq!(client_hello, ClientHello::default());
+}
+
+#[test]
+fn test_parse_client_hello_invalid() {
+    let buf = &include_bytes!("fixtures/client_hello.bin")[5..];
+
+    // truncated
+    assert!(parse_client_hello(&buf[..buf.len() - 1]).is_err());
+
+    // not a ClientHello
+    let mut server_hello = buf.to_vec();
+    server_hello[0] = 0x02;
+    assert!(parse_client_hello(&server_hello).is_err());
+
+    // unknown version
+    let mut unknown_version = buf.to_vec();
+    unknown_version[4] = 0x04;
+    assert!(parse_client_hello(&unknown_version).is_err());
+}
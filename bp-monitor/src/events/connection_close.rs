@@ -10,6 +10,8 @@ pub struct ConnectionClose {
     pub peer_addr: SocketAddr,
     pub live_cnt: usize,
     pub total_cnt: usize,
+    /// The user identified by key on server side
+    pub user: Option<String>,
 }
 
 impl Event for ConnectionClose {}
@@ -72,7 +72,7 @@ export class ConfigurationCtrl extends ControllerBaseProxy<Data> {
     if (RUN_TYPE_CLIENT && config?.tls_key) {
       return false;
     }
-    if (RUN_TYPE_SERVER && config?.with_basic_auth) {
+    if (RUN_TYPE_SERVER && (config?.with_basic_auth || config?.socks_auth || config?.https_bind)) {
       return false;
     }
     return true;
@@ -1,6 +1,6 @@
 use std::net::SocketAddr;
 
-use bp_core::utils::net::create_udp_client_with_random_port;
+use bp_core::{utils::net::create_udp_client_with_random_port, Address};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
@@ -29,3 +29,41 @@ pub async fn udp_oneshot(bind_addr: SocketAddr, buf: &[u8]) -> Vec<u8> {
 
     buf[0..n].to_vec()
 }
+
+/// Send Socks5 BIND request, then connect to the replied address and send buf from there,
+/// returns what is received by the Socks5 client.
+pub async fn socks5_bind_oneshot(bind_addr: SocketAddr, buf: &[u8]) -> Vec<u8> {
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    let mut reply = [0u8; 10];
+
+    socket.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    socket.read_exact(&mut reply[0..2]).await.unwrap();
+
+    socket
+        .write_all(&[0x05, 0x02, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, 0x00, 0x00])
+        .await
+        .unwrap();
+
+    // first reply contains the listening address
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0..2], [0x05, 0x00]);
+
+    let (listen_addr, _) = Address::from_bytes(reply[3..].to_vec().into()).unwrap();
+
+    let mut peer = TcpStream::connect(listen_addr.as_socket_addr()).await.unwrap();
+
+    // second reply contains the address of incoming connection
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0..2], [0x05, 0x00]);
+
+    let (peer_addr, _) = Address::from_bytes(reply[3..].to_vec().into()).unwrap();
+    assert_eq!(peer_addr.as_socket_addr(), peer.local_addr().unwrap());
+
+    peer.write_all(buf).await.unwrap();
+    peer.flush().await.unwrap();
+
+    let mut buf = vec![0u8; buf.len()];
+    socket.read_exact(&mut buf).await.unwrap();
+
+    buf
+}
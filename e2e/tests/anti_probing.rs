@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use bp_core::{Address, HandshakeFailurePolicy, Options, ServerOptions, ServiceInfo};
+use e2e::runner::run_bp;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+const PROBE: [u8; 256] = [0x5a; 256];
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_handshake_failure_close() {
+    let ServiceInfo { bind_addr, .. } = run_bp(Options::Server(ServerOptions {
+        key: Some("key".to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    socket.write_all(&PROBE).await.unwrap();
+
+    let mut buf = [0u8; 1];
+    let res = timeout(Duration::from_secs(5), socket.read(&mut buf)).await.unwrap();
+
+    assert!(matches!(res, Ok(0) | Err(_)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_handshake_failure_drain() {
+    let ServiceInfo { bind_addr, .. } = run_bp(Options::Server(ServerOptions {
+        key: Some("key".to_string()),
+        handshake_failure: HandshakeFailurePolicy::Drain,
+        ..Default::default()
+    }))
+    .await;
+
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    socket.write_all(&PROBE).await.unwrap();
+
+    // the connection is kept open rather than closed right away
+    let mut buf = [0u8; 1];
+    assert!(timeout(Duration::from_secs(2), socket.read(&mut buf)).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_handshake_failure_fallback() {
+    let fallback = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let ServiceInfo { bind_addr, .. } = run_bp(Options::Server(ServerOptions {
+        key: Some("key".to_string()),
+        handshake_failure: HandshakeFailurePolicy::Fallback(Address::from(fallback.local_addr().unwrap())),
+        ..Default::default()
+    }))
+    .await;
+
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    socket.write_all(&PROBE).await.unwrap();
+
+    // data consumed by handshake is relayed to fallback address as well
+    let (mut stream, _) = timeout(Duration::from_secs(5), fallback.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut buf = [0u8; PROBE.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+
+    assert_eq!(buf, PROBE);
+
+    stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await.unwrap();
+
+    let mut buf = [0u8; 28];
+    socket.read_exact(&mut buf).await.unwrap();
+
+    assert_eq!(&buf, b"HTTP/1.1 400 Bad Request\r\n\r\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_handshake_failure_fallback_with_acl() {
+    let fallback = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    // the fallback address is not allowed by acl, but it's not requested by peer
+    let ServiceInfo { bind_addr, .. } = run_bp(Options::Server(ServerOptions {
+        key: Some("key".to_string()),
+        handshake_failure: HandshakeFailurePolicy::Fallback(Address::from(fallback.local_addr().unwrap())),
+        acl: Some("tests/fixtures/acl.txt".to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    socket.write_all(&PROBE).await.unwrap();
+
+    let (mut stream, _) = timeout(Duration::from_secs(5), fallback.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut buf = [0u8; PROBE.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+
+    assert_eq!(buf, PROBE);
+}
@@ -27,6 +27,31 @@ async fn test_generate_configs() {
     insta::assert_snapshot!("server", fs::read_file(config_path_server).await);
 }
 
+#[test]
+fn test_generate_key() {
+    let key = generate::generate_key();
+
+    assert_eq!(key.len(), 43);
+    assert!(key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    assert_ne!(key, generate::generate_key());
+}
+
+#[test]
+fn test_generate_key_derivation() {
+    let kdf = generate::generate_key_derivation("argon2id").unwrap();
+
+    assert!(kdf.to_string().starts_with("argon2id:m=19456,t=2,p=1,salt="));
+    assert_eq!(kdf.to_string().parse(), Ok(kdf));
+    assert_ne!(kdf, generate::generate_key_derivation("argon2id").unwrap());
+
+    let kdf = generate::generate_key_derivation("scrypt:n=15").unwrap();
+
+    assert!(kdf.to_string().starts_with("scrypt:n=15,r=8,p=1,salt="));
+
+    assert!(generate::generate_key_derivation("none").is_err());
+    assert!(generate::generate_key_derivation("bcrypt").is_err());
+}
+
 // #[tokio::test(flavor = "multi_thread")]
 // async fn test_generate_certificate() {
 
@@ -1,3 +1,4 @@
+use bp_cli::commands::generate;
 use bp_core::{ClientOptions, EncryptionMethod, ServerOptions};
 use cmd_lib::run_fun;
 use e2e::runner::{run_all, TestResponse};
@@ -33,11 +34,11 @@ async fn test_plain() {
 async fn test_erp() {
     let resp = run_all(
         ClientOptions {
-            encryption: EncryptionMethod::EncryptRandomPadding,
+            encryption: EncryptionMethod::EncryptRandomPadding(Default::default()),
             ..Default::default()
         },
         ServerOptions {
-            encryption: EncryptionMethod::EncryptRandomPadding,
+            encryption: EncryptionMethod::EncryptRandomPadding(Default::default()),
             ..Default::default()
         },
         None,
@@ -55,3 +56,96 @@ async fn test_erp() {
         http_resp
     );
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_erp_key_derivation() {
+    for kdf in ["argon2id:m=64,t=1,p=1", "scrypt:n=10,r=8,p=1"] {
+        let key_derivation = generate::generate_key_derivation(kdf).unwrap();
+
+        let resp = run_all(
+            ClientOptions {
+                key_derivation,
+                ..Default::default()
+            },
+            ServerOptions {
+                key_derivation,
+                ..Default::default()
+            },
+            None,
+        )
+        .await;
+
+        let TestResponse {
+            bind_addr,
+            http_addr,
+            http_resp,
+        } = resp;
+
+        assert_eq!(
+            run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+            http_resp
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_erp_algorithms() {
+    for method in ["erp-xchacha20-poly1305", "erp-aes-256-gcm"] {
+        let encryption: EncryptionMethod = method.parse().unwrap();
+
+        let resp = run_all(
+            ClientOptions {
+                encryption,
+                ..Default::default()
+            },
+            ServerOptions {
+                encryption,
+                ..Default::default()
+            },
+            None,
+        )
+        .await;
+
+        let TestResponse {
+            bind_addr,
+            http_addr,
+            http_resp,
+        } = resp;
+
+        assert_eq!(
+            run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+            http_resp
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shadowsocks() {
+    for method in ["chacha20-ietf-poly1305", "aes-256-gcm"] {
+        let encryption: EncryptionMethod = method.parse().unwrap();
+
+        let resp = run_all(
+            ClientOptions {
+                encryption,
+                ..Default::default()
+            },
+            ServerOptions {
+                encryption,
+                ..Default::default()
+            },
+            None,
+        )
+        .await;
+
+        let TestResponse {
+            bind_addr,
+            http_addr,
+            http_resp,
+        } = resp;
+
+        assert_eq!(
+            run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+            http_resp
+        );
+    }
+}
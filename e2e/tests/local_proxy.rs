@@ -1,9 +1,15 @@
-use bp_core::{ClientOptions, Options, ServiceInfo};
+use std::{net::SocketAddr, time::Duration};
+
+use bp_core::{ClientOptions, Options, ServerOptions, ServiceInfo};
 use cmd_lib::run_fun;
 use e2e::{
     http_server::{run_http_mock_server, HttpServerContext},
-    oneshot::{tcp_oneshot, udp_oneshot},
-    runner::run_bp,
+    oneshot::{socks5_bind_oneshot, tcp_oneshot, udp_oneshot},
+    runner::{run_all, run_bp, TestResponse},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpSocket, TcpStream, UdpSocket},
 };
 
 #[tokio::test(flavor = "multi_thread")]
@@ -22,16 +28,241 @@ async fn test_socks5() {
     );
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks5_connection_refused() {
+    let opts = Options::Client(ClientOptions::default());
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+
+    // take a port which nobody is listening on
+    let port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let [port_hi, port_lo] = port.to_be_bytes();
+
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    let mut buf = [0u8; 10];
+
+    socket.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    socket.read_exact(&mut buf[0..2]).await.unwrap();
+
+    socket
+        .write_all(&[0x05, 0x01, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port_hi, port_lo])
+        .await
+        .unwrap();
+    socket.read_exact(&mut buf).await.unwrap();
+
+    // REP = connection refused
+    assert_eq!(buf[0..2], [0x05, 0x05]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks4() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+
+    let opts = Options::Client(ClientOptions::default());
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+    let bind_addr = bind_addr.to_string();
+
+    assert_eq!(run_fun!(curl --socks4 $bind_addr $http_addr).unwrap(), http_resp);
+    assert_eq!(run_fun!(curl --socks4a $bind_addr $http_addr).unwrap(), http_resp);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks5_with_auth() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+
+    let auth = "user:pass";
+
+    let opts = Options::Client(ClientOptions {
+        socks_auth: Some(auth.parse().unwrap()),
+        ..Default::default()
+    });
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+    let bind_addr = bind_addr.to_string();
+
+    assert!(run_fun!(curl --socks5 $bind_addr $http_addr).is_err());
+    assert!(run_fun!(curl --socks5 $bind_addr -U "user:wrong" $http_addr).is_err());
+    assert!(run_fun!(curl --socks4a $bind_addr $http_addr).is_err());
+    assert_eq!(
+        run_fun!(curl --socks5 $bind_addr -U $auth $http_addr).unwrap(),
+        http_resp
+    );
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_socks5_udp() {
     let opts = Options::Client(ClientOptions::default());
 
     let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
 
+    // UDP ASSOCIATE over the control connection first
+    let mut control = TcpStream::connect(bind_addr).await.unwrap();
+    let mut buf = [0u8; 10];
+
+    control.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    control.read_exact(&mut buf[0..2]).await.unwrap();
+    assert_eq!(buf[0..2], [0x05, 0x00]);
+
+    control
+        .write_all(&[0x05, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+        .await
+        .unwrap();
+    control.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf[0..4], [0x05, 0x00, 0x00, 0x01]);
+
     let buf = udp_oneshot(bind_addr, include_bytes!("fixtures/socks5_dns_query.bin")).await;
 
+    // reply is wrapped with RSV | FRAG | ATYP | DST.ADDR | DST.PORT
+    assert_eq!(buf[0..10], include_bytes!("fixtures/socks5_dns_query.bin")[0..10]);
+
     // TODO: improve this assertion
-    assert_eq!(buf[0..7], include_bytes!("fixtures/dns_resp.bin")[0..7]);
+    assert_eq!(buf[10..17], include_bytes!("fixtures/dns_resp.bin")[0..7]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks5_udp_announced_addr() {
+    let opts = Options::Client(ClientOptions::default());
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+
+    // a local udp echo server as the destination
+    let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let echo_addr = echo.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        while let Ok((n, addr)) = echo.recv_from(&mut buf).await {
+            let _ = echo.send_to(&buf[..n], addr).await;
+        }
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // UDP ASSOCIATE announces the address datagrams will be sent from
+    let mut control = TcpStream::connect(bind_addr).await.unwrap();
+    let mut buf = [0u8; 10];
+
+    control.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    control.read_exact(&mut buf[0..2]).await.unwrap();
+
+    let port = client.local_addr().unwrap().port().to_be_bytes();
+    control
+        .write_all(&[0x05, 0x03, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]])
+        .await
+        .unwrap();
+    control.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf[0..4], [0x05, 0x00, 0x00, 0x01]);
+
+    let port = echo_addr.port().to_be_bytes();
+    let packet = [
+        &[0x00, 0x00, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]][..],
+        b"hello",
+    ]
+    .concat();
+
+    // datagrams from other ports are dropped
+    other.send_to(&packet, bind_addr).await.unwrap();
+    let res = tokio::time::timeout(Duration::from_millis(500), other.recv(&mut [0u8; 1500])).await;
+    assert!(res.is_err());
+
+    client.send_to(&packet, bind_addr).await.unwrap();
+    let mut reply = [0u8; 1500];
+    let n = client.recv(&mut reply).await.unwrap();
+    assert_eq!(reply[..n], packet[..]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks5_bind() {
+    let opts = Options::Client(ClientOptions::default());
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+
+    assert_eq!(socks5_bind_oneshot(bind_addr, b"hello").await, b"hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks5_bind_via_server() {
+    let TestResponse { bind_addr, .. } = run_all(ClientOptions::default(), ServerOptions::default(), None).await;
+
+    assert_eq!(socks5_bind_oneshot(bind_addr, b"hello").await, b"hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks5_connect_status_via_server() {
+    let TestResponse { bind_addr, .. } = run_all(ClientOptions::default(), ServerOptions::default(), None).await;
+
+    // nothing is listening at the dest address
+    let port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+        .to_be_bytes();
+
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    let mut reply = [0u8; 10];
+
+    socket.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    socket.read_exact(&mut reply[0..2]).await.unwrap();
+
+    socket
+        .write_all(&[0x05, 0x01, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]])
+        .await
+        .unwrap();
+
+    // REP of bp server connecting to dest address is replied, rather than of bp client connecting to bp server
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0..2], [0x05, 0x05]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_socks5_bind_expected_addr() {
+    let TestResponse { bind_addr, .. } = run_all(ClientOptions::default(), ServerOptions::default(), None).await;
+
+    // the peer connects from a known port, which is the DST.PORT of BIND request
+    let peer = TcpSocket::new_v4().unwrap();
+    peer.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let port = peer.local_addr().unwrap().port().to_be_bytes();
+
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    let mut reply = [0u8; 10];
+
+    socket.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    socket.read_exact(&mut reply[0..2]).await.unwrap();
+
+    socket
+        .write_all(&[0x05, 0x02, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]])
+        .await
+        .unwrap();
+
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0..2], [0x05, 0x00]);
+
+    let listen_addr = SocketAddr::new([127, 0, 0, 1].into(), u16::from_be_bytes([reply[8], reply[9]]));
+
+    // connections from other ports are rejected
+    let mut other = TcpStream::connect(listen_addr).await.unwrap();
+    assert_eq!(other.read(&mut [0u8; 1]).await.unwrap_or(0), 0);
+
+    let mut peer = peer.connect(listen_addr).await.unwrap();
+
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0..2], [0x05, 0x00]);
+    assert_eq!(reply[8..10], port);
+
+    peer.write_all(b"hello").await.unwrap();
+
+    let mut buf = [0u8; 5];
+    socket.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -46,6 +277,108 @@ async fn test_http() {
     assert_eq!(run_fun!(curl -x $bind_addr $http_addr).unwrap(), http_resp);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_http_keep_alive() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+    let HttpServerContext {
+        http_addr: another_http_addr,
+        ..
+    } = run_http_mock_server();
+
+    let opts = Options::Client(ClientOptions::default());
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+    let bind_addr = bind_addr.to_string();
+
+    // requests for different dest addresses are sent on the same connection
+    assert_eq!(
+        run_fun!(curl -x $bind_addr -w "|%{num_connects}|" $http_addr $another_http_addr $http_addr).unwrap(),
+        format!("{0}|1|{0}|0|{0}|0|", http_resp)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_http_pipelining() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+    let HttpServerContext {
+        http_addr: another_http_addr,
+        ..
+    } = run_http_mock_server();
+
+    let opts = Options::Client(ClientOptions::default());
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+
+    // the second request is sent before the first one is responded
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    let requests = format!(
+        "GET http://{0}/ HTTP/1.1\r\nHost: {0}\r\n\r\nGET http://{1}/ HTTP/1.1\r\nHost: {1}\r\n\r\n",
+        http_addr, another_http_addr
+    );
+    socket.write_all(requests.as_bytes()).await.unwrap();
+
+    let mut buf = vec![];
+    let read_responses = async {
+        while String::from_utf8_lossy(&buf).matches(http_resp).count() < 2 {
+            let mut chunk = [0u8; 1024];
+            let n = socket.read(&mut chunk).await.unwrap();
+            assert_ne!(n, 0);
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), read_responses)
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_http_error_responses() {
+    let opts = Options::Client(ClientOptions::default());
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+    let bind_addr = bind_addr.to_string();
+
+    // take a port which nobody is listening on
+    let port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let refused_addr = format!("127.0.0.1:{}", port);
+
+    assert_eq!(
+        run_fun!(curl -s -o /dev/null -w "%{http_code}" -x $bind_addr $refused_addr).unwrap(),
+        "502"
+    );
+    assert_eq!(
+        run_fun!(ignore curl -s -o /dev/null -w "%{http_connect}" -p -x $bind_addr $refused_addr).unwrap(),
+        "502"
+    );
+    assert!(run_fun!(curl -s -x $bind_addr $refused_addr)
+        .unwrap()
+        .contains("connection refused"));
+
+    // dest address is bp itself
+    assert_eq!(
+        run_fun!(curl -s -o /dev/null -w "%{http_code}" -x $bind_addr $bind_addr).unwrap(),
+        "403"
+    );
+
+    // the status replied by bp server is propagated
+    let TestResponse { bind_addr, .. } = run_all(ClientOptions::default(), ServerOptions::default(), None).await;
+    let bind_addr = bind_addr.to_string();
+
+    assert_eq!(
+        run_fun!(curl -s -o /dev/null -w "%{http_code}" -x $bind_addr $refused_addr).unwrap(),
+        "502"
+    );
+    assert!(run_fun!(curl -s -x $bind_addr $refused_addr)
+        .unwrap()
+        .contains("connection refused"));
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_http_with_auth() {
     let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
@@ -60,8 +393,19 @@ async fn test_http_with_auth() {
     let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
     let bind_addr = bind_addr.to_string();
 
-    assert_eq!(run_fun!(curl -x $bind_addr $http_addr).unwrap(), "");
-    assert_eq!(run_fun!(curl -u $auth -x $bind_addr $http_addr).unwrap(), http_resp);
+    assert_eq!(
+        run_fun!(curl -s -o /dev/null -w "%{http_code}" -x $bind_addr $http_addr).unwrap(),
+        "407"
+    );
+    assert_eq!(
+        run_fun!(curl -s -o /dev/null -w "%{http_code}" -U "user:wrong" -x $bind_addr $http_addr).unwrap(),
+        "407"
+    );
+    assert_eq!(run_fun!(curl -U $auth -x $bind_addr $http_addr).unwrap(), http_resp);
+
+    // CONNECT tunnel
+    assert!(run_fun!(curl -p -x $bind_addr $http_addr).is_err());
+    assert_eq!(run_fun!(curl -p -U $auth -x $bind_addr $http_addr).unwrap(), http_resp);
 }
 
 #[tokio::test(flavor = "multi_thread")]
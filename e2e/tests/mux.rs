@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+
+use bp_core::{utils::tls, Address, ClientOptions, ServerOptions};
+use cmd_lib::run_fun;
+use e2e::runner::{run_all, TestResponse};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const HOSTNAME: &str = "localhost";
+const CERT_PATH: &str = "tests/tmp/mux_cert.der";
+const KEY_PATH: &str = "tests/tmp/mux_key.der";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mux() {
+    let TestResponse {
+        bind_addr,
+        http_addr,
+        http_resp,
+    } = run_all(
+        ClientOptions {
+            mux: true,
+            ..Default::default()
+        },
+        Default::default(),
+        None,
+    )
+    .await;
+
+    for _ in 0..3 {
+        assert_eq!(
+            run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+            http_resp
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mux_over_tls() {
+    tls::generate_cert_and_key(vec![HOSTNAME.to_string()], CERT_PATH, KEY_PATH).unwrap();
+
+    let TestResponse {
+        bind_addr,
+        http_addr,
+        http_resp,
+    } = run_all(
+        ClientOptions {
+            mux: true,
+            tls: true,
+            tls_cert: Some(CERT_PATH.to_string()),
+            ..Default::default()
+        },
+        ServerOptions {
+            tls: true,
+            tls_cert: Some(CERT_PATH.to_string()),
+            tls_key: Some(KEY_PATH.to_string()),
+            ..Default::default()
+        },
+        Some(HOSTNAME),
+    )
+    .await;
+
+    assert_eq!(
+        run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+        http_resp
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mux_concurrent_streams() {
+    let echo_addr = run_echo_server().await;
+
+    let TestResponse { bind_addr, .. } = run_all(
+        ClientOptions {
+            mux: true,
+            ..Default::default()
+        },
+        Default::default(),
+        None,
+    )
+    .await;
+
+    // each stream sends more data than the window, so that flow control takes effect
+    let tasks = (0..8u8).map(|i| {
+        tokio::spawn(async move {
+            let data = vec![i; 512 * 1024];
+            let mut socket = socks5_connect(bind_addr, echo_addr).await;
+
+            let (mut reader, mut writer) = socket.split();
+            let (_, echoed) = tokio::join!(writer.write_all(&data), async {
+                let mut buf = vec![0u8; data.len()];
+                reader.read_exact(&mut buf).await.unwrap();
+                buf
+            });
+
+            assert!(echoed == data);
+        })
+    });
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+async fn run_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    addr
+}
+
+async fn socks5_connect(bind_addr: SocketAddr, dest_addr: SocketAddr) -> TcpStream {
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    let mut reply = [0u8; 10];
+
+    socket.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    socket.read_exact(&mut reply[0..2]).await.unwrap();
+
+    let dest_addr: Address = dest_addr.into();
+    socket
+        .write_all(&[&[0x05, 0x01, 0x00][..], &dest_addr.as_bytes()[..]].concat())
+        .await
+        .unwrap();
+
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0..2], [0x05, 0x00]);
+
+    socket
+}
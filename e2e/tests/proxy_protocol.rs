@@ -0,0 +1,52 @@
+use bp_core::{
+    utils::proxy_protocol::{self, ProxyProtocolVersion},
+    ClientOptions, Options, ServiceInfo,
+};
+use e2e::runner::run_bp;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_proxy_protocol() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dest_addr = listener.local_addr().unwrap();
+
+    let opts = Options::Client(ClientOptions {
+        pin_dest_addr: Some(dest_addr.into()),
+        accept_proxy_protocol: true,
+        send_proxy_protocol: Some(ProxyProtocolVersion::V2),
+        ..Default::default()
+    });
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+
+    // the real client address is passed from load balancer to destination
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    socket
+        .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 56324 1080\r\nhello")
+        .await
+        .unwrap();
+
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let header = proxy_protocol::read_header(&mut stream).await.unwrap().unwrap();
+
+    assert_eq!(header.source, "203.0.113.7:56324".parse().unwrap());
+    assert_eq!(header.destination, dest_addr);
+
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+
+    assert_eq!(&buf, b"hello");
+
+    // connections without PROXY protocol header are dropped
+    let mut socket = TcpStream::connect(bind_addr).await.unwrap();
+    socket.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+
+    // closed with unread data may lead to a reset
+    let mut buf = [0u8; 1024];
+    let n = socket.read(&mut buf).await.unwrap_or(0);
+
+    assert_eq!(n, 0);
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use bp_core::{Address, ClientOptions, Options, ServerOptions, ServiceInfo};
+use e2e::runner::run_bp;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_erp_replay() {
+    let dest = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let fake_server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let server_opts = Options::Server(ServerOptions {
+        key: Some("key".to_string()),
+        ..Default::default()
+    });
+
+    let ServiceInfo {
+        bind_addr: server_addr, ..
+    } = run_bp(server_opts).await;
+
+    let client_opts = Options::Client(ClientOptions {
+        key: Some("key".to_string()),
+        server_bind: Some(fake_server.local_addr().unwrap().into()),
+        pin_dest_addr: Some(Address::from(dest.local_addr().unwrap())),
+        ..Default::default()
+    });
+
+    let ServiceInfo {
+        bind_addr: client_addr, ..
+    } = run_bp(client_opts).await;
+
+    // capture the first flight sent by bp client
+    let mut socket = TcpStream::connect(client_addr).await.unwrap();
+    socket.write_all(b"hello").await.unwrap();
+
+    let (mut stream, _) = fake_server.accept().await.unwrap();
+    let mut first_flight = vec![0u8; 4096];
+    let n = stream.read(&mut first_flight).await.unwrap();
+    first_flight.truncate(n);
+
+    // the first flight is accepted by bp server
+    let mut socket = TcpStream::connect(server_addr).await.unwrap();
+    socket.write_all(&first_flight).await.unwrap();
+
+    let (mut stream, _) = timeout(Duration::from_secs(5), dest.accept()).await.unwrap().unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+
+    assert_eq!(&buf, b"hello");
+
+    // the replayed one is rejected
+    let mut socket = TcpStream::connect(server_addr).await.unwrap();
+    socket.write_all(&first_flight).await.unwrap();
+
+    assert!(timeout(Duration::from_secs(2), dest.accept()).await.is_err());
+}
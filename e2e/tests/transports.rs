@@ -1,8 +1,17 @@
-use std::sync::Once;
+use std::{net::SocketAddr, sync::Once};
 
-use bp_core::{utils::tls, ClientOptions, ServerOptions};
+use bp_core::{utils::tls, ClientOptions, Options, ServerOptions, Transport};
 use cmd_lib::run_fun;
-use e2e::runner::{run_all, TestResponse};
+use e2e::{
+    http_server::{run_http_mock_server, HttpServerContext},
+    oneshot::udp_oneshot,
+    runner::{run_all, run_bp, TestResponse},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    time::{timeout, Duration},
+};
 
 static INIT: Once = Once::new();
 
@@ -38,12 +47,188 @@ async fn test_quic() {
         http_resp,
     } = run_test(false, true).await;
 
+    // streams of the same connection
+    for _ in 0..3 {
+        assert_eq!(
+            run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+            http_resp
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_websocket() {
+    let TestResponse {
+        bind_addr,
+        http_addr,
+        http_resp,
+    } = run_transport_test(Transport::WebSocket, false).await;
+
+    assert_eq!(
+        run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+        http_resp
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_secure_websocket() {
+    let TestResponse {
+        bind_addr,
+        http_addr,
+        http_resp,
+    } = run_transport_test(Transport::WebSocket, true).await;
+
     assert_eq!(
         run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
         http_resp
     );
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_h2() {
+    let TestResponse {
+        bind_addr,
+        http_addr,
+        http_resp,
+    } = run_transport_test(Transport::H2, true).await;
+
+    // streams of the same connection
+    for _ in 0..3 {
+        assert_eq!(
+            run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+            http_resp
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_quic_datagram() {
+    initialize();
+
+    // dns queries are relayed to --dns-server of bp server, which echoes them back
+    let echo_addr = run_udp_echo_server().await;
+
+    let TestResponse { bind_addr, .. } = run_all(
+        ClientOptions {
+            quic: true,
+            quic_datagram: true,
+            tls_cert: Some(CERT_PATH.to_string()),
+            ..Default::default()
+        },
+        ServerOptions {
+            quic: true,
+            tls_cert: Some(CERT_PATH.to_string()),
+            tls_key: Some(KEY_PATH.to_string()),
+            dns_server: echo_addr.into(),
+            ..Default::default()
+        },
+        Some(HOSTNAME),
+    )
+    .await;
+
+    // sessions of the same connection
+    for _ in 0..3 {
+        let query = include_bytes!("fixtures/normal_dns_query.bin");
+        assert_eq!(udp_oneshot(bind_addr, query).await, query);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_quic_datagram_large_packet() {
+    initialize();
+
+    let echo_addr = run_udp_echo_server().await;
+
+    let TestResponse { bind_addr, .. } = run_all(
+        ClientOptions {
+            quic: true,
+            quic_datagram: true,
+            tls_cert: Some(CERT_PATH.to_string()),
+            ..Default::default()
+        },
+        ServerOptions {
+            quic: true,
+            tls_cert: Some(CERT_PATH.to_string()),
+            tls_key: Some(KEY_PATH.to_string()),
+            ..Default::default()
+        },
+        Some(HOSTNAME),
+    )
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut control = TcpStream::connect(bind_addr).await.unwrap();
+    let mut buf = [0u8; 10];
+
+    control.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    control.read_exact(&mut buf[0..2]).await.unwrap();
+
+    let port = client.local_addr().unwrap().port().to_be_bytes();
+    control
+        .write_all(&[0x05, 0x03, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]])
+        .await
+        .unwrap();
+    control.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf[0..4], [0x05, 0x00, 0x00, 0x01]);
+
+    // the packet with encryption overhead exceeds the max datagram size, in both directions
+    let port = echo_addr.port().to_be_bytes();
+    let packet = [
+        &[0x00, 0x00, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]][..],
+        &[0xff; 1200],
+    ]
+    .concat();
+
+    client.send_to(&packet, bind_addr).await.unwrap();
+
+    let mut reply = [0u8; 1500];
+    let n = timeout(Duration::from_secs(5), client.recv(&mut reply))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply[..n], packet[..]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_https_proxy() {
+    initialize();
+
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+
+    // take a free port for --https-bind
+    let port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let auth = "user:pass";
+
+    run_bp(Options::Client(ClientOptions {
+        https_bind: Some(format!("127.0.0.1:{}", port).parse().unwrap()),
+        https_cert: Some(CERT_PATH.to_string()),
+        https_key: Some(KEY_PATH.to_string()),
+        with_basic_auth: Some(auth.parse().unwrap()),
+        ..Default::default()
+    }))
+    .await;
+
+    let proxy = format!("https://{}:{}", HOSTNAME, port);
+
+    assert_eq!(
+        run_fun!(curl -s -o /dev/null -w "%{http_code}" --proxy $proxy --proxy-insecure $http_addr).unwrap(),
+        "407"
+    );
+    assert_eq!(
+        run_fun!(curl --proxy $proxy --proxy-insecure -U $auth $http_addr).unwrap(),
+        http_resp
+    );
+    assert_eq!(
+        run_fun!(curl -p --proxy $proxy --proxy-insecure -U $auth $http_addr).unwrap(),
+        http_resp
+    );
+}
+
 async fn run_test(tls: bool, quic: bool) -> TestResponse {
     initialize();
 
@@ -65,3 +250,42 @@ async fn run_test(tls: bool, quic: bool) -> TestResponse {
     )
     .await
 }
+
+async fn run_transport_test(transport: Transport, tls: bool) -> TestResponse {
+    initialize();
+
+    run_all(
+        ClientOptions {
+            tls,
+            tls_cert: Some(CERT_PATH.to_string()),
+            transport: Some(transport),
+            transport_path: "/tunnel".to_string(),
+            transport_host: Some("cdn.example.com".to_string()),
+            ..Default::default()
+        },
+        ServerOptions {
+            tls,
+            tls_cert: Some(CERT_PATH.to_string()),
+            tls_key: Some(KEY_PATH.to_string()),
+            transport: Some(transport),
+            transport_path: "/tunnel".to_string(),
+            ..Default::default()
+        },
+        Some(HOSTNAME),
+    )
+    .await
+}
+
+async fn run_udp_echo_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 1500];
+        while let Ok((n, peer_addr)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..n], peer_addr).await;
+        }
+    });
+
+    addr
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use bp_core::{ClientOptions, Options, ServerOptions, ServiceInfo};
+use cmd_lib::run_fun;
+use e2e::{
+    http_server::{run_http_mock_server, HttpServerContext},
+    runner::{run_all, run_bp, TestResponse},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upstream_http_proxy() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+
+    // another bp client plays the upstream proxy
+    let upstream = run_bp(Options::Client(ClientOptions {
+        with_basic_auth: Some("user:pass".parse().unwrap()),
+        ..Default::default()
+    }))
+    .await;
+
+    let opts = Options::Client(ClientOptions {
+        upstream_proxy: Some(format!("http://user:pass@{}", upstream.bind_addr).parse().unwrap()),
+        ..Default::default()
+    });
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+    let bind_addr = bind_addr.to_string();
+
+    assert_eq!(run_fun!(curl -x $bind_addr $http_addr).unwrap(), http_resp);
+
+    // wrong credentials
+    let opts = Options::Client(ClientOptions {
+        upstream_proxy: Some(format!("http://user:wrong@{}", upstream.bind_addr).parse().unwrap()),
+        ..Default::default()
+    });
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+    let bind_addr = bind_addr.to_string();
+
+    assert_eq!(
+        run_fun!(curl -s -o /dev/null -w "%{http_code}" -x $bind_addr $http_addr).unwrap(),
+        "502"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upstream_socks5_proxy() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+
+    let upstream = run_bp(Options::Client(ClientOptions {
+        socks_auth: Some("user:pass".parse().unwrap()),
+        ..Default::default()
+    }))
+    .await;
+
+    let opts = Options::Client(ClientOptions {
+        upstream_proxy: Some(format!("socks5://user:pass@{}", upstream.bind_addr).parse().unwrap()),
+        ..Default::default()
+    });
+
+    let ServiceInfo { bind_addr, .. } = run_bp(opts).await;
+    let bind_addr = bind_addr.to_string();
+
+    assert_eq!(
+        run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+        http_resp
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upstream_proxy_to_server() {
+    let upstream = run_bp(Options::Client(ClientOptions::default())).await;
+
+    let client_opts = ClientOptions {
+        upstream_proxy: Some(format!("http://{}", upstream.bind_addr).parse().unwrap()),
+        ..Default::default()
+    };
+
+    let TestResponse {
+        bind_addr,
+        http_addr,
+        http_resp,
+    } = run_all(client_opts, ServerOptions::default(), None).await;
+    let bind_addr = bind_addr.to_string();
+
+    assert_eq!(run_fun!(curl -x $bind_addr $http_addr).unwrap(), http_resp);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upstream_proxy_refuses_udp() {
+    let upstream = run_bp(Options::Client(ClientOptions::default())).await;
+
+    let ServiceInfo { bind_addr, .. } = run_bp(Options::Client(ClientOptions {
+        upstream_proxy: Some(format!("http://{}", upstream.bind_addr).parse().unwrap()),
+        ..Default::default()
+    }))
+    .await;
+
+    // a local udp echo server as the destination
+    let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let echo_addr = echo.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        while let Ok((n, addr)) = echo.recv_from(&mut buf).await {
+            let _ = echo.send_to(&buf[..n], addr).await;
+        }
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let mut control = TcpStream::connect(bind_addr).await.unwrap();
+    let mut buf = [0u8; 10];
+
+    control.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    control.read_exact(&mut buf[0..2]).await.unwrap();
+
+    let port = client.local_addr().unwrap().port().to_be_bytes();
+    control
+        .write_all(&[0x05, 0x03, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]])
+        .await
+        .unwrap();
+    control.read_exact(&mut buf).await.unwrap();
+
+    let port = echo_addr.port().to_be_bytes();
+    let packet = [
+        &[0x00, 0x00, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x01, port[0], port[1]][..],
+        b"hello",
+    ]
+    .concat();
+
+    // datagrams must not go out directly bypassing the upstream proxy
+    client.send_to(&packet, bind_addr).await.unwrap();
+    let res = tokio::time::timeout(Duration::from_millis(500), client.recv(&mut [0u8; 1500])).await;
+    assert!(res.is_err());
+}
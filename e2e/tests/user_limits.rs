@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use bp_core::{get_user_limiter, Address, ClientOptions, Options, ServerOptions, ServiceInfo};
+use cmd_lib::run_fun;
+use e2e::{
+    http_server::{run_http_mock_server, HttpServerContext},
+    runner::run_bp,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+// limits of users are global, so they are tested in a single test
+#[tokio::test(flavor = "multi_thread")]
+async fn test_user_limits() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+    let dest = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let server_opts = Options::Server(ServerOptions {
+        users: vec!["alice:alice-key".parse().unwrap()],
+        user_quota: Some(2048),
+        user_max_connections: Some(1),
+        ..Default::default()
+    });
+
+    let ServiceInfo {
+        bind_addr: server_addr, ..
+    } = run_bp(server_opts).await;
+
+    let new_client = |pin_dest_addr: Option<Address>| {
+        run_bp(Options::Client(ClientOptions {
+            key: Some("alice-key".to_string()),
+            server_bind: Some(server_addr.into()),
+            pin_dest_addr,
+            ..Default::default()
+        }))
+    };
+
+    // 1. concurrent connections
+    let ServiceInfo { bind_addr, .. } = new_client(Some(dest.local_addr().unwrap().into())).await;
+
+    let mut first = TcpStream::connect(bind_addr).await.unwrap();
+    first.write_all(b"hello").await.unwrap();
+
+    let (mut stream, _) = timeout(Duration::from_secs(5), dest.accept()).await.unwrap().unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+
+    let mut second = TcpStream::connect(bind_addr).await.unwrap();
+    second.write_all(b"hello").await.unwrap();
+
+    assert!(timeout(Duration::from_secs(2), dest.accept()).await.is_err());
+
+    // the slot is released once the first connection is closed
+    drop(first);
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // 2. monthly quota
+    let ServiceInfo { bind_addr, .. } = new_client(None).await;
+
+    assert_eq!(
+        run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+        http_resp
+    );
+
+    let mut is_rejected = false;
+
+    for _ in 0..20 {
+        if run_fun!(curl -s --max-time 5 --socks5-hostname $bind_addr $http_addr).is_err() {
+            is_rejected = true;
+            break;
+        }
+    }
+
+    assert!(is_rejected);
+    assert!(get_user_limiter().usage("alice") >= 2048);
+}
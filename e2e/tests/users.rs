@@ -0,0 +1,48 @@
+use bp_core::{ClientOptions, Options, ServerOptions, ServiceInfo};
+use cmd_lib::run_fun;
+use e2e::{
+    http_server::{run_http_mock_server, HttpServerContext},
+    runner::run_bp,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multiple_users() {
+    let HttpServerContext { http_addr, http_resp } = run_http_mock_server();
+
+    let server_opts = Options::Server(ServerOptions {
+        key: Some("key".to_string()),
+        users: vec!["alice:alice-key".parse().unwrap(), "bob:bob-key".parse().unwrap()],
+        ..Default::default()
+    });
+
+    let ServiceInfo {
+        bind_addr: server_addr, ..
+    } = run_bp(server_opts).await;
+
+    // both the shared key and keys of users are accepted
+    for key in ["key", "alice-key", "bob-key"] {
+        let client_opts = Options::Client(ClientOptions {
+            key: Some(key.to_string()),
+            server_bind: Some(server_addr.into()),
+            ..Default::default()
+        });
+
+        let ServiceInfo { bind_addr, .. } = run_bp(client_opts).await;
+
+        assert_eq!(
+            run_fun!(curl --socks5-hostname $bind_addr $http_addr).unwrap(),
+            http_resp
+        );
+    }
+
+    // unknown key is rejected
+    let client_opts = Options::Client(ClientOptions {
+        key: Some("mallory-key".to_string()),
+        server_bind: Some(server_addr.into()),
+        ..Default::default()
+    });
+
+    let ServiceInfo { bind_addr, .. } = run_bp(client_opts).await;
+
+    assert!(run_fun!(curl -s --max-time 5 --socks5-hostname $bind_addr $http_addr).is_err());
+}